@@ -0,0 +1,281 @@
+//! Dominator and post-dominator trees, dominance frontiers and reverse
+//! postorder of the blocks of a function.
+//!
+//! Immediate dominators are computed with the iterative algorithm from Cooper,
+//! Harvey and Kennedy, "A Simple, Fast Dominance Algorithm".
+
+use crate::ir::{BlockId, Function, Terminator};
+
+/// The dominator tree of a function, or its post-dominator tree if built with
+/// `DominatorTree::new_post`.
+///
+/// The entry of the function is always `BlockId(0)`. Blocks that are not
+/// reachable from it (or, for post-dominators, that cannot reach a `ret`) are
+/// not part of the tree: they have no immediate dominator, dominate nothing and
+/// are missing from the reverse postorder.
+#[derive(Debug, Clone)]
+pub struct DominatorTree {
+    // indexed by block; for post-dominators an extra virtual exit node is
+    // appended that post-dominates every returning block
+    idom: Vec<Option<usize>>,
+    children: Vec<Vec<BlockId>>,
+    frontiers: Vec<Vec<BlockId>>,
+    rpo: Vec<BlockId>,
+    // preorder entry and exit numbers in the tree, for O(1) `dominates`
+    pre: Vec<usize>,
+    post: Vec<usize>,
+    roots: Vec<BlockId>,
+    block_count: usize,
+}
+
+impl DominatorTree {
+    /// Computes the dominator tree of `func`.
+    pub fn new(func: &Function) -> DominatorTree {
+        let n = func.blocks.len();
+        let mut succs = vec![Vec::new(); n];
+        let mut preds = vec![Vec::new(); n];
+        for (i, block) in func.blocks.iter().enumerate() {
+            for s in block.terminator.successors() {
+                if s.0 < n {
+                    succs[i].push(s.0);
+                    preds[s.0].push(i);
+                }
+            }
+        }
+        Self::compute(n, 0, &succs, &preds)
+    }
+
+    /// Computes the post-dominator tree of `func`. The roots of the tree are
+    /// the blocks ending in `ret`, see `DominatorTree::roots`.
+    pub fn new_post(func: &Function) -> DominatorTree {
+        let n = func.blocks.len();
+        let exit = n;
+        let mut succs = vec![Vec::new(); n + 1];
+        let mut preds = vec![Vec::new(); n + 1];
+        for (i, block) in func.blocks.iter().enumerate() {
+            for s in block.terminator.successors() {
+                if s.0 < n {
+                    succs[s.0].push(i);
+                    preds[i].push(s.0);
+                }
+            }
+            if let Terminator::Return(_) = block.terminator {
+                succs[exit].push(i);
+                preds[i].push(exit);
+            }
+        }
+        Self::compute(n, exit, &succs, &preds)
+    }
+
+    fn compute(
+        block_count: usize,
+        root: usize,
+        succs: &[Vec<usize>],
+        preds: &[Vec<usize>],
+    ) -> DominatorTree {
+        let n = succs.len();
+        let mut idom = vec![None; n];
+        if root >= n {
+            return DominatorTree {
+                idom,
+                children: vec![],
+                frontiers: vec![],
+                rpo: vec![],
+                pre: vec![],
+                post: vec![],
+                roots: vec![],
+                block_count,
+            };
+        }
+
+        // postorder via an explicit stack, so deep CFGs don't overflow
+        let mut po_num = vec![usize::MAX; n];
+        let mut postorder = Vec::with_capacity(n);
+        let mut visited = vec![false; n];
+        let mut stack = vec![(root, 0)];
+        visited[root] = true;
+        while let Some((node, next)) = stack.pop() {
+            if let Some(&s) = succs[node].get(next) {
+                stack.push((node, next + 1));
+                if !visited[s] {
+                    visited[s] = true;
+                    stack.push((s, 0));
+                }
+            } else {
+                po_num[node] = postorder.len();
+                postorder.push(node);
+            }
+        }
+        let rpo_nodes: Vec<usize> = postorder.iter().rev().copied().collect();
+
+        idom[root] = Some(root);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in rpo_nodes.iter().skip(1) {
+                let mut new_idom = None;
+                for &p in preds[b].iter() {
+                    if idom[p].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => p,
+                        Some(cur) => intersect(&idom, &po_num, p, cur),
+                    });
+                }
+                if new_idom.is_some() && idom[b] != new_idom {
+                    idom[b] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children = vec![Vec::new(); n];
+        for &b in rpo_nodes.iter().skip(1) {
+            children[idom[b].unwrap()].push(b);
+        }
+
+        let mut frontiers: Vec<Vec<usize>> = vec![Vec::new(); n];
+        for &b in rpo_nodes.iter() {
+            let reachable_preds: Vec<usize> = preds[b]
+                .iter()
+                .copied()
+                .filter(|p| idom[*p].is_some())
+                .collect();
+            // the root has an implicit edge from outside the function, so it
+            // is a join point even with a single predecessor
+            if reachable_preds.len() < 2 && b != root {
+                continue;
+            }
+            // the root has no real idom, so walk all the way up for it
+            let stop = if b == root { None } else { idom[b] };
+            for p in reachable_preds {
+                let mut runner = p;
+                while Some(runner) != stop {
+                    if !frontiers[runner].contains(&b) {
+                        frontiers[runner].push(b);
+                    }
+                    if runner == root {
+                        break;
+                    }
+                    runner = idom[runner].unwrap();
+                }
+            }
+        }
+
+        let mut pre = vec![usize::MAX; n];
+        let mut post = vec![usize::MAX; n];
+        let mut counter = 0;
+        let mut stack = vec![(root, 0)];
+        pre[root] = counter;
+        while let Some((node, next)) = stack.pop() {
+            if let Some(&c) = children[node].get(next) {
+                stack.push((node, next + 1));
+                counter += 1;
+                pre[c] = counter;
+                stack.push((c, 0));
+            } else {
+                post[node] = counter;
+            }
+        }
+
+        // the root is its own idom only internally
+        idom[root] = None;
+        let real = |nodes: &[usize]| -> Vec<BlockId> {
+            nodes
+                .iter()
+                .filter(|b| **b < block_count)
+                .map(|b| BlockId(*b))
+                .collect()
+        };
+        let roots = if root < block_count {
+            vec![BlockId(root)]
+        } else {
+            real(&children[root])
+        };
+        if root >= block_count {
+            for d in idom.iter_mut().filter(|d| **d == Some(root)) {
+                *d = None;
+            }
+        }
+
+        DominatorTree {
+            idom,
+            children: children.iter().map(|c| real(c)).collect(),
+            frontiers: frontiers.iter().map(|f| real(f)).collect(),
+            rpo: real(&rpo_nodes),
+            pre,
+            post,
+            roots,
+            block_count,
+        }
+    }
+
+    /// Returns the immediate dominator of `block`, or `None` for a root or an
+    /// unreachable block.
+    pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+        self.idom.get(block.0).copied().flatten().map(BlockId)
+    }
+
+    /// Returns the blocks immediately dominated by `block`.
+    pub fn children(&self, block: BlockId) -> &[BlockId] {
+        self.children.get(block.0).map_or(&[], |c| c.as_slice())
+    }
+
+    /// Returns the dominance frontier of `block`.
+    pub fn frontier(&self, block: BlockId) -> &[BlockId] {
+        self.frontiers.get(block.0).map_or(&[], |f| f.as_slice())
+    }
+
+    /// Returns the roots of the tree: the entry block for dominators, and the
+    /// returning blocks for post-dominators.
+    pub fn roots(&self) -> &[BlockId] {
+        &self.roots
+    }
+
+    /// Returns the reachable blocks in reverse postorder of the CFG (or of the
+    /// reversed CFG for post-dominators).
+    pub fn rpo(&self) -> &[BlockId] {
+        &self.rpo
+    }
+
+    /// Returns the reachable blocks in preorder of the tree, so every block
+    /// comes after its dominator.
+    pub fn preorder(&self) -> Vec<BlockId> {
+        let mut order = Vec::with_capacity(self.rpo.len());
+        let mut stack: Vec<BlockId> = self.roots.iter().rev().copied().collect();
+        while let Some(b) = stack.pop() {
+            order.push(b);
+            stack.extend(self.children(b).iter().rev());
+        }
+        order
+    }
+
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        block.0 < self.block_count && self.pre.get(block.0).is_some_and(|p| *p != usize::MAX)
+    }
+
+    /// Returns whether `a` dominates `b`. Every block dominates itself.
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        if !self.is_reachable(a) || !self.is_reachable(b) {
+            return false;
+        }
+        self.pre[a.0] <= self.pre[b.0] && self.post[b.0] <= self.post[a.0]
+    }
+
+    pub fn strictly_dominates(&self, a: BlockId, b: BlockId) -> bool {
+        a != b && self.dominates(a, b)
+    }
+}
+
+fn intersect(idom: &[Option<usize>], po_num: &[usize], mut a: usize, mut b: usize) -> usize {
+    while a != b {
+        while po_num[a] < po_num[b] {
+            a = idom[a].unwrap();
+        }
+        while po_num[b] < po_num[a] {
+            b = idom[b].unwrap();
+        }
+    }
+    a
+}
@@ -0,0 +1,317 @@
+//! Natural loops and the loop nest of a function.
+//!
+//! A back edge is an edge `latch -> header` where the header dominates the
+//! latch. The natural loop of a header is the header plus every block that can
+//! reach one of its latches without going through the header; all back edges
+//! to the same header make up a single loop. Irreducible cycles have no header
+//! dominating them and are not considered loops.
+
+use crate::ir::{BasicBlock, BlockId, Function, Instruction, Operation, Terminator, ValueId};
+
+use super::dominators::DominatorTree;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LoopId(pub(crate) usize);
+
+#[derive(Debug, Clone)]
+pub struct Loop {
+    header: BlockId,
+    latches: Vec<BlockId>,
+    // sorted by id
+    blocks: Vec<BlockId>,
+    exits: Vec<BlockId>,
+    parent: Option<LoopId>,
+    children: Vec<LoopId>,
+    depth: usize,
+}
+
+impl Loop {
+    pub fn header(&self) -> BlockId {
+        self.header
+    }
+
+    /// Returns the blocks with a back edge to the header.
+    pub fn latches(&self) -> &[BlockId] {
+        &self.latches
+    }
+
+    /// Returns the blocks of the loop, including those of nested loops.
+    pub fn blocks(&self) -> &[BlockId] {
+        &self.blocks
+    }
+
+    pub fn contains(&self, block: BlockId) -> bool {
+        self.blocks.binary_search_by_key(&block.0, |b| b.0).is_ok()
+    }
+
+    /// Returns the blocks outside the loop that are jumped to from inside it.
+    pub fn exits(&self) -> &[BlockId] {
+        &self.exits
+    }
+
+    pub fn parent(&self) -> Option<LoopId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[LoopId] {
+        &self.children
+    }
+
+    /// Returns the nesting depth, which is 1 for outermost loops.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the single predecessor of the header from outside the loop,
+    /// if it jumps only to the header.
+    pub fn preheader(&self, func: &Function) -> Option<BlockId> {
+        let mut outside = func.blocks[self.header.0]
+            .preds
+            .iter()
+            .filter(|p| !self.contains(**p));
+        let pred = *outside.next()?;
+        if outside.any(|p| *p != pred) {
+            return None;
+        }
+        match func.blocks[pred.0].terminator {
+            Terminator::Jump(_) => Some(pred),
+            _ => None,
+        }
+    }
+}
+
+/// The loops of a function. Ids are assigned so that a loop always comes
+/// before the loops nested in it.
+#[derive(Debug, Clone)]
+pub struct LoopInfo {
+    loops: Vec<Loop>,
+    // block -> innermost loop containing it
+    innermost: Vec<Option<LoopId>>,
+}
+
+impl LoopInfo {
+    pub fn new(func: &Function, dom: &DominatorTree) -> LoopInfo {
+        let block_count = func.blocks.len();
+
+        // headers in reverse postorder, so outer loops come first
+        let mut loops: Vec<Loop> = Vec::new();
+        for &header in dom.rpo() {
+            let latches: Vec<BlockId> = func.blocks[header.0]
+                .preds
+                .iter()
+                .copied()
+                .filter(|p| dom.dominates(header, *p))
+                .fold(Vec::new(), |mut acc, p| {
+                    if !acc.contains(&p) {
+                        acc.push(p);
+                    }
+                    acc
+                });
+            if latches.is_empty() {
+                continue;
+            }
+
+            let mut in_loop = vec![false; block_count];
+            in_loop[header.0] = true;
+            let mut worklist = latches.clone();
+            while let Some(b) = worklist.pop() {
+                if in_loop[b.0] {
+                    continue;
+                }
+                in_loop[b.0] = true;
+                worklist.extend(
+                    func.blocks[b.0]
+                        .preds
+                        .iter()
+                        .filter(|p| dom.is_reachable(**p)),
+                );
+            }
+            let blocks: Vec<BlockId> = (0..block_count)
+                .filter(|b| in_loop[*b])
+                .map(BlockId)
+                .collect();
+            let mut exits = Vec::new();
+            for b in blocks.iter() {
+                for s in func.blocks[b.0].terminator.successors() {
+                    if !in_loop[s.0] && !exits.contains(&s) {
+                        exits.push(s);
+                    }
+                }
+            }
+
+            loops.push(Loop {
+                header,
+                latches,
+                blocks,
+                exits,
+                parent: None,
+                children: vec![],
+                depth: 1,
+            });
+        }
+
+        // an outer loop's header comes before the headers nested in it in
+        // reverse postorder, so the parent is the last earlier loop
+        // containing the header
+        let mut innermost = vec![None; block_count];
+        for l in 0..loops.len() {
+            let parent = (0..l).rev().find(|p| loops[*p].contains(loops[l].header));
+            if let Some(p) = parent {
+                loops[l].parent = Some(LoopId(p));
+                loops[l].depth = loops[p].depth + 1;
+                loops[p].children.push(LoopId(l));
+            }
+            for b in loops[l].blocks.clone() {
+                innermost[b.0] = Some(LoopId(l));
+            }
+        }
+
+        LoopInfo { loops, innermost }
+    }
+
+    pub fn loops(&self) -> &[Loop] {
+        &self.loops
+    }
+
+    pub fn get(&self, id: LoopId) -> &Loop {
+        &self.loops[id.0]
+    }
+
+    /// Returns the ids of all loops, inner loops before the loops containing
+    /// them.
+    pub fn innermost_first(&self) -> impl Iterator<Item = LoopId> {
+        (0..self.loops.len()).rev().map(LoopId)
+    }
+
+    /// Returns the innermost loop containing `block`.
+    pub fn loop_of(&self, block: BlockId) -> Option<LoopId> {
+        self.innermost.get(block.0).copied().flatten()
+    }
+
+    /// Returns the number of loops containing `block`.
+    pub fn depth(&self, block: BlockId) -> usize {
+        self.loop_of(block).map_or(0, |l| self.loops[l.0].depth)
+    }
+}
+
+/// Gives every loop of `func` a preheader and dedicated exits, that is exit
+/// blocks only reached from inside the loop, and returns the updated loop
+/// info.
+///
+/// Preheaders are where loop invariant code can be hoisted to, and dedicated
+/// exits where values computed in the loop can be sunk to.
+pub fn simplify_loops(func: &mut Function) -> LoopInfo {
+    // a split can change the loops around the one it is done for, so start
+    // over after every change
+    loop {
+        let info = LoopInfo::new(func, &DominatorTree::new(func));
+        if !info.loops.iter().any(|lp| simplify_loop(func, lp)) {
+            return info;
+        }
+    }
+}
+
+/// Adds a preheader or a dedicated exit to `lp` if it is missing one, and
+/// returns whether it did.
+fn simplify_loop(func: &mut Function, lp: &Loop) -> bool {
+    let mut outside: Vec<BlockId> = Vec::new();
+    for p in func.blocks[lp.header.0].preds.iter() {
+        if !lp.contains(*p) && !outside.contains(p) {
+            outside.push(*p);
+        }
+    }
+    // a loop around the entry block can't have a preheader
+    if lp.preheader(func).is_none() && !outside.is_empty() {
+        split_preds(func, lp.header, &outside);
+        return true;
+    }
+    for &exit in lp.exits.iter() {
+        let preds = &func.blocks[exit.0].preds;
+        let mut inside: Vec<BlockId> = Vec::new();
+        for p in preds.iter() {
+            if lp.contains(*p) && !inside.contains(p) {
+                inside.push(*p);
+            }
+        }
+        if preds.iter().any(|p| !lp.contains(*p)) {
+            split_preds(func, exit, &inside);
+            return true;
+        }
+    }
+    false
+}
+
+/// Moves the edges from `preds` to `block` to a new block jumping to `block`,
+/// merging the Φ operands coming from them in the new block, and returns it.
+/// `preds` can't contain duplicates.
+fn split_preds(func: &mut Function, block: BlockId, preds: &[BlockId]) -> BlockId {
+    let new = BlockId(func.blocks.len());
+    let edges: Vec<BlockId> = func.blocks[block.0]
+        .preds
+        .iter()
+        .copied()
+        .filter(|p| preds.contains(p))
+        .collect();
+
+    let mut new_instrs = Vec::new();
+    for ii in 0..func.blocks[block.0].instructions.len() {
+        let Instruction {
+            yielded,
+            operation: Operation::Phi(ref vals),
+        } = func.blocks[block.0].instructions[ii]
+        else {
+            continue;
+        };
+        let moved: Vec<(BlockId, ValueId)> = vals
+            .iter()
+            .copied()
+            .filter(|(p, _)| preds.contains(p))
+            .collect();
+        let mut kept: Vec<(BlockId, ValueId)> = vals
+            .iter()
+            .copied()
+            .filter(|(p, _)| !preds.contains(p))
+            .collect();
+        let merged = if moved.iter().all(|(_, v)| *v == moved[0].1) {
+            moved[0].1
+        } else {
+            let ty = func.values[yielded.unwrap().0].ty.clone();
+            let val = func.push_value(ty);
+            func.values[val.0].owner = new;
+            new_instrs.push(Instruction {
+                yielded: Some(val),
+                operation: Operation::Phi(moved),
+            });
+            val
+        };
+        kept.push((new, merged));
+        func.blocks[block.0].instructions[ii].operation = Operation::Phi(kept);
+    }
+
+    let target = &mut func.blocks[block.0];
+    target.preds.retain(|p| !preds.contains(p));
+    target.preds.push(new);
+    for p in preds {
+        match func.blocks[p.0].terminator {
+            Terminator::Jump(ref mut b) => *b = new,
+            Terminator::Branch(_, ref mut t, ref mut f) => {
+                if *t == block {
+                    *t = new;
+                }
+                if *f == block {
+                    *f = new;
+                }
+            }
+            Terminator::Return(_) | Terminator::NoTerm => {}
+        }
+    }
+    func.push_block(BasicBlock {
+        instructions: new_instrs,
+        terminator: Terminator::Jump(block),
+        preds: edges,
+        id: new.0,
+        par_moves: vec![],
+    });
+    func.rebuild_children();
+    new
+}
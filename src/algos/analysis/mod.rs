@@ -0,0 +1,4 @@
+//! Analyses that compute info about the IR without changing it.
+
+pub mod dominators;
+pub mod loops;
@@ -0,0 +1,307 @@
+//! Moves aggregate values out of registers, which the backends can't hold
+//! them in, and into memory.
+//!
+//! Every value of an array or struct type becomes a pointer to memory holding
+//! it, which isn't written to while the value is live:
+//!  - `load`, `insertvalue`, `undef` and Φs of aggregates get memory of their
+//!    own, reserved by an `alloca` in the entry block, which `load` and
+//!    `insertvalue` fill by copying member by member
+//!  - a Φ is filled on each incoming edge instead, at the end of the
+//!    predecessor or, if it branches elsewhere too, at the start of the block
+//!  - `extractvalue` becomes a `fieldptr`, followed by a `load` for a scalar
+//!  - a `store` of an aggregate becomes a copy
+//!  - arguments are passed as pointers to the memory of the caller, and
+//!    aggregates are returned by copying them to memory of the caller, which
+//!    is passed as an extra last argument and returned
+//!
+//! A value still live when the instruction defining it runs again can only be
+//! read through a Φ, which holds a copy, so the memory of a value is only
+//! overwritten once it is dead.
+
+use crate::ir::{
+    Algo, BlockId, Function, Instruction, Module, Operation, Terminator, Type, Value, ValueId,
+};
+
+use super::opt::OptPass;
+
+/// `lower_aggregates` as a pass, establishing `Algo::AggregateLowering`.
+pub struct LowerAggregates;
+
+impl OptPass for LowerAggregates {
+    fn run(&mut self, module: &mut Module) {
+        lower_aggregates(module);
+    }
+
+    fn requires(&self) -> Vec<Algo> {
+        vec![Algo::CriticalEdgeSplitting, Algo::PhiLowering]
+    }
+
+    fn conflicts(&self) -> Vec<Algo> {
+        vec![Algo::PhiRemoval]
+    }
+}
+
+pub fn lower_aggregates(module: &mut Module) {
+    // copies into the Φs of a block are put at its start when a predecessor
+    // has other successors, which is only right if that block has no other
+    // predecessors
+    assert!(module.algos_run.contains(&Algo::CriticalEdgeSplitting));
+    module.algos_run.push(Algo::AggregateLowering);
+    let ret_types: Vec<Option<Type>> = module
+        .functions
+        .iter()
+        .map(|f| f.ret_type.is_aggregate().then(|| f.ret_type.clone()))
+        .collect();
+    for (fi, func) in module.functions.iter_mut().enumerate() {
+        lower_function(func, fi, &ret_types);
+    }
+}
+
+fn lower_function(func: &mut Function, fi: usize, ret_types: &[Option<Type>]) {
+    let ret = ret_types[fi].as_ref().map(|ty| {
+        func.ret_type = pointer(ty);
+        push_arg(func, "ret", pointer(ty))
+    });
+    // the types before lowering, from which those of the pointers replacing
+    // aggregates are made
+    let tys: Vec<Type> = func.values.iter().map(|v| v.ty.clone()).collect();
+    for (val, ty) in func.values.iter_mut().zip(tys.iter()) {
+        if ty.is_aggregate() {
+            val.ty = pointer(ty);
+        }
+    }
+    for (i, arg) in func.args.iter_mut().enumerate() {
+        arg.1 = func.values[i].ty.clone();
+    }
+    if func.blocks.is_empty() {
+        return;
+    }
+
+    // the allocas of the entry block
+    let mut slots = Vec::new();
+    // the Φs of aggregates of each block, with their operands
+    let mut phis = vec![Vec::new(); func.blocks.len()];
+    for (bi, block_phis) in phis.iter_mut().enumerate() {
+        let instrs = std::mem::take(&mut func.blocks[bi].instructions);
+        let mut e = Emitter {
+            func,
+            tys: &tys,
+            instrs: Vec::with_capacity(instrs.len()),
+        };
+        for instr in instrs {
+            let aggregate = instr.yielded.filter(|v| tys[v.0].is_aggregate());
+            match (aggregate, instr.operation) {
+                (Some(val), Operation::Phi(vals)) => {
+                    slots.push(alloca(val, &tys[val.0]));
+                    block_phis.push((val, vals));
+                }
+                (Some(val), Operation::Undef) => slots.push(alloca(val, &tys[val.0])),
+                (Some(val), Operation::Load(ptr)) => {
+                    slots.push(alloca(val, &tys[val.0]));
+                    e.copy(val, ptr, &tys[val.0]);
+                }
+                (Some(val), Operation::InsertValue(agg, member, index)) => {
+                    slots.push(alloca(val, &tys[val.0]));
+                    e.copy(val, agg, &tys[val.0]);
+                    let field = e.field(val, &tys[val.0], index);
+                    e.store(field, member);
+                }
+                (Some(val), Operation::ExtractValue(agg, index)) => {
+                    e.push(Some(val), Operation::FieldPtr(agg, index));
+                }
+                (None, Operation::ExtractValue(agg, index)) => {
+                    if let Some(val) = instr.yielded {
+                        let field = e.field(agg, &tys[agg.0], index);
+                        e.push(Some(val), Operation::Load(field));
+                    }
+                }
+                (None, Operation::InsertValue(..)) => {}
+                (None, Operation::Store(ptr, val)) => e.store(ptr, val),
+                (_, Operation::Call(callee, mut args)) => {
+                    if let Some(ty) = &ret_types[callee.0] {
+                        let mem = e.func.push_value(pointer(ty));
+                        slots.push(alloca(mem, ty));
+                        args.push(mem);
+                    }
+                    e.push(instr.yielded, Operation::Call(callee, args));
+                }
+                (_, operation) => e.push(instr.yielded, operation),
+            }
+        }
+        if let (Terminator::Return(val), Some(ret)) = (e.func.blocks[bi].terminator, ret) {
+            e.copy(ret, val, &tys[val.0]);
+            e.func.blocks[bi].terminator = Terminator::Return(ret);
+        }
+        func.blocks[bi].instructions = e.instrs;
+    }
+
+    for (bi, phis) in phis.iter().enumerate().filter(|(_, phis)| !phis.is_empty()) {
+        for pred in func.blocks[bi].preds.clone() {
+            copy_on_edge(func, &tys, &mut slots, pred, BlockId(bi), phis);
+        }
+    }
+
+    let at = phi_count(&func.blocks[0].instructions);
+    func.blocks[0].instructions.splice(at..at, slots);
+    for (bi, block) in func.blocks.iter().enumerate() {
+        for val in block.instructions.iter().filter_map(|i| i.yielded) {
+            func.values[val.0].owner = BlockId(bi);
+        }
+    }
+    func.rebuild_children();
+}
+
+/// Fills the Φs of `block` with their operands coming from `pred`.
+fn copy_on_edge(
+    func: &mut Function,
+    tys: &[Type],
+    slots: &mut Vec<Instruction>,
+    pred: BlockId,
+    block: BlockId,
+    phis: &[(ValueId, Vec<(BlockId, ValueId)>)],
+) {
+    let mut e = Emitter {
+        func,
+        tys,
+        instrs: Vec::new(),
+    };
+    // the copies are made one after the other, so an operand that is a Φ of
+    // the block, which may be overwritten by an earlier copy, is saved first
+    let mut srcs = Vec::new();
+    for (val, vals) in phis.iter() {
+        let (_, src) = *vals.iter().find(|(b, _)| *b == pred).unwrap();
+        let ty = &tys[val.0];
+        if phis.iter().any(|(phi, _)| *phi == src) {
+            let saved = e.func.push_value(pointer(ty));
+            slots.push(alloca(saved, ty));
+            e.copy(saved, src, ty);
+            srcs.push(saved);
+        } else {
+            srcs.push(src);
+        }
+    }
+    for ((val, _), src) in phis.iter().zip(srcs) {
+        e.copy(*val, src, &tys[val.0]);
+    }
+
+    let instrs = e.instrs;
+    if func.blocks[pred.0].terminator.successors().len() == 1 {
+        func.blocks[pred.0].instructions.extend(instrs);
+    } else {
+        let at = phi_count(&func.blocks[block.0].instructions);
+        func.blocks[block.0].instructions.splice(at..at, instrs);
+    }
+}
+
+/// Adds an argument to `func`, renumbering the other values after the
+/// arguments.
+fn push_arg(func: &mut Function, name: &str, ty: Type) -> ValueId {
+    let arg = ValueId(func.args.len());
+    let renumber = |val: &mut ValueId| {
+        if val.0 >= arg.0 {
+            val.0 += 1;
+        }
+    };
+    for block in func.blocks.iter_mut() {
+        for instr in block.instructions.iter_mut() {
+            if let Some(val) = &mut instr.yielded {
+                renumber(val);
+            }
+            for val in instr.operation.operands_mut() {
+                renumber(val);
+            }
+        }
+        for (dst, src) in block.par_moves.iter_mut() {
+            renumber(dst);
+            renumber(src);
+        }
+        if let Terminator::Return(val) | Terminator::Branch(val, ..) = &mut block.terminator {
+            renumber(val);
+        }
+    }
+    func.values.insert(
+        arg.0,
+        Value {
+            ty: ty.clone(),
+            children: vec![],
+            owner: BlockId(0),
+        },
+    );
+    func.args.push((name.to_string(), ty));
+    arg
+}
+
+fn pointer(ty: &Type) -> Type {
+    Type::Pointer(Box::new(ty.clone()))
+}
+
+fn alloca(val: ValueId, ty: &Type) -> Instruction {
+    Instruction {
+        yielded: Some(val),
+        operation: Operation::Alloca(ty.clone()),
+    }
+}
+
+fn phi_count(instrs: &[Instruction]) -> usize {
+    instrs
+        .iter()
+        .take_while(|i| matches!(i.operation, Operation::Phi(_)))
+        .count()
+}
+
+/// Collects the instructions replacing those of a block.
+struct Emitter<'a> {
+    func: &'a mut Function,
+    // the types of the values before lowering
+    tys: &'a [Type],
+    instrs: Vec<Instruction>,
+}
+
+impl Emitter<'_> {
+    fn push(&mut self, yielded: Option<ValueId>, operation: Operation) {
+        self.instrs.push(Instruction { yielded, operation });
+    }
+
+    fn emit(&mut self, operation: Operation, ty: Type) -> ValueId {
+        let val = self.func.push_value(ty);
+        self.push(Some(val), operation);
+        val
+    }
+
+    /// Returns a pointer to member `index` of the aggregate of type `ty` at
+    /// `ptr`.
+    fn field(&mut self, ptr: ValueId, ty: &Type, index: usize) -> ValueId {
+        let member = ty.member(index).unwrap();
+        self.emit(Operation::FieldPtr(ptr, index), pointer(member))
+    }
+
+    /// Stores `val`, which is a pointer to memory holding it if it is an
+    /// aggregate, at `ptr`.
+    fn store(&mut self, ptr: ValueId, val: ValueId) {
+        let ty = &self.tys[val.0];
+        if ty.is_aggregate() {
+            self.copy(ptr, val, &ty.clone());
+        } else {
+            self.push(None, Operation::Store(ptr, val));
+        }
+    }
+
+    /// Copies the value of type `ty` at `src` to `dst`, one scalar at a time.
+    fn copy(&mut self, dst: ValueId, src: ValueId, ty: &Type) {
+        let count = match ty {
+            Type::Array(_, len) => *len,
+            Type::Struct(fields) => fields.len(),
+            _ => {
+                let val = self.emit(Operation::Load(src), ty.clone());
+                self.push(None, Operation::Store(dst, val));
+                return;
+            }
+        };
+        for index in 0..count {
+            let member = ty.member(index).unwrap();
+            let dst = self.field(dst, ty, index);
+            let src = self.field(src, ty, index);
+            self.copy(dst, src, member);
+        }
+    }
+}
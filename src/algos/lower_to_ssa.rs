@@ -1,65 +1,264 @@
 use std::collections::HashMap;
 
-use crate::ir::{
-    Algo, BasicBlock, BlockId, Function, Instruction, Module, Operation, ValueId, VariableId,
+use crate::{
+    algos::analysis::dominators::DominatorTree,
+    ir::{Algo, BlockId, Function, Instruction, Module, Operation, ValueId, VariableId},
 };
 
-use super::delete_instructions::delete;
+use super::{delete_instructions::delete, opt::OptPass};
 
 /// Gets rid of all `load` and `store` instructions and replaces them with values and Φ functions.
 ///
 /// notes on impl:
-/// if no def in the current blk:
-///  - recurse into preds and find a def for each one
-///  - all bbs have a last definition
+/// this is the construction of Cytron et al.:
+///  - Φs for a variable are placed on the iterated dominance frontier of the
+///    blocks storing to it, but only where the variable is live (pruned SSA)
+///  - loads and stores are then renamed by walking the dominator tree, and
+///    every Φ gets exactly one operand per predecessor
+///  - reads of a variable that has not been stored to on some path get an
+///    `undef` value
 pub fn lower(module: &mut Module) {
     module.algos_run.push(Algo::PhiLowering);
-    // Function: V: BB: I -> should delete instruction
-    let mut func_dels: Vec<Vec<Vec<bool>>> = Vec::new();
-    for (func_id, func) in (module.functions).iter_mut().enumerate() {
-        func_dels.push(Vec::new());
-        for (block_id, block) in func.blocks.clone().iter().enumerate() {
-            func_dels[func_id].push(Vec::new());
-            let mut last_var_defs = HashMap::new();
-            for (instr_id, instr) in block.instructions.iter().enumerate() {
-                func_dels[func_id][block_id].push(false);
-                match instr.operation {
-                    Operation::LoadVar(var) => {
-                        if let Some(val) = last_var_defs.get(&var) {
-                            // mark for deletion
-                            func_dels[func_id][block_id][instr_id] = true;
-                            // replace uses of the load with the val
-                            func.replace_children_with(instr.yielded.unwrap(), *val);
-                        } else {
-                            // backtrack through preds for a def
-                            let defs = find_defs_in_preds(
-                                func,
-                                BlockId(block_id),
-                                var,
-                                BlockId(block_id),
-                                instr.yielded.unwrap(),
-                            );
-                            func.replace_instruction(
-                                BlockId(block_id),
-                                instr_id,
-                                Instruction {
-                                    yielded: instr.yielded,
-                                    operation: Operation::Phi(defs),
-                                },
-                            )
-                        }
-                    }
-                    Operation::StoreVar(to, val) => {
-                        last_var_defs.insert(to, val);
-                        func_dels[func_id][block_id][instr_id] = true;
+    for func in module.functions.iter_mut() {
+        construct_ssa(func);
+    }
+}
+
+/// `lower` as a pass, establishing `Algo::PhiLowering`. Once phis have been
+/// removed, values are defined more than once and SSA form can't be rebuilt.
+pub struct LowerToSsa;
+
+impl OptPass for LowerToSsa {
+    fn run(&mut self, module: &mut Module) {
+        lower(module);
+    }
+
+    fn conflicts(&self) -> Vec<Algo> {
+        vec![Algo::PhiRemoval]
+    }
+}
+
+/// Lowers all loads and stores of variables in `func` to SSA form.
+pub(crate) fn construct_ssa(func: &mut Function) {
+    let var_count = func.variables.len();
+    if var_count == 0 || func.blocks.is_empty() {
+        return;
+    }
+    let block_count = func.blocks.len();
+    let dom = DominatorTree::new(func);
+
+    // upward exposed uses and definitions of every variable per block
+    let mut uses = vec![vec![false; var_count]; block_count];
+    let mut defs = vec![vec![false; var_count]; block_count];
+    for (bi, block) in func.blocks.iter().enumerate() {
+        for instr in block.instructions.iter() {
+            match instr.operation {
+                Operation::LoadVar(var) if !defs[bi][var.0] => uses[bi][var.0] = true,
+                Operation::StoreVar(var, _) => defs[bi][var.0] = true,
+                _ => {}
+            }
+        }
+    }
+
+    let succs: Vec<Vec<BlockId>> = func
+        .blocks
+        .iter()
+        .map(|b| b.terminator.successors())
+        .collect();
+    let mut live_in = uses.clone();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &b in dom.rpo().iter().rev() {
+            for var in 0..var_count {
+                if live_in[b.0][var] || defs[b.0][var] {
+                    continue;
+                }
+                if succs[b.0].iter().any(|s| live_in[s.0][var]) {
+                    live_in[b.0][var] = true;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    // place the Φs, remembering which variable each one is for
+    let mut phis: Vec<Vec<(VariableId, ValueId)>> = vec![Vec::new(); block_count];
+    for var in 0..var_count {
+        let mut has_phi = vec![false; block_count];
+        let mut worklist: Vec<BlockId> = (0..block_count)
+            .filter(|b| defs[*b][var])
+            .map(BlockId)
+            .collect();
+        let mut queued: Vec<bool> = (0..block_count).map(|b| defs[b][var]).collect();
+        while let Some(b) = worklist.pop() {
+            for &df in dom.frontier(b) {
+                if has_phi[df.0] || !live_in[df.0][var] {
+                    continue;
+                }
+                has_phi[df.0] = true;
+                let ty = func.variables[var].ty.clone();
+                let val = func.push_value(ty);
+                func.values[val.0].owner = df;
+                phis[df.0].push((VariableId(var), val));
+                if !queued[df.0] {
+                    queued[df.0] = true;
+                    worklist.push(df);
+                }
+            }
+        }
+    }
+
+    let mut renamer = Renamer {
+        replacements: HashMap::new(),
+        stacks: vec![Vec::new(); var_count],
+        undefs: vec![None; var_count],
+        phi_operands: phis
+            .iter()
+            .enumerate()
+            .map(|(b, p)| {
+                let incoming: Vec<_> = func.blocks[b].preds.iter().map(|p| (*p, None)).collect();
+                vec![incoming; p.len()]
+            })
+            .collect(),
+        dels: vec![Vec::new(); block_count],
+    };
+
+    // walk the dominator tree, popping the definitions of a block once all
+    // blocks it dominates are done
+    let mut stack = vec![(BlockId(0), false)];
+    while let Some((block, done)) = stack.pop() {
+        if done {
+            for var in renamer.pop_block(func, &phis[block.0], block) {
+                renamer.stacks[var.0].pop();
+            }
+            continue;
+        }
+        renamer.rename_block(func, &phis, &succs, block);
+        stack.push((block, true));
+        for &child in dom.children(block).iter().rev() {
+            stack.push((child, false));
+        }
+    }
+    // unreachable blocks only see undefined variables
+    for b in 0..block_count {
+        if !dom.is_reachable(BlockId(b)) {
+            renamer.stacks.iter_mut().for_each(|s| s.clear());
+            renamer.rename_block(func, &phis, &succs, BlockId(b));
+        }
+    }
+
+    let Renamer {
+        replacements,
+        undefs,
+        phi_operands,
+        dels,
+        ..
+    } = renamer;
+
+    for (bi, block) in func.blocks.iter_mut().enumerate() {
+        let mut del = dels[bi].iter();
+        block.instructions.retain(|_| !*del.next().unwrap());
+
+        let mut new_instrs: Vec<Instruction> = phis[bi]
+            .iter()
+            .zip(phi_operands[bi].iter())
+            .map(|((_, val), ops)| Instruction {
+                yielded: Some(*val),
+                operation: Operation::Phi(ops.iter().map(|(b, v)| (*b, v.unwrap())).collect()),
+            })
+            .collect();
+        if bi == 0 {
+            new_instrs.extend(undefs.iter().flatten().map(|val| Instruction {
+                yielded: Some(*val),
+                operation: Operation::Undef,
+            }));
+        }
+        new_instrs.append(&mut block.instructions);
+        block.instructions = new_instrs;
+    }
+
+    func.replace_uses(&replacements);
+}
+
+type Incoming = Vec<(BlockId, Option<ValueId>)>;
+
+struct Renamer {
+    // loaded value -> the value it is replaced with
+    replacements: HashMap<ValueId, ValueId>,
+    stacks: Vec<Vec<ValueId>>,
+    undefs: Vec<Option<ValueId>>,
+    // block: Φ: incoming (pred, operand)
+    phi_operands: Vec<Vec<Incoming>>,
+    // block: instruction -> should delete instruction
+    dels: Vec<Vec<bool>>,
+}
+
+impl Renamer {
+    fn current_def(&mut self, func: &mut Function, var: VariableId) -> ValueId {
+        if let Some(val) = self.stacks[var.0].last() {
+            return *val;
+        }
+        *self.undefs[var.0].get_or_insert_with(|| func.push_value(func.variables[var.0].ty.clone()))
+    }
+
+    fn rename_block(
+        &mut self,
+        func: &mut Function,
+        phis: &[Vec<(VariableId, ValueId)>],
+        succs: &[Vec<BlockId>],
+        block: BlockId,
+    ) {
+        for (var, val) in phis[block.0].iter() {
+            self.stacks[var.0].push(*val);
+        }
+
+        let mut dels = Vec::with_capacity(func.blocks[block.0].instructions.len());
+        for i in 0..func.blocks[block.0].instructions.len() {
+            let instr = &func.blocks[block.0].instructions[i];
+            match instr.operation {
+                Operation::LoadVar(var) => {
+                    let loaded = instr.yielded.unwrap();
+                    let val = self.current_def(func, var);
+                    self.replacements.insert(loaded, val);
+                    dels.push(true);
+                }
+                Operation::StoreVar(var, val) => {
+                    self.stacks[var.0].push(val);
+                    dels.push(true);
+                }
+                _ => dels.push(false),
+            }
+        }
+        self.dels[block.0] = dels;
+
+        for succ in succs[block.0].iter() {
+            for (phi, (var, _)) in phis[succ.0].iter().enumerate() {
+                let val = self.current_def(func, *var);
+                for (pred, op) in self.phi_operands[succ.0][phi].iter_mut() {
+                    if *pred == block {
+                        *op = Some(val);
                     }
-                    _ => {}
                 }
             }
         }
     }
-    delete(module, &func_dels);
-    remove_singleelem_phis(module);
+
+    /// Returns the variables defined in `block`, once per definition.
+    fn pop_block(
+        &self,
+        func: &Function,
+        phis: &[(VariableId, ValueId)],
+        block: BlockId,
+    ) -> Vec<VariableId> {
+        let mut defined: Vec<VariableId> = phis.iter().map(|(var, _)| *var).collect();
+        for instr in func.blocks[block.0].instructions.iter() {
+            if let Operation::StoreVar(var, _) = instr.operation {
+                defined.push(var);
+            }
+        }
+        defined
+    }
 }
 
 pub fn remove_singleelem_phis(module: &mut Module) {
@@ -72,7 +271,7 @@ pub fn remove_singleelem_phis(module: &mut Module) {
                 dels[func_id][block_id].push(false);
                 if let Operation::Phi(ref vals) = instr.operation {
                     if vals.len() == 1 {
-                        func.replace_children_with(instr.yielded.unwrap(), vals[0]);
+                        func.replace_children_with(instr.yielded.unwrap(), vals[0].1);
                         // mark for deletion
                         dels[func_id][block_id][pos] = true;
                     }
@@ -82,37 +281,3 @@ pub fn remove_singleelem_phis(module: &mut Module) {
     }
     delete(module, &dels);
 }
-
-fn find_last_def(bb: &BasicBlock, var: VariableId) -> Option<ValueId> {
-    let rev_instrs = bb.instructions.iter().rev();
-    for instr in rev_instrs {
-        if let Operation::StoreVar(to, val) = instr.operation {
-            if to == var {
-                return Some(val);
-            }
-        }
-    }
-    None
-}
-
-fn find_defs_in_preds(
-    func: &Function,
-    block_id: BlockId,
-    var: VariableId,
-    stop_at: BlockId,
-    to: ValueId,
-) -> Vec<ValueId> {
-    let mut defs = Vec::new();
-    for pred in func.blocks[block_id.0].preds.clone() {
-        if pred == stop_at {
-            defs.push(to);
-            return defs;
-        }
-        if let Some(val) = find_last_def(&func.blocks[pred.0], var) {
-            defs.push(val);
-        } else {
-            defs.append(&mut find_defs_in_preds(func, pred, var, stop_at, to));
-        }
-    }
-    defs
-}
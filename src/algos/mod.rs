@@ -1,7 +1,11 @@
+pub mod analysis;
 pub mod delete_instructions;
+pub mod lower_aggregates;
 pub mod lower_to_ssa;
 pub mod opt;
+pub mod pass_manager;
 pub mod phi_removal;
 pub mod remove_critical_edges;
+pub mod simplify_cfg;
 
 pub(crate) mod par_move;
@@ -8,31 +8,61 @@ pub struct ConstantFolding;
 impl OptPass for ConstantFolding {
     fn run(&mut self, module: &mut Module) {
         for f in module.functions.iter_mut() {
+            // after phi removal, copies define the same value in several
+            // blocks, so only values defined once have a known value
+            let mut def_counts = HashMap::new();
+            for b in f.blocks.iter() {
+                for i in b.instructions.iter() {
+                    if let Some(val) = i.yielded {
+                        *def_counts.entry(val).or_insert(0) += 1;
+                    }
+                }
+            }
             let mut known_values = HashMap::new();
 
             for b in f.blocks.iter_mut() {
                 for i in b.instructions.iter_mut() {
-                    match i.operation {
+                    let result = match i.operation {
                         Operation::Integer(int) => {
-                            known_values.insert(i.yielded.unwrap(), int);
+                            let val = i.yielded.unwrap();
+                            if def_counts[&val] == 1 {
+                                known_values.insert(val, f.values[val.0].ty.normalize(int));
+                            }
+                            continue;
+                        }
+                        Operation::Copy(src) => known_values.get(&src).copied(),
+                        Operation::ZExt(src) | Operation::SExt(src) | Operation::Trunc(src) => {
+                            known_values.get(&src).and_then(|c| {
+                                let to = &f.values[i.yielded.unwrap().0].ty;
+                                i.operation.fold_cast(&f.values[src.0].ty, to, *c)
+                            })
                         }
                         Operation::BinOp(op, a, b) => {
-                            if let (Some(av), Some(bv)) =
-                                (known_values.get(&a), known_values.get(&b))
-                            {
-                                if let Some(result) = op.operate(*av, *bv) {
-                                    known_values.insert(i.yielded.unwrap(), result);
-                                    *i = Instruction {
-                                        operation: Operation::Integer(result),
-                                        yielded: i.yielded,
-                                    };
-                                }
+                            match (known_values.get(&a), known_values.get(&b)) {
+                                (Some(av), Some(bv)) => op.operate(&f.values[a.0].ty, *av, *bv),
+                                _ => None,
                             }
                         }
-                        _ => {}
+                        Operation::UnOp(op, src) => known_values
+                            .get(&src)
+                            .and_then(|c| op.operate(&f.values[src.0].ty, *c)),
+                        _ => None,
+                    };
+                    if let Some(result) = result {
+                        if def_counts[&i.yielded.unwrap()] == 1 {
+                            known_values.insert(i.yielded.unwrap(), result);
+                        }
+                        *i = Instruction {
+                            operation: Operation::Integer(result),
+                            yielded: i.yielded,
+                        };
                     }
                 }
             }
         }
     }
+
+    fn requires(&self) -> Vec<Algo> {
+        vec![Algo::PhiLowering]
+    }
 }
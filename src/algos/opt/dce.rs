@@ -0,0 +1,98 @@
+//! Dead code elimination.
+//!
+//! Both passes mark every value reachable through operands from the
+//! instructions with side effects (calls and stores) and the terminators, then
+//! sweep all instructions defining an unmarked value. `DeadCodeElimination`
+//! keeps every Φ, `AggressiveDce` only the ones that are used, and also
+//! removes the blocks that can't be reached from the entry.
+
+use super::FunctionPass;
+use crate::{
+    algos::analysis::dominators::DominatorTree,
+    ir::{BlockId, Function, Instruction, Operation, Terminator},
+};
+
+pub struct DeadCodeElimination;
+
+impl FunctionPass for DeadCodeElimination {
+    fn run_on_function(&mut self, func: &mut Function) {
+        eliminate_dead_code(func, false);
+    }
+}
+
+pub struct AggressiveDce;
+
+impl FunctionPass for AggressiveDce {
+    fn run_on_function(&mut self, func: &mut Function) {
+        remove_unreachable_blocks(func);
+        eliminate_dead_code(func, true);
+    }
+}
+
+/// Removes the blocks that can't be reached from the entry.
+pub(crate) fn remove_unreachable_blocks(func: &mut Function) {
+    let dom = DominatorTree::new(func);
+    let dead: Vec<bool> = (0..func.blocks.len())
+        .map(|b| !dom.is_reachable(BlockId(b)))
+        .collect();
+    if dead.contains(&true) {
+        func.remove_blocks(&dead);
+    }
+}
+
+fn has_side_effects(instr: &Instruction) -> bool {
+    matches!(
+        instr.operation,
+        Operation::Call(..) | Operation::StoreVar(..) | Operation::Store(..)
+    )
+}
+
+fn eliminate_dead_code(func: &mut Function, aggressive: bool) {
+    let mut live = vec![false; func.values.len()];
+    let mut worklist = Vec::new();
+    for block in func.blocks.iter() {
+        for instr in block.instructions.iter() {
+            let is_phi = matches!(instr.operation, Operation::Phi(_));
+            if has_side_effects(instr) || instr.yielded.is_none() || (is_phi && !aggressive) {
+                worklist.extend(instr.operation.operands());
+                if let Some(val) = instr.yielded {
+                    worklist.push(val);
+                }
+            }
+        }
+        worklist.extend(block.par_moves.iter().flat_map(|(dst, src)| [*dst, *src]));
+        match block.terminator {
+            Terminator::Return(val) | Terminator::Branch(val, ..) => worklist.push(val),
+            Terminator::Jump(_) | Terminator::NoTerm => {}
+        }
+    }
+
+    // a value may be defined more than once after phi removal, so find all of
+    // its definitions up front
+    let mut defs = vec![Vec::new(); func.values.len()];
+    for (bi, block) in func.blocks.iter().enumerate() {
+        for (ii, instr) in block.instructions.iter().enumerate() {
+            if let Some(val) = instr.yielded {
+                defs[val.0].push((bi, ii));
+            }
+        }
+    }
+
+    while let Some(val) = worklist.pop() {
+        if live[val.0] {
+            continue;
+        }
+        live[val.0] = true;
+        for (bi, ii) in defs[val.0].iter() {
+            worklist.extend(func.blocks[*bi].instructions[*ii].operation.operands());
+        }
+    }
+
+    for block in func.blocks.iter_mut() {
+        block.instructions.retain(|instr| match instr.yielded {
+            Some(val) => live[val.0] || has_side_effects(instr),
+            None => true,
+        });
+    }
+    func.rebuild_children();
+}
@@ -0,0 +1,109 @@
+//! Dominator-scoped global value numbering.
+//!
+//! Blocks are walked in preorder of the dominator tree with a hash table of
+//! the pure operations seen on the way down. An operation already in the table
+//! was computed in a dominating block, so its result can be reused, and the
+//! table is unwound when leaving a subtree.
+
+use std::collections::HashMap;
+
+use super::FunctionPass;
+use crate::{
+    algos::analysis::dominators::DominatorTree,
+    ir::{Algo, BlockId, Function, Operation, Type, ValueId},
+};
+
+pub struct Gvn;
+
+impl FunctionPass for Gvn {
+    fn run_on_function(&mut self, func: &mut Function) {
+        if func.blocks.is_empty() {
+            return;
+        }
+        let dom = DominatorTree::new(func);
+        let mut table: HashMap<(Operation, Type), ValueId> = HashMap::new();
+        // keys added by the blocks on the current path, to unwind the table
+        let mut scopes: Vec<Vec<(Operation, Type)>> = Vec::new();
+        let mut dels = vec![Vec::new(); func.blocks.len()];
+        // redundant values and the ones replacing them, which are never
+        // replaced themselves
+        let mut replaced: HashMap<ValueId, ValueId> = HashMap::new();
+
+        let mut stack = vec![(BlockId(0), false)];
+        while let Some((block, done)) = stack.pop() {
+            if done {
+                for key in scopes.pop().unwrap() {
+                    table.remove(&key);
+                }
+                continue;
+            }
+            let mut added = Vec::new();
+            for ii in 0..func.blocks[block.0].instructions.len() {
+                let instr = &mut func.blocks[block.0].instructions[ii];
+                // operands are defined in dominating blocks, which were already
+                // visited, so apply the replacements found there to match
+                // operations on the values replacing them
+                for op in instr.operation.operands_mut() {
+                    if let Some(&to) = replaced.get(op) {
+                        *op = to;
+                    }
+                }
+                let (Some(val), Some(op)) = (instr.yielded, canonical(&instr.operation)) else {
+                    continue;
+                };
+                let key = (op, func.values[val.0].ty.clone());
+                match table.get(&key) {
+                    Some(&existing) => {
+                        replaced.insert(val, existing);
+                        dels[block.0].push(ii);
+                    }
+                    None => {
+                        table.insert(key.clone(), val);
+                        added.push(key);
+                    }
+                }
+            }
+            scopes.push(added);
+            stack.push((block, true));
+            for &child in dom.children(block).iter().rev() {
+                stack.push((child, false));
+            }
+        }
+
+        for (block, dels) in func.blocks.iter_mut().zip(dels) {
+            for ii in dels.into_iter().rev() {
+                block.instructions.remove(ii);
+            }
+        }
+        func.replace_uses(&replaced);
+    }
+
+    fn requires(&self) -> Vec<Algo> {
+        vec![Algo::PhiLowering]
+    }
+
+    fn conflicts(&self) -> Vec<Algo> {
+        vec![Algo::PhiRemoval]
+    }
+}
+
+/// Returns the form of a pure operation used as the key of the table, with
+/// the operands of commutative (or mirrored, like `lt`/`gt`) operations in a
+/// fixed order, or `None` if the operation can't be reused.
+fn canonical(op: &Operation) -> Option<Operation> {
+    match op {
+        Operation::Integer(_) => Some(op.clone()),
+        op if op.is_cast() => Some(op.clone()),
+        Operation::UnOp(..)
+        | Operation::ElementPtr(..)
+        | Operation::GlobalAddr(_)
+        | Operation::FieldPtr(..)
+        | Operation::ExtractValue(..)
+        | Operation::InsertValue(..) => Some(op.clone()),
+        Operation::BinOp(binop, a, b) => match binop.swapped() {
+            Some(swapped) if b.0 < a.0 => Some(Operation::BinOp(swapped, *b, *a)),
+            _ => Some(op.clone()),
+        },
+        _ => None,
+    }
+}
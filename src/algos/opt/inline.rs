@@ -0,0 +1,271 @@
+//! Function inlining.
+//!
+//! A call is replaced by a copy of the callee's body: the calling block is
+//! split after the call, jumps to the copied entry block, and every copied
+//! `ret` jumps to the second half, where a Φ merges the returned values.
+//! Functions are processed callees first, so small call chains collapse
+//! completely, but only the calls a function had before it was processed are
+//! inlined into it, which keeps recursion from unrolling forever.
+
+use std::collections::HashMap;
+
+use super::OptPass;
+use crate::ir::{
+    Algo, Attribute, BasicBlock, BlockId, Function, FunctionId, Instruction, Linkage, Module,
+    Operation, Terminator, Type, ValueId,
+};
+
+/// Inlines calls to functions with at most `threshold` instructions (counting
+/// terminators), and to every function with `Attribute::AlwaysInline`.
+/// Functions with `Attribute::NoInline` and external functions are never
+/// inlined.
+pub struct Inline {
+    pub threshold: usize,
+}
+
+impl Default for Inline {
+    fn default() -> Self {
+        Inline { threshold: 24 }
+    }
+}
+
+impl OptPass for Inline {
+    fn run(&mut self, module: &mut Module) {
+        for caller in call_graph_postorder(module) {
+            let mut calls: Vec<(BlockId, usize)> = module.functions[caller.0]
+                .blocks
+                .iter()
+                .enumerate()
+                .flat_map(|(b, block)| {
+                    block
+                        .instructions
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, i)| matches!(i.operation, Operation::Call(..)))
+                        .map(move |(pos, _)| (BlockId(b), pos))
+                })
+                .collect();
+            for i in 0..calls.len() {
+                let (block, pos) = calls[i];
+                let func = &module.functions[caller.0];
+                let Operation::Call(callee, ref args) =
+                    func.blocks[block.0].instructions[pos].operation
+                else {
+                    unreachable!()
+                };
+                if callee == caller || !self.should_inline(&module.functions[callee.0]) {
+                    continue;
+                }
+                let callee = module.functions[callee.0].clone();
+                let args = args.clone();
+                let (join, offset) =
+                    inline_call(&mut module.functions[caller.0], block, pos, &callee, &args);
+                // the rest of the calling block moved to the join block
+                for (b, p) in calls[i + 1..].iter_mut() {
+                    if *b == block && *p > pos {
+                        (*b, *p) = (join, *p - pos - 1 + offset);
+                    }
+                }
+            }
+        }
+    }
+
+    fn requires(&self) -> Vec<Algo> {
+        vec![Algo::PhiLowering]
+    }
+
+    fn conflicts(&self) -> Vec<Algo> {
+        vec![Algo::PhiRemoval]
+    }
+}
+
+impl Inline {
+    fn should_inline(&self, callee: &Function) -> bool {
+        if callee.linkage == Linkage::External
+            || callee.blocks.is_empty()
+            || callee.attributes.contains(&Attribute::NoInline)
+            // the entry is jumped to from the caller, so it can't have Φs
+            || !callee.blocks[0].preds.is_empty()
+        {
+            return false;
+        }
+        if callee.attributes.contains(&Attribute::AlwaysInline) {
+            return true;
+        }
+        let size: usize = callee.blocks.iter().map(|b| b.instructions.len() + 1).sum();
+        size <= self.threshold
+    }
+}
+
+/// Returns the functions of `module` ordered so that callees come before their
+/// callers, except in cycles.
+fn call_graph_postorder(module: &Module) -> Vec<FunctionId> {
+    let callees: Vec<Vec<usize>> = module
+        .functions
+        .iter()
+        .map(|f| {
+            f.blocks
+                .iter()
+                .flat_map(|b| b.instructions.iter())
+                .filter_map(|i| match i.operation {
+                    Operation::Call(callee, _) => Some(callee.0),
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut order = Vec::new();
+    let mut visited = vec![false; callees.len()];
+    for root in 0..callees.len() {
+        if visited[root] {
+            continue;
+        }
+        visited[root] = true;
+        let mut stack = vec![(root, 0)];
+        while let Some((f, next)) = stack.pop() {
+            if let Some(&c) = callees[f].get(next) {
+                stack.push((f, next + 1));
+                if c < callees.len() && !visited[c] {
+                    visited[c] = true;
+                    stack.push((c, 0));
+                }
+            } else {
+                order.push(FunctionId(f));
+            }
+        }
+    }
+    order
+}
+
+/// Replaces the call at `func.blocks[block].instructions[pos]` by the body of
+/// `callee`. Returns the block the instructions after the call were moved
+/// to, and the position of the first one in it.
+fn inline_call(
+    func: &mut Function,
+    block: BlockId,
+    pos: usize,
+    callee: &Function,
+    args: &[ValueId],
+) -> (BlockId, usize) {
+    let base = func.blocks.len();
+    let join = BlockId(base + callee.blocks.len());
+    let map_block = |b: BlockId| BlockId(base + b.0);
+
+    // the callee's arguments become the call's operands, everything else is
+    // a fresh value
+    let mut values: HashMap<ValueId, ValueId> = HashMap::new();
+    for (i, arg) in args.iter().enumerate() {
+        values.insert(ValueId(i), *arg);
+    }
+    for (i, val) in callee.values.iter().enumerate().skip(callee.args.len()) {
+        let new = func.push_value(val.ty.clone());
+        func.values[new.0].owner = map_block(val.owner);
+        values.insert(ValueId(i), new);
+    }
+    let map_value = |v: ValueId| values.get(&v).copied().unwrap_or(v);
+
+    // split the calling block after the call
+    let call = func.blocks[block.0].instructions.remove(pos);
+    let after: Vec<Instruction> = func.blocks[block.0].instructions.split_off(pos);
+    let terminator = std::mem::replace(
+        &mut func.blocks[block.0].terminator,
+        Terminator::Jump(map_block(BlockId(0))),
+    );
+    for succ in terminator.successors() {
+        let succ = &mut func.blocks[succ.0];
+        for p in succ.preds.iter_mut().filter(|p| **p == block) {
+            *p = join;
+        }
+        for instr in succ.instructions.iter_mut() {
+            if let Operation::Phi(ref mut vals) = instr.operation {
+                for (p, _) in vals.iter_mut().filter(|(p, _)| *p == block) {
+                    *p = join;
+                }
+            }
+        }
+    }
+    for instr in after.iter() {
+        if let Some(val) = instr.yielded {
+            func.values[val.0].owner = join;
+        }
+    }
+
+    let mut returns = Vec::new();
+    for (bi, cb) in callee.blocks.iter().enumerate() {
+        let instructions = cb
+            .instructions
+            .iter()
+            .map(|i| {
+                let mut operation = i.operation.clone();
+                for op in operation.operands_mut() {
+                    *op = map_value(*op);
+                }
+                if let Operation::Phi(ref mut vals) = operation {
+                    for (p, _) in vals.iter_mut() {
+                        *p = map_block(*p);
+                    }
+                }
+                Instruction {
+                    yielded: i.yielded.map(map_value),
+                    operation,
+                }
+            })
+            .collect();
+        let terminator = match cb.terminator {
+            Terminator::Return(val) => {
+                returns.push((map_block(BlockId(bi)), map_value(val)));
+                Terminator::Jump(join)
+            }
+            Terminator::Jump(b) => Terminator::Jump(map_block(b)),
+            Terminator::Branch(val, t, f) => {
+                Terminator::Branch(map_value(val), map_block(t), map_block(f))
+            }
+            Terminator::NoTerm => Terminator::NoTerm,
+        };
+        let mut preds: Vec<BlockId> = cb.preds.iter().map(|p| map_block(*p)).collect();
+        if bi == 0 {
+            preds.push(block);
+        }
+        func.push_block(BasicBlock {
+            instructions,
+            terminator,
+            preds,
+            id: base + bi,
+            par_moves: vec![],
+        });
+    }
+
+    // the call's value is now the returned one
+    let mut join_instrs = Vec::new();
+    let returned = call.yielded.filter(|_| callee.ret_type != Type::Void);
+    if let Some(val) = returned {
+        func.values[val.0].owner = join;
+        match returns.len() {
+            1 => {}
+            // the callee never returns, so the join block is unreachable
+            0 => join_instrs.push(Instruction {
+                yielded: Some(val),
+                operation: Operation::Undef,
+            }),
+            _ => join_instrs.push(Instruction {
+                yielded: Some(val),
+                operation: Operation::Phi(returns.clone()),
+            }),
+        }
+    }
+    let offset = join_instrs.len();
+    join_instrs.extend(after);
+    func.push_block(BasicBlock {
+        instructions: join_instrs,
+        terminator,
+        preds: returns.iter().map(|(b, _)| *b).collect(),
+        id: join.0,
+        par_moves: vec![],
+    });
+    if let (Some(val), [(_, single)]) = (returned, returns.as_slice()) {
+        func.replace_children_with(val, *single);
+    }
+    func.rebuild_children();
+    (join, offset)
+}
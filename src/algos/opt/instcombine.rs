@@ -0,0 +1,338 @@
+//! Algebraic simplification of `BinOp`s, `UnOp`s, casts, element addresses
+//! and extracts of aggregate members, and folding of loads of constants.
+//!
+//! Every binop is rewritten to a simpler instruction where an identity
+//! applies (`x + 0` to `copy x`, `x - x` to `0`, ...), constant operands are
+//! moved to the right, `gt`/`ge` turned into `lt`/`le`, and `0 - x` and
+//! `x ^ -1` into `neg x` and `not x`, so later rules and GVN only need to look
+//! at one form. This is repeated until nothing changes.
+//!
+//! Rules looking through the definition of an operand, like reassociating
+//! `(x + 1) + 2` to `x + 3`, merging `zext (zext x)` into `zext x` or
+//! cancelling `neg (neg x)`, move uses of `x` and are only applied in SSA
+//! form. So is reading a member back out of an `insertvalue`.
+//! Loads of immutable integer globals are replaced with their initializer.
+//! In SSA form the copies left behind are propagated to their uses as well.
+
+use std::{cmp::Ordering, collections::HashMap};
+
+use super::OptPass;
+use crate::ir::{
+    APInt, Algo, BinOp, Constant, Function, Global, Instruction, Linkage, Module, Operation, Type,
+    UnOp, ValueId,
+};
+
+pub struct InstCombine;
+
+impl OptPass for InstCombine {
+    fn run(&mut self, module: &mut Module) {
+        let ssa = module.algos_run.contains(&Algo::PhiLowering)
+            && !module.algos_run.contains(&Algo::PhiRemoval);
+        for func in module.functions.iter_mut() {
+            while combine(func, &module.globals, ssa) {
+                if ssa {
+                    propagate_copies(func);
+                }
+            }
+            func.rebuild_children();
+        }
+    }
+}
+
+enum Rewrite {
+    Op(Operation),
+    /// `op x c` with a new constant `c`
+    WithConst(BinOp, ValueId, i64),
+    /// `elementptr ty base c` with a new constant index `c` of the given type
+    ElementWithConst(ValueId, i64, Type, Type),
+}
+
+struct Defs {
+    // values defined exactly once
+    ops: HashMap<ValueId, Operation>,
+    types: Vec<Type>,
+    // the value of each global that is an integer constant
+    globals: Vec<Option<i64>>,
+    ssa: bool,
+}
+
+impl Defs {
+    fn new(func: &Function, globals: &[Global], ssa: bool) -> Defs {
+        let mut ops = HashMap::new();
+        let mut multiple = Vec::new();
+        for instr in func.blocks.iter().flat_map(|b| b.instructions.iter()) {
+            if let Some(val) = instr.yielded {
+                if ops.insert(val, instr.operation.clone()).is_some() {
+                    multiple.push(val);
+                }
+            }
+        }
+        for val in multiple {
+            ops.remove(&val);
+        }
+        let types = func.values.iter().map(|v| v.ty.clone()).collect();
+        let globals = globals
+            .iter()
+            .map(|g| match (&g.init, &g.ty) {
+                _ if g.mutable || g.linkage == Linkage::External => None,
+                (Some(Constant::Integer(c)), ty @ Type::Integer(..)) => Some(ty.normalize(*c)),
+                (None, Type::Integer(..)) => Some(0),
+                _ => None,
+            })
+            .collect();
+        Defs {
+            ops,
+            types,
+            globals,
+            ssa,
+        }
+    }
+
+    fn constant(&self, val: ValueId) -> Option<i64> {
+        match self.ops.get(&val) {
+            Some(Operation::Integer(c)) => Some(self.types[val.0].normalize(*c)),
+            _ => None,
+        }
+    }
+
+    /// Returns the operation defining `val`, if its operands may be used in
+    /// place of `val`.
+    fn def(&self, val: ValueId) -> Option<&Operation> {
+        self.ssa.then(|| self.ops.get(&val)).flatten()
+    }
+}
+
+fn simplify(defs: &Defs, instr: &Instruction) -> Option<Rewrite> {
+    if instr.operation.is_cast() {
+        return simplify_cast(defs, instr);
+    }
+    if let Operation::UnOp(op, x) = instr.operation {
+        return simplify_unop(defs, op, x);
+    }
+    if let Operation::ElementPtr(base, index, ref ty) = instr.operation {
+        return simplify_element_ptr(defs, base, index, ty);
+    }
+    if let Operation::ExtractValue(agg, index) = instr.operation {
+        return simplify_extract_value(defs, agg, index);
+    }
+    if let Operation::Load(ptr) = instr.operation {
+        let Some(Operation::GlobalAddr(g)) = defs.ops.get(&ptr) else {
+            return None;
+        };
+        return defs.globals[g.0].map(|c| Rewrite::Op(Operation::Integer(c)));
+    }
+    let Operation::BinOp(op, a, b) = instr.operation else {
+        return None;
+    };
+    let ty = &defs.types[a.0];
+    let (ca, cb) = (defs.constant(a), defs.constant(b));
+    if let (Some(ca), Some(cb)) = (ca, cb) {
+        return op.operate(ty, ca, cb).map(|c| Rewrite::Op(Operation::Integer(c)));
+    }
+
+    // canonical forms
+    if ca.is_some() && op.is_commutative() {
+        return Some(Rewrite::Op(Operation::BinOp(op, b, a)));
+    }
+    if let BinOp::Gt | BinOp::Ge = op {
+        return Some(Rewrite::Op(Operation::BinOp(op.swapped().unwrap(), b, a)));
+    }
+    if let (BinOp::Sub, Some(0)) = (op, ca) {
+        return Some(Rewrite::Op(Operation::UnOp(UnOp::Neg, b)));
+    }
+    let all_ones = cb
+        .and_then(|cb| APInt::from_constant(ty, cb))
+        .is_some_and(|cb| cb.not().is_zero());
+    if op == BinOp::Xor && all_ones {
+        return Some(Rewrite::Op(Operation::UnOp(UnOp::Not, a)));
+    }
+
+    let rewrite = |op| Some(Rewrite::Op(op));
+    if a == b {
+        match op {
+            BinOp::Sub | BinOp::Xor | BinOp::Ne | BinOp::Lt => {
+                return rewrite(Operation::Integer(0))
+            }
+            BinOp::Eq | BinOp::Le => return rewrite(Operation::Integer(1)),
+            BinOp::And | BinOp::Or => return rewrite(Operation::Copy(a)),
+            _ => {}
+        }
+    }
+    match (op, cb) {
+        (BinOp::Add | BinOp::Sub | BinOp::Or | BinOp::Xor | BinOp::Shl | BinOp::Shr, Some(0))
+        | (BinOp::Mul | BinOp::Div, Some(1)) => return rewrite(Operation::Copy(a)),
+        (BinOp::Mul | BinOp::And, Some(0)) => return rewrite(Operation::Integer(0)),
+        _ => {}
+    }
+
+    // (x op c1) op c2
+    let (Some(c2), Some(&Operation::BinOp(inner, x, c1))) = (cb, defs.def(a)) else {
+        return None;
+    };
+    let c1 = defs.constant(c1)?;
+    let (new_op, c) = match (inner, op) {
+        (BinOp::Add, BinOp::Add) | (BinOp::Sub, BinOp::Sub) => {
+            (inner, BinOp::Add.operate(ty, c1, c2)?)
+        }
+        (BinOp::Add, BinOp::Sub) | (BinOp::Sub, BinOp::Add) => {
+            (inner, BinOp::Sub.operate(ty, c1, c2)?)
+        }
+        (BinOp::Mul | BinOp::And | BinOp::Or | BinOp::Xor, _) if inner == op => {
+            (op, op.operate(ty, c1, c2)?)
+        }
+        _ => return None,
+    };
+    Some(Rewrite::WithConst(new_op, x, c))
+}
+
+/// Folds unops of constants and cancels `neg (neg x)` and `not (not x)`.
+fn simplify_unop(defs: &Defs, op: UnOp, x: ValueId) -> Option<Rewrite> {
+    if let Some(c) = defs.constant(x) {
+        let folded = op.operate(&defs.types[x.0], c)?;
+        return Some(Rewrite::Op(Operation::Integer(folded)));
+    }
+    match defs.def(x)? {
+        Operation::UnOp(inner, y) if *inner == op && op != UnOp::LNot => {
+            Some(Rewrite::Op(Operation::Copy(*y)))
+        }
+        _ => None,
+    }
+}
+
+/// Turns the address of the first element of an array of the type pointed to
+/// into the pointer itself, and adds up the constant indices of addresses of
+/// elements of elements of the same type.
+fn simplify_element_ptr(defs: &Defs, base: ValueId, index: ValueId, ty: &Type) -> Option<Rewrite> {
+    let c2 = defs.constant(index)?;
+    let same_type = matches!(defs.types[base.0], Type::Pointer(ref p) if **p == *ty);
+    if same_type && c2 == 0 {
+        return Some(Rewrite::Op(Operation::Copy(base)));
+    }
+    let index_ty = &defs.types[index.0];
+    let Some(Operation::ElementPtr(inner, c1, inner_ty)) = defs.def(base) else {
+        return None;
+    };
+    if inner_ty != ty || defs.types[c1.0] != *index_ty {
+        return None;
+    }
+    let c = BinOp::Add.operate(index_ty, defs.constant(*c1)?, c2)?;
+    Some(Rewrite::ElementWithConst(*inner, c, index_ty.clone(), ty.clone()))
+}
+
+/// Extracts a member from the `insertvalue` it was inserted by, or from the
+/// aggregate the `insertvalue` inserted some other member into.
+fn simplify_extract_value(defs: &Defs, agg: ValueId, index: usize) -> Option<Rewrite> {
+    match defs.def(agg)? {
+        Operation::InsertValue(_, val, i) if *i == index => {
+            Some(Rewrite::Op(Operation::Copy(*val)))
+        }
+        Operation::InsertValue(inner, ..) => {
+            Some(Rewrite::Op(Operation::ExtractValue(*inner, index)))
+        }
+        _ => None,
+    }
+}
+
+/// Folds casts of constants and merges casts of casts.
+fn simplify_cast(defs: &Defs, instr: &Instruction) -> Option<Rewrite> {
+    let cast = &instr.operation;
+    let src = cast.operands()[0];
+    let to = &defs.types[instr.yielded?.0];
+    if let Some(c) = defs.constant(src) {
+        let folded = cast.fold_cast(&defs.types[src.0], to, c)?;
+        return Some(Rewrite::Op(Operation::Integer(folded)));
+    }
+
+    let inner = defs.def(src)?;
+    if !inner.is_cast() {
+        return None;
+    }
+    let x = inner.operands()[0];
+    let from = &defs.types[x.0];
+    let op = match (inner, cast) {
+        // a zero extended value has a clear sign bit
+        (Operation::ZExt(_), Operation::ZExt(_) | Operation::SExt(_)) => Operation::ZExt(x),
+        (Operation::SExt(_), Operation::SExt(_)) => Operation::SExt(x),
+        (Operation::Trunc(_), Operation::Trunc(_)) => Operation::Trunc(x),
+        (Operation::ZExt(_) | Operation::SExt(_), Operation::Trunc(_)) => {
+            let (Type::Integer(from_width, _), Type::Integer(to_width, _)) = (from, to) else {
+                return None;
+            };
+            match from_width.cmp(to_width) {
+                Ordering::Less if matches!(inner, Operation::ZExt(_)) => Operation::ZExt(x),
+                Ordering::Less => Operation::SExt(x),
+                Ordering::Equal if from == to => Operation::Copy(x),
+                Ordering::Equal => return None,
+                Ordering::Greater => Operation::Trunc(x),
+            }
+        }
+        (Operation::PtrToInt(_), Operation::IntToPtr(_))
+        | (Operation::IntToPtr(_), Operation::PtrToInt(_))
+            if from == to =>
+        {
+            Operation::Copy(x)
+        }
+        _ => return None,
+    };
+    Some(Rewrite::Op(op))
+}
+
+/// Does one round of rewrites over `func` and returns whether anything
+/// changed.
+fn combine(func: &mut Function, globals: &[Global], ssa: bool) -> bool {
+    let defs = Defs::new(func, globals, ssa);
+    let mut changed = false;
+    for bi in 0..func.blocks.len() {
+        let mut ii = 0;
+        while ii < func.blocks[bi].instructions.len() {
+            let instr = &func.blocks[bi].instructions[ii];
+            let Some(rewrite) = simplify(&defs, instr) else {
+                ii += 1;
+                continue;
+            };
+            let yielded = instr.yielded;
+            changed = true;
+            let (operation, constant) = match rewrite {
+                Rewrite::Op(op) => (op, None),
+                Rewrite::WithConst(op, x, c) => {
+                    let ty = func.values[yielded.unwrap().0].ty.clone();
+                    let constant = func.push_value(ty);
+                    (Operation::BinOp(op, x, constant), Some((constant, c)))
+                }
+                Rewrite::ElementWithConst(base, c, index_ty, ty) => {
+                    let constant = func.push_value(index_ty);
+                    (Operation::ElementPtr(base, constant, ty), Some((constant, c)))
+                }
+            };
+            if let Some((constant, c)) = constant {
+                func.values[constant.0].owner = func.values[yielded.unwrap().0].owner;
+                func.blocks[bi].instructions.insert(
+                    ii,
+                    Instruction {
+                        yielded: Some(constant),
+                        operation: Operation::Integer(c),
+                    },
+                );
+                ii += 1;
+            }
+            func.blocks[bi].instructions[ii].operation = operation;
+            ii += 1;
+        }
+    }
+    changed
+}
+
+/// Replaces the uses of every copy with its source and removes the copies.
+fn propagate_copies(func: &mut Function) {
+    let mut map = HashMap::new();
+    for block in func.blocks.iter_mut() {
+        block.instructions.retain(|instr| match instr.operation {
+            Operation::Copy(src) => {
+                map.insert(instr.yielded.unwrap(), src);
+                false
+            }
+            _ => true,
+        });
+    }
+    func.replace_uses(&map);
+}
@@ -0,0 +1,198 @@
+//! Loop invariant code motion.
+//!
+//! Pure operations whose operands are all defined outside a loop are hoisted
+//! to its preheader, and pure operations whose results are only used after
+//! the loop are sunk to its exit. Loops are processed innermost first, so code
+//! hoisted out of an inner loop can be hoisted out of the outer one as well.
+
+use super::FunctionPass;
+use crate::{
+    algos::analysis::{
+        dominators::DominatorTree,
+        loops::{simplify_loops, LoopId, LoopInfo},
+    },
+    ir::{Algo, BinOp, BlockId, Function, Instruction, Operation, Terminator, ValueId},
+};
+
+pub struct Licm;
+
+impl FunctionPass for Licm {
+    fn run_on_function(&mut self, func: &mut Function) {
+        if func.blocks.is_empty() {
+            return;
+        }
+        let info = simplify_loops(func);
+        // hoisting and sinking move instructions but never change the CFG,
+        // so the loops and dominators stay valid
+        let dom = DominatorTree::new(func);
+        for l in info.innermost_first() {
+            hoist(func, &info, l);
+            sink(func, &dom, &info, l);
+        }
+        func.rebuild_children();
+    }
+
+    fn requires(&self) -> Vec<Algo> {
+        vec![Algo::PhiLowering]
+    }
+
+    fn conflicts(&self) -> Vec<Algo> {
+        vec![Algo::PhiRemoval]
+    }
+}
+
+/// Returns the block defining every value, arguments being defined in the
+/// entry block.
+fn def_blocks(func: &Function) -> Vec<Option<BlockId>> {
+    let mut defs = vec![None; func.values.len()];
+    for d in defs.iter_mut().take(func.args.len()) {
+        *d = Some(BlockId(0));
+    }
+    for (bi, block) in func.blocks.iter().enumerate() {
+        for instr in block.instructions.iter() {
+            if let Some(val) = instr.yielded {
+                defs[val.0] = Some(BlockId(bi));
+            }
+        }
+    }
+    defs
+}
+
+/// Returns whether `instr` can be moved to a place where it is executed more
+/// or less often than it was.
+fn is_movable(func: &Function, instr: &Instruction) -> bool {
+    match instr.operation {
+        Operation::Integer(_) => true,
+        ref op if op.is_cast() => true,
+        Operation::BinOp(BinOp::Div | BinOp::Mod, _, divisor) => {
+            // division by zero may trap, and so may the signed `MIN / -1`, so
+            // only constant divisors other than those are safe
+            let ty = &func.values[divisor.0].ty;
+            let safe = |c: i64| {
+                let c = ty.normalize(c);
+                c != 0 && !(ty.is_signed() && c == -1)
+            };
+            func.blocks
+                .iter()
+                .flat_map(|b| b.instructions.iter())
+                .filter(|i| i.yielded == Some(divisor))
+                .any(|i| matches!(i.operation, Operation::Integer(c) if safe(c)))
+        }
+        Operation::BinOp(..)
+        | Operation::UnOp(..)
+        | Operation::ElementPtr(..)
+        | Operation::GlobalAddr(_)
+        | Operation::FieldPtr(..)
+        | Operation::ExtractValue(..)
+        | Operation::InsertValue(..) => true,
+        _ => false,
+    }
+}
+
+fn hoist(func: &mut Function, info: &LoopInfo, l: LoopId) {
+    let lp = info.get(l);
+    let Some(preheader) = lp.preheader(func) else {
+        return;
+    };
+    let mut defs = def_blocks(func);
+    let invariant = |defs: &[Option<BlockId>], val: &ValueId| {
+        defs[val.0].is_some_and(|b| !lp.contains(b))
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &b in lp.blocks() {
+            let mut ii = 0;
+            while ii < func.blocks[b.0].instructions.len() {
+                let instr = &func.blocks[b.0].instructions[ii];
+                let hoistable = is_movable(func, instr)
+                    && instr.operation.operands().iter().all(|v| invariant(&defs, v));
+                if !hoistable {
+                    ii += 1;
+                    continue;
+                }
+                let instr = func.blocks[b.0].instructions.remove(ii);
+                let val = instr.yielded.unwrap();
+                defs[val.0] = Some(preheader);
+                func.values[val.0].owner = preheader;
+                func.blocks[preheader.0].instructions.push(instr);
+                changed = true;
+            }
+        }
+    }
+}
+
+/// Returns the blocks where `val` is used, a Φ operand being used at the end
+/// of the predecessor it comes from.
+fn use_blocks(func: &Function, val: ValueId) -> Vec<BlockId> {
+    let mut uses = Vec::new();
+    for (bi, block) in func.blocks.iter().enumerate() {
+        for instr in block.instructions.iter() {
+            match &instr.operation {
+                Operation::Phi(vals) => {
+                    uses.extend(vals.iter().filter(|(_, v)| *v == val).map(|(p, _)| *p))
+                }
+                op if op.operands().contains(&val) => uses.push(BlockId(bi)),
+                _ => {}
+            }
+        }
+        match block.terminator {
+            Terminator::Return(v) | Terminator::Branch(v, ..) if v == val => {
+                uses.push(BlockId(bi))
+            }
+            _ => {}
+        }
+    }
+    uses
+}
+
+fn sink(func: &mut Function, dom: &DominatorTree, info: &LoopInfo, l: LoopId) {
+    let lp = info.get(l);
+    let &[exit] = lp.exits() else {
+        return;
+    };
+    let exiting: Vec<BlockId> = func.blocks[exit.0].preds.clone();
+    if exiting.iter().any(|p| !lp.contains(*p)) {
+        return;
+    }
+    let defs = def_blocks(func);
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &b in lp.blocks().iter().rev() {
+            // the block must run in the last iteration, and the operands
+            // defined in the loop must not be redefined after it
+            if info.loop_of(b) != Some(l) || !exiting.iter().all(|e| dom.dominates(b, *e)) {
+                continue;
+            }
+            for ii in (0..func.blocks[b.0].instructions.len()).rev() {
+                let instr = &func.blocks[b.0].instructions[ii];
+                let Some(val) = instr.yielded else {
+                    continue;
+                };
+                let operands_ok = instr.operation.operands().iter().all(|v| {
+                    defs[v.0].is_some_and(|d| !lp.contains(d) || info.loop_of(d) == Some(l))
+                });
+                let uses = use_blocks(func, val);
+                if !is_movable(func, instr)
+                    || !operands_ok
+                    || uses.is_empty()
+                    || !uses.iter().all(|u| !lp.contains(*u) && dom.dominates(exit, *u))
+                {
+                    continue;
+                }
+                let instr = func.blocks[b.0].instructions.remove(ii);
+                let phi_count = func.blocks[exit.0]
+                    .instructions
+                    .iter()
+                    .take_while(|i| matches!(i.operation, Operation::Phi(_)))
+                    .count();
+                func.blocks[exit.0].instructions.insert(phi_count, instr);
+                func.values[val.0].owner = exit;
+                changed = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Promotion of stack slots to SSA values.
+//!
+//! An `alloca` of a scalar whose address is only ever loaded from and stored
+//! to, never stored itself, passed to a call or returned, can't be accessed
+//! in any other way, so it behaves exactly like a variable. Its loads and
+//! stores are turned into `load #var` and `store #var` of a new variable,
+//! which the SSA construction of `lower_to_ssa` then replaces with values and
+//! Φs like it does for the variables of the front-end.
+
+use super::FunctionPass;
+use crate::{
+    algos::lower_to_ssa::construct_ssa,
+    ir::{Algo, Function, Operation, Terminator, Type, ValueId, Variable, VariableId},
+};
+
+pub struct Mem2Reg;
+
+impl FunctionPass for Mem2Reg {
+    fn run_on_function(&mut self, func: &mut Function) {
+        let slots = promotable_allocas(func);
+        if slots.is_empty() {
+            return;
+        }
+        let var_count = func.variables.len();
+        let mut vars = vec![None; func.values.len()];
+        for (i, (slot, ty)) in slots.into_iter().enumerate() {
+            vars[slot.0] = Some(VariableId(var_count + i));
+            func.variables.push(Variable {
+                name: slot.to_string(),
+                ty,
+                bbs_assign_to: Default::default(),
+            });
+        }
+
+        for block in func.blocks.iter_mut() {
+            block.instructions.retain(|instr| match instr.operation {
+                Operation::Alloca(_) => vars[instr.yielded.unwrap().0].is_none(),
+                _ => true,
+            });
+            for instr in block.instructions.iter_mut() {
+                instr.operation = match instr.operation {
+                    Operation::Load(ptr) => match vars[ptr.0] {
+                        Some(var) => Operation::LoadVar(var),
+                        None => continue,
+                    },
+                    Operation::Store(ptr, val) => match vars[ptr.0] {
+                        Some(var) => Operation::StoreVar(var, val),
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+            }
+        }
+
+        construct_ssa(func);
+        // the slots are gone for good, unlike the variables of the front-end
+        func.variables.truncate(var_count);
+        func.rebuild_children();
+    }
+
+    fn requires(&self) -> Vec<Algo> {
+        vec![Algo::PhiLowering]
+    }
+
+    fn conflicts(&self) -> Vec<Algo> {
+        vec![Algo::PhiRemoval]
+    }
+}
+
+/// Returns the `alloca`s of scalars whose address doesn't escape, with the
+/// type of their slot.
+fn promotable_allocas(func: &Function) -> Vec<(ValueId, Type)> {
+    let mut slots = Vec::new();
+    let mut escapes = vec![false; func.values.len()];
+    for block in func.blocks.iter() {
+        for instr in block.instructions.iter() {
+            match instr.operation {
+                Operation::Alloca(ref ty @ (Type::Integer(..) | Type::Pointer(_))) => {
+                    slots.push((instr.yielded.unwrap(), ty.clone()));
+                }
+                Operation::Load(_) => {}
+                Operation::Store(_, val) => escapes[val.0] = true,
+                ref op => op.operands().iter().for_each(|v| escapes[v.0] = true),
+            }
+        }
+        match block.terminator {
+            Terminator::Return(val) | Terminator::Branch(val, ..) => escapes[val.0] = true,
+            Terminator::Jump(_) | Terminator::NoTerm => {}
+        }
+    }
+    slots.retain(|(slot, _)| !escapes[slot.0]);
+    slots
+}
@@ -1,7 +1,89 @@
-use crate::ir::Module;
+use crate::ir::{Algo, Function, Module};
 
 pub mod constant_folding;
+pub mod dce;
+pub mod gvn;
+pub mod inline;
+pub mod instcombine;
+pub mod licm;
+pub mod mem2reg;
+pub mod sccp;
+pub mod strength_reduction;
 
+/// A transformation of a module.
+///
+/// The properties a pass depends on are expressed as the `Algo`s that must
+/// (or must not) have run on the module, which lets the `PassManager` run any
+/// missing mandatory transforms before it.
 pub trait OptPass {
     fn run(&mut self, module: &mut Module);
+
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// `Algo`s that must have run before this pass.
+    fn requires(&self) -> Vec<Algo> {
+        vec![]
+    }
+
+    /// `Algo`s that must not have run before this pass.
+    fn conflicts(&self) -> Vec<Algo> {
+        vec![]
+    }
+
+    /// `Algo`s whose guarantees no longer hold after this pass, for example
+    /// `Algo::CriticalEdgeSplitting` for a pass adding edges.
+    fn invalidates(&self) -> Vec<Algo> {
+        vec![]
+    }
+}
+
+/// A transformation that looks at a single function at a time. It can be run
+/// on a whole module with `ForEachFunction`.
+pub trait FunctionPass {
+    fn run_on_function(&mut self, func: &mut Function);
+
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    fn requires(&self) -> Vec<Algo> {
+        vec![]
+    }
+
+    fn conflicts(&self) -> Vec<Algo> {
+        vec![]
+    }
+
+    fn invalidates(&self) -> Vec<Algo> {
+        vec![]
+    }
+}
+
+/// Runs a `FunctionPass` on every function of the module.
+pub struct ForEachFunction<P: FunctionPass>(pub P);
+
+impl<P: FunctionPass> OptPass for ForEachFunction<P> {
+    fn run(&mut self, module: &mut Module) {
+        for func in module.functions.iter_mut() {
+            self.0.run_on_function(func);
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    fn requires(&self) -> Vec<Algo> {
+        self.0.requires()
+    }
+
+    fn conflicts(&self) -> Vec<Algo> {
+        self.0.conflicts()
+    }
+
+    fn invalidates(&self) -> Vec<Algo> {
+        self.0.invalidates()
+    }
 }
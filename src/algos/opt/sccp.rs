@@ -0,0 +1,262 @@
+//! Sparse conditional constant propagation, from Wegman and Zadeck,
+//! "Constant Propagation with Conditional Branches".
+//!
+//! Every value starts out as `Lattice::Top` (no definition seen yet) and only
+//! moves down the lattice. Blocks are only evaluated once an edge into them is
+//! known to be executable, and Φs only meet the operands coming in through
+//! executable edges, so constants flow through branches that are never taken.
+
+use super::{dce::remove_unreachable_blocks, FunctionPass};
+use crate::ir::{Algo, BlockId, Function, Instruction, Operation, Terminator, ValueId};
+
+pub struct Sccp;
+
+impl FunctionPass for Sccp {
+    fn run_on_function(&mut self, func: &mut Function) {
+        if func.blocks.is_empty() {
+            return;
+        }
+        let mut solver = Solver::new(func);
+        solver.solve(func);
+        rewrite(func, &solver);
+    }
+
+    fn requires(&self) -> Vec<Algo> {
+        vec![Algo::PhiLowering]
+    }
+
+    fn conflicts(&self) -> Vec<Algo> {
+        vec![Algo::PhiRemoval]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lattice {
+    Top,
+    Const(i64),
+    Bottom,
+}
+
+impl Lattice {
+    fn meet(self, other: Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Top, x) | (x, Lattice::Top) => x,
+            (Lattice::Const(a), Lattice::Const(b)) if a == b => Lattice::Const(a),
+            _ => Lattice::Bottom,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Use {
+    Instr(usize, usize),
+    Term(usize),
+}
+
+struct Solver {
+    values: Vec<Lattice>,
+    uses: Vec<Vec<Use>>,
+    executable: Vec<bool>,
+    // (from, to) edges known to be executable
+    edges: Vec<(BlockId, BlockId)>,
+    cfg_worklist: Vec<(Option<BlockId>, BlockId)>,
+    ssa_worklist: Vec<ValueId>,
+}
+
+impl Solver {
+    fn new(func: &Function) -> Solver {
+        let mut values = vec![Lattice::Top; func.values.len()];
+        for v in values.iter_mut().take(func.args.len()) {
+            *v = Lattice::Bottom;
+        }
+        let mut uses = vec![Vec::new(); func.values.len()];
+        for (bi, block) in func.blocks.iter().enumerate() {
+            for (ii, instr) in block.instructions.iter().enumerate() {
+                for op in instr.operation.operands() {
+                    uses[op.0].push(Use::Instr(bi, ii));
+                }
+            }
+            if let Terminator::Branch(val, ..) = block.terminator {
+                uses[val.0].push(Use::Term(bi));
+            }
+        }
+        Solver {
+            values,
+            uses,
+            executable: vec![false; func.blocks.len()],
+            edges: Vec::new(),
+            cfg_worklist: vec![(None, BlockId(0))],
+            ssa_worklist: Vec::new(),
+        }
+    }
+
+    fn solve(&mut self, func: &Function) {
+        loop {
+            if let Some((from, to)) = self.cfg_worklist.pop() {
+                if let Some(from) = from {
+                    if self.edges.contains(&(from, to)) {
+                        continue;
+                    }
+                    self.edges.push((from, to));
+                }
+                let first_visit = !self.executable[to.0];
+                self.executable[to.0] = true;
+                for (ii, instr) in func.blocks[to.0].instructions.iter().enumerate() {
+                    // Φs have to be reevaluated for every new incoming edge
+                    if first_visit || matches!(instr.operation, Operation::Phi(_)) {
+                        self.visit_instr(func, to.0, ii);
+                    }
+                }
+                if first_visit {
+                    self.visit_terminator(func, to.0);
+                }
+            } else if let Some(val) = self.ssa_worklist.pop() {
+                for u in self.uses[val.0].clone() {
+                    match u {
+                        Use::Instr(bi, ii) if self.executable[bi] => self.visit_instr(func, bi, ii),
+                        Use::Term(bi) if self.executable[bi] => self.visit_terminator(func, bi),
+                        _ => {}
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn set(&mut self, val: ValueId, new: Lattice) {
+        let old = self.values[val.0];
+        let new = old.meet(new);
+        if new != old {
+            self.values[val.0] = new;
+            self.ssa_worklist.push(val);
+        }
+    }
+
+    fn visit_instr(&mut self, func: &Function, bi: usize, ii: usize) {
+        let instr = &func.blocks[bi].instructions[ii];
+        let Some(yielded) = instr.yielded else {
+            return;
+        };
+        let result = match &instr.operation {
+            Operation::Integer(int) => {
+                Lattice::Const(func.values[yielded.0].ty.normalize(*int))
+            }
+            Operation::Copy(src) => self.values[src.0],
+            Operation::ZExt(src) | Operation::SExt(src) | Operation::Trunc(src) => {
+                match self.values[src.0] {
+                    Lattice::Const(c) => {
+                        let (from, to) = (&func.values[src.0].ty, &func.values[yielded.0].ty);
+                        let folded = instr.operation.fold_cast(from, to, c);
+                        folded.map_or(Lattice::Bottom, Lattice::Const)
+                    }
+                    other => other,
+                }
+            }
+            Operation::BinOp(op, a, b) => match (self.values[a.0], self.values[b.0]) {
+                (Lattice::Const(a), Lattice::Const(b)) => {
+                    let ty = &func.values[instr.operation.operands()[0].0].ty;
+                    op.operate(ty, a, b).map_or(Lattice::Bottom, Lattice::Const)
+                }
+                (Lattice::Bottom, _) | (_, Lattice::Bottom) => Lattice::Bottom,
+                _ => Lattice::Top,
+            },
+            Operation::UnOp(op, src) => match self.values[src.0] {
+                Lattice::Const(c) => {
+                    let folded = op.operate(&func.values[src.0].ty, c);
+                    folded.map_or(Lattice::Bottom, Lattice::Const)
+                }
+                other => other,
+            },
+            Operation::Phi(vals) => vals
+                .iter()
+                .filter(|(pred, _)| self.edges.contains(&(*pred, BlockId(bi))))
+                .fold(Lattice::Top, |acc, (_, val)| acc.meet(self.values[val.0])),
+            Operation::Call(..)
+            | Operation::LoadVar(_)
+            | Operation::StoreVar(..)
+            | Operation::Alloca(_)
+            | Operation::Load(_)
+            | Operation::Store(..)
+            | Operation::ElementPtr(..)
+            | Operation::GlobalAddr(_)
+            | Operation::FieldPtr(..)
+            | Operation::ExtractValue(..)
+            | Operation::InsertValue(..)
+            | Operation::Undef
+            | Operation::PtrToInt(_)
+            | Operation::IntToPtr(_) => Lattice::Bottom,
+        };
+        self.set(yielded, result);
+    }
+
+    fn visit_terminator(&mut self, func: &Function, bi: usize) {
+        let from = Some(BlockId(bi));
+        match func.blocks[bi].terminator {
+            Terminator::Jump(to) => self.cfg_worklist.push((from, to)),
+            Terminator::Branch(cond, t, f) => match self.values[cond.0] {
+                Lattice::Top => {}
+                Lattice::Const(c) => self.cfg_worklist.push((from, if c != 0 { t } else { f })),
+                Lattice::Bottom => {
+                    self.cfg_worklist.push((from, t));
+                    self.cfg_worklist.push((from, f));
+                }
+            },
+            Terminator::Return(_) | Terminator::NoTerm => {}
+        }
+    }
+}
+
+fn rewrite(func: &mut Function, solver: &Solver) {
+    for block in func.blocks.iter_mut() {
+        let mut folded_phis = Vec::new();
+        block.instructions.retain_mut(|instr| {
+            let Some(Lattice::Const(c)) = instr.yielded.map(|v| solver.values[v.0]) else {
+                return true;
+            };
+            let folded = Instruction {
+                yielded: instr.yielded,
+                operation: Operation::Integer(c),
+            };
+            if let Operation::Phi(_) = instr.operation {
+                // keep the Φs at the start of the block
+                folded_phis.push(folded);
+                return false;
+            }
+            *instr = folded;
+            true
+        });
+        let phi_count = block
+            .instructions
+            .iter()
+            .take_while(|i| matches!(i.operation, Operation::Phi(_)))
+            .count();
+        block.instructions.splice(phi_count..phi_count, folded_phis);
+    }
+
+    for bi in 0..func.blocks.len() {
+        let Terminator::Branch(cond, t, f) = func.blocks[bi].terminator else {
+            continue;
+        };
+        let Lattice::Const(c) = solver.values[cond.0] else {
+            continue;
+        };
+        let (taken, not_taken) = if c != 0 { (t, f) } else { (f, t) };
+        func.blocks[bi].terminator = Terminator::Jump(taken);
+        // drop one edge to the other target, which may be the same block
+        let target = &mut func.blocks[not_taken.0];
+        if let Some(pos) = target.preds.iter().position(|p| p.0 == bi) {
+            target.preds.remove(pos);
+        }
+        for instr in target.instructions.iter_mut() {
+            if let Operation::Phi(ref mut vals) = instr.operation {
+                if let Some(pos) = vals.iter().position(|(p, _)| p.0 == bi) {
+                    vals.remove(pos);
+                }
+            }
+        }
+    }
+
+    remove_unreachable_blocks(func);
+    func.rebuild_children();
+}
@@ -0,0 +1,295 @@
+//! Strength reduction: replacing expensive operations by cheaper ones.
+//!
+//! `StrengthReduction` rewrites multiplications, and unsigned divisions and
+//! remainders, by constants into shifts, masks and (if the target has it)
+//! `mulhi`. `IvStrengthReduction` replaces multiplications of a loop's
+//! induction variable by a constant with a second induction variable that is
+//! incremented by the scaled step instead.
+
+use std::collections::HashMap;
+
+use super::FunctionPass;
+use crate::{
+    algos::analysis::loops::simplify_loops,
+    ir::{APInt, Algo, BinOp, BlockId, Function, Instruction, Operation, Type, ValueId},
+};
+
+/// Rewrites `mul`, `div` and `mod` by constants. Division by constants that
+/// aren't powers of two is only rewritten with `mul_high` set, as it needs
+/// `BinOp::MulHi`.
+#[derive(Default)]
+pub struct StrengthReduction {
+    pub mul_high: bool,
+}
+
+impl FunctionPass for StrengthReduction {
+    fn run_on_function(&mut self, func: &mut Function) {
+        let constants = func.constants();
+        for bi in 0..func.blocks.len() {
+            let mut ii = 0;
+            while ii < func.blocks[bi].instructions.len() {
+                let instr = &func.blocks[bi].instructions[ii];
+                let (Some(val), Operation::BinOp(op, a, b)) = (instr.yielded, &instr.operation)
+                else {
+                    ii += 1;
+                    continue;
+                };
+                let (op, a, b) = (*op, *a, *b);
+                let ty = func.values[val.0].ty.clone();
+                let mut emitter = Emitter {
+                    func,
+                    block: BlockId(bi),
+                    pos: ii,
+                    ty,
+                };
+                if let Some(operation) = self.reduce(&mut emitter, &constants, op, a, b) {
+                    ii = emitter.pos;
+                    func.blocks[bi].instructions[ii].operation = operation;
+                }
+                ii += 1;
+            }
+        }
+        func.rebuild_children();
+    }
+
+    fn requires(&self) -> Vec<Algo> {
+        vec![Algo::PhiLowering]
+    }
+
+    fn conflicts(&self) -> Vec<Algo> {
+        vec![Algo::PhiRemoval]
+    }
+}
+
+impl StrengthReduction {
+    /// Returns the operation replacing `a op b`, after emitting whatever it
+    /// depends on.
+    fn reduce(
+        &self,
+        e: &mut Emitter,
+        constants: &HashMap<ValueId, i64>,
+        op: BinOp,
+        a: ValueId,
+        b: ValueId,
+    ) -> Option<Operation> {
+        let Type::Integer(width, signed) = e.ty else {
+            return None;
+        };
+        if width > 64 {
+            return None;
+        }
+        let value = |v: ValueId| constants.get(&v).map(|c| APInt::from_i64(width, *c).zext_value());
+        match op {
+            BinOp::Mul => {
+                let (x, c) = match (value(a), value(b)) {
+                    (_, Some(c)) => (a, c),
+                    (Some(c), None) => (b, c),
+                    (None, None) => return None,
+                };
+                let shift = log2(c)?;
+                Some(Operation::BinOp(BinOp::Shl, x, e.constant(shift)))
+            }
+            BinOp::Div | BinOp::Mod if !signed => {
+                let d = value(b)? as u64;
+                if d < 2 {
+                    return None;
+                }
+                if let Some(shift) = log2(d as u128) {
+                    return Some(match op {
+                        BinOp::Div => Operation::BinOp(BinOp::Shr, a, e.constant(shift)),
+                        _ => Operation::BinOp(BinOp::And, a, e.constant((d - 1) as i64)),
+                    });
+                }
+                if !self.mul_high {
+                    return None;
+                }
+                let q = divide_by_constant(e, a, d, width);
+                if op == BinOp::Div {
+                    return Some(q);
+                }
+                let q = e.emit(q);
+                let d = e.constant(d as i64);
+                let p = e.binop(BinOp::Mul, q, d);
+                Some(Operation::BinOp(BinOp::Sub, a, p))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Returns the unsigned division of `n` by `d`, which must be larger than 1
+/// and not a power of two, after emitting its operands, as in Granlund and
+/// Montgomery, "Division by Invariant Integers using Multiplication", figure
+/// 4.1:
+///
+/// ```text
+/// t1 = mulhi(m, n)
+/// q = (t1 + ((n - t1) >> 1)) >> (l - 1)
+/// ```
+fn divide_by_constant(e: &mut Emitter, n: ValueId, d: u64, width: usize) -> Operation {
+    let l = 64 - (d - 1).leading_zeros() as usize;
+    let m = ((1u128 << width) * ((1u128 << l) - d as u128) / d as u128 + 1) as u64;
+    let m = e.constant(m as i64);
+    let t1 = e.binop(BinOp::MulHi, n, m);
+    let diff = e.binop(BinOp::Sub, n, t1);
+    let one = e.constant(1);
+    let half = e.binop(BinOp::Shr, diff, one);
+    let sum = e.binop(BinOp::Add, t1, half);
+    let shift = e.constant(l as i64 - 1);
+    Operation::BinOp(BinOp::Shr, sum, shift)
+}
+
+fn log2(c: u128) -> Option<i64> {
+    c.is_power_of_two().then(|| c.trailing_zeros() as i64)
+}
+
+/// Inserts instructions of type `ty` before `pos` in `block`, moving `pos`
+/// along.
+struct Emitter<'a> {
+    func: &'a mut Function,
+    block: BlockId,
+    pos: usize,
+    ty: Type,
+}
+
+impl Emitter<'_> {
+    fn emit(&mut self, operation: Operation) -> ValueId {
+        let val = self.func.push_value(self.ty.clone());
+        self.func.values[val.0].owner = self.block;
+        self.func.blocks[self.block.0].instructions.insert(
+            self.pos,
+            Instruction {
+                yielded: Some(val),
+                operation,
+            },
+        );
+        self.pos += 1;
+        val
+    }
+
+    /// Emits `c` wrapped to `ty`, like the result of an operation computing it.
+    fn constant(&mut self, c: i64) -> ValueId {
+        let c = self.ty.normalize(c);
+        self.emit(Operation::Integer(c))
+    }
+
+    fn binop(&mut self, op: BinOp, a: ValueId, b: ValueId) -> ValueId {
+        self.emit(Operation::BinOp(op, a, b))
+    }
+}
+
+/// Replaces `i * c` in a loop, where `i` is an induction variable going up by
+/// a constant step, with a new induction variable starting at `init * c` and
+/// going up by `step * c`.
+pub struct IvStrengthReduction;
+
+impl FunctionPass for IvStrengthReduction {
+    fn run_on_function(&mut self, func: &mut Function) {
+        if func.blocks.is_empty() {
+            return;
+        }
+        let info = simplify_loops(func);
+        let mut replacements = HashMap::new();
+        for lp in info.loops() {
+            let (Some(preheader), &[latch]) = (lp.preheader(func), lp.latches()) else {
+                continue;
+            };
+            let constants = func.constants();
+            let header = lp.header();
+
+            // i = phi [preheader, init], [latch, next] with next = i + step
+            let mut ivs = Vec::new();
+            for instr in func.blocks[header.0].instructions.iter() {
+                let Operation::Phi(ref vals) = instr.operation else {
+                    continue;
+                };
+                let incoming = |b: BlockId| vals.iter().find(|(p, _)| *p == b).map(|(_, v)| *v);
+                let (Some(init), Some(next)) = (incoming(preheader), incoming(latch)) else {
+                    continue;
+                };
+                let iv = instr.yielded.unwrap();
+                let step = func
+                    .blocks
+                    .iter()
+                    .filter(|b| lp.contains(BlockId(b.id)))
+                    .flat_map(|b| b.instructions.iter())
+                    .find(|i| i.yielded == Some(next))
+                    .and_then(|i| match i.operation {
+                        Operation::BinOp(BinOp::Add, x, s) if x == iv => constants.get(&s),
+                        Operation::BinOp(BinOp::Add, s, x) if x == iv => constants.get(&s),
+                        _ => None,
+                    });
+                if let Some(step) = step {
+                    ivs.push((iv, init, *step));
+                }
+            }
+
+            // j = i * c inside the loop
+            let mut derived = Vec::new();
+            for &b in lp.blocks() {
+                for instr in func.blocks[b.0].instructions.iter() {
+                    let Operation::BinOp(BinOp::Mul, x, y) = instr.operation else {
+                        continue;
+                    };
+                    for (iv, init, step) in ivs.iter() {
+                        let c = match (x == *iv, y == *iv) {
+                            (true, _) => constants.get(&y),
+                            (_, true) => constants.get(&x),
+                            _ => None,
+                        };
+                        if let Some(c) = c {
+                            derived.push((instr.yielded.unwrap(), *init, *step, *c));
+                        }
+                    }
+                }
+            }
+
+            for (j, init, step, c) in derived {
+                let ty = func.values[j.0].ty.clone();
+                let mut e = Emitter {
+                    pos: func.blocks[preheader.0].instructions.len(),
+                    func,
+                    block: preheader,
+                    ty: ty.clone(),
+                };
+                let factor = e.constant(c);
+                let start = e.binop(BinOp::Mul, init, factor);
+
+                let new_iv = func.push_value(ty.clone());
+                let mut e = Emitter {
+                    pos: func.blocks[latch.0].instructions.len(),
+                    func,
+                    block: latch,
+                    ty,
+                };
+                let scaled = e.constant(step.wrapping_mul(c));
+                let next = e.binop(BinOp::Add, new_iv, scaled);
+
+                let vals = func.blocks[header.0]
+                    .preds
+                    .iter()
+                    .map(|p| (*p, if *p == latch { next } else { start }))
+                    .collect();
+                func.values[new_iv.0].owner = header;
+                func.blocks[header.0].instructions.insert(
+                    0,
+                    Instruction {
+                        yielded: Some(new_iv),
+                        operation: Operation::Phi(vals),
+                    },
+                );
+                replacements.insert(j, new_iv);
+            }
+        }
+        // the multiplications are left for DCE
+        func.replace_uses(&replacements);
+    }
+
+    fn requires(&self) -> Vec<Algo> {
+        vec![Algo::PhiLowering]
+    }
+
+    fn conflicts(&self) -> Vec<Algo> {
+        vec![Algo::PhiRemoval]
+    }
+}
@@ -0,0 +1,219 @@
+//! Runs a pipeline of `OptPass`es on a module.
+//!
+//! The properties of a module are the `Algo`s in `Module::algos_run`: a pass
+//! lists the ones it needs in `OptPass::requires`, and the ones it breaks in
+//! `OptPass::invalidates`. Before running a pass, the manager runs the
+//! mandatory transform establishing every missing requirement, so a pipeline
+//! only has to list the passes it actually wants.
+
+use std::fmt::Display;
+
+use crate::{
+    ir::{self, Algo, Module, VerifyError},
+    vcode::InstrSelector,
+};
+
+use super::{
+    lower_aggregates::LowerAggregates,
+    lower_to_ssa::LowerToSsa,
+    opt::{
+        constant_folding::ConstantFolding,
+        dce::{AggressiveDce, DeadCodeElimination},
+        gvn::Gvn,
+        inline::Inline,
+        instcombine::InstCombine,
+        licm::Licm,
+        mem2reg::Mem2Reg,
+        sccp::Sccp,
+        strength_reduction::{IvStrengthReduction, StrengthReduction},
+        ForEachFunction, FunctionPass, OptPass,
+    },
+    phi_removal::RemovePhis,
+    remove_critical_edges::RemoveCriticalEdges,
+    simplify_cfg::SimplifyCfg,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// Only the mandatory transforms.
+    O0,
+    O1,
+    O2,
+}
+
+#[derive(Debug, Clone)]
+pub enum PassError {
+    /// `pass` requires `property`, which can't be established at that point
+    Unsatisfiable { pass: &'static str, property: Algo },
+    /// `pass` can't run once `property` holds
+    Conflict { pass: &'static str, property: Algo },
+    /// the module didn't verify after `pass`
+    Verify {
+        pass: &'static str,
+        errors: Vec<VerifyError>,
+    },
+}
+
+impl Display for PassError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PassError::Unsatisfiable { pass, property } => {
+                write!(
+                    f,
+                    "{pass} requires {property:?}, which can't be established"
+                )
+            }
+            PassError::Conflict { pass, property } => {
+                write!(f, "{pass} can't run on a module with {property:?}")
+            }
+            PassError::Verify { pass, errors } => {
+                write!(f, "module is invalid after {pass}:")?;
+                for e in errors {
+                    write!(f, "\n  {e}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for PassError {}
+
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<Box<dyn OptPass>>,
+    verify: bool,
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes the pipeline for `level`. It ends with `RemovePhis`, so the module
+    /// can be lowered to vcode afterwards.
+    ///
+    /// The pipeline may introduce any operation, like the selectors of this
+    /// crate support; `for_target` leaves out those a selector can't handle.
+    pub fn with_opt_level(level: OptLevel) -> Self {
+        Self::pipeline(level, true)
+    }
+
+    /// Makes the pipeline for `level`, only introducing operations that `S`
+    /// selects.
+    pub fn for_target<S: InstrSelector>(level: OptLevel) -> Self {
+        Self::pipeline(level, S::SUPPORTS_MUL_HIGH)
+    }
+
+    fn pipeline(level: OptLevel, mul_high: bool) -> Self {
+        let mut pm = Self::new();
+        match level {
+            OptLevel::O0 => {}
+            OptLevel::O1 => {
+                pm.add_function_pass(Mem2Reg)
+                    .add_pass(ConstantFolding)
+                    .add_pass(InstCombine)
+                    .add_function_pass(DeadCodeElimination)
+                    .add_function_pass(SimplifyCfg);
+            }
+            OptLevel::O2 => {
+                pm.add_pass(Inline::default())
+                    .add_function_pass(Mem2Reg)
+                    .add_function_pass(Sccp)
+                    .add_pass(InstCombine)
+                    .add_function_pass(Gvn)
+                    .add_function_pass(IvStrengthReduction)
+                    .add_function_pass(Licm)
+                    .add_function_pass(StrengthReduction { mul_high })
+                    .add_function_pass(AggressiveDce)
+                    .add_function_pass(SimplifyCfg);
+            }
+        }
+        pm.add_pass(RemovePhis);
+        pm
+    }
+
+    pub fn add_pass(&mut self, pass: impl OptPass + 'static) -> &mut Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    /// Adds a pass that is run on every function of the module in turn.
+    pub fn add_function_pass(&mut self, pass: impl FunctionPass + 'static) -> &mut Self {
+        self.add_pass(ForEachFunction(pass))
+    }
+
+    /// Verifies the module after every pass, including the inserted ones.
+    pub fn set_verify(&mut self, verify: bool) -> &mut Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Returns the names of the registered passes, in order.
+    pub fn passes(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|p| p.name()).collect()
+    }
+
+    pub fn run(&mut self, module: &mut Module) -> Result<(), PassError> {
+        for pass in self.passes.iter_mut() {
+            prepare(module, pass.as_ref(), self.verify)?;
+            run_pass(module, pass.as_mut(), self.verify)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the mandatory transform establishing `property`.
+fn provider(property: Algo) -> Option<Box<dyn OptPass>> {
+    match property {
+        Algo::CriticalEdgeSplitting => Some(Box::new(RemoveCriticalEdges)),
+        Algo::PhiLowering => Some(Box::new(LowerToSsa)),
+        Algo::PhiRemoval => Some(Box::new(RemovePhis)),
+        Algo::LowerParMoves => None,
+        Algo::AggregateLowering => Some(Box::new(LowerAggregates)),
+    }
+}
+
+/// Runs whatever is needed for the requirements of `pass` to hold.
+fn prepare(module: &mut Module, pass: &dyn OptPass, verify: bool) -> Result<(), PassError> {
+    for property in pass.conflicts() {
+        if module.algos_run.contains(&property) {
+            return Err(PassError::Conflict {
+                pass: pass.name(),
+                property,
+            });
+        }
+    }
+    for property in pass.requires() {
+        if module.algos_run.contains(&property) {
+            continue;
+        }
+        let unsatisfiable = PassError::Unsatisfiable {
+            pass: pass.name(),
+            property,
+        };
+        let Some(mut provider) = provider(property) else {
+            return Err(unsatisfiable);
+        };
+        match prepare(module, provider.as_ref(), verify) {
+            Ok(()) => {}
+            Err(e @ PassError::Verify { .. }) => return Err(e),
+            Err(_) => return Err(unsatisfiable),
+        }
+        run_pass(module, provider.as_mut(), verify)?;
+    }
+    Ok(())
+}
+
+fn run_pass(module: &mut Module, pass: &mut dyn OptPass, verify: bool) -> Result<(), PassError> {
+    pass.run(module);
+    let invalidated = pass.invalidates();
+    module.algos_run.retain(|a| !invalidated.contains(a));
+    if verify {
+        ir::verify(module).map_err(|errors| PassError::Verify {
+            pass: pass.name(),
+            errors,
+        })?;
+    }
+    Ok(())
+}
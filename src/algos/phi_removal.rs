@@ -1,8 +1,25 @@
 use crate::{
-    algos::delete_instructions::delete,
+    algos::{delete_instructions::delete, opt::OptPass},
     ir::{Algo, Module, Operation},
 };
 
+/// `remove_phis` as a pass, establishing `Algo::PhiRemoval`.
+pub struct RemovePhis;
+
+impl OptPass for RemovePhis {
+    fn run(&mut self, module: &mut Module) {
+        remove_phis(module);
+    }
+
+    fn requires(&self) -> Vec<Algo> {
+        vec![
+            Algo::CriticalEdgeSplitting,
+            Algo::PhiLowering,
+            Algo::AggregateLowering,
+        ]
+    }
+}
+
 pub fn remove_phis(module: &mut Module) {
     // Modules without critical edge splitting will have program semantics changed if phis are removed
     assert!(module.algos_run.contains(&Algo::CriticalEdgeSplitting));
@@ -18,11 +35,12 @@ pub fn remove_phis(module: &mut Module) {
                 func_dels[func_id][block_id].push(false);
                 match &instr.operation {
                     Operation::Phi(defs) => {
-                        for val in defs {
-                            let target_block = func.values[val.0].owner;
-                            func.blocks[target_block.0]
+                        // with critical edges split, the end of the
+                        // predecessor is only reached through this edge
+                        for (pred, val) in defs {
+                            func.blocks[pred.0]
                                 .par_moves
-                                .push((instr.yielded.unwrap(), val.clone()))
+                                .push((instr.yielded.unwrap(), *val))
                         }
                         func_dels[func_id][block_id][instr_id] = true;
                     }
@@ -34,7 +52,7 @@ pub fn remove_phis(module: &mut Module) {
 
     delete(module, &func_dels);
 
-    for (_, func) in module.functions.iter_mut().enumerate() {
+    for func in module.functions.iter_mut() {
         for (bi, block) in func.blocks.iter_mut().enumerate() {
             for m in super::par_move::parallel_move(&mut block.par_moves, &mut |a, _| {
                 func.values.push(crate::ir::Value {
@@ -47,7 +65,7 @@ pub fn remove_phis(module: &mut Module) {
             }) {
                 block.instructions.push(crate::ir::Instruction {
                     yielded: Some(m.0),
-                    operation: Operation::BinOp(crate::ir::BinOp::And, m.1, m.1),
+                    operation: Operation::Copy(m.1),
                 });
             }
         }
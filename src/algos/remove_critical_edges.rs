@@ -1,4 +1,15 @@
-use crate::ir::{Algo, BasicBlock, BlockId, Module, Terminator};
+use crate::ir::{Algo, BasicBlock, BlockId, Module, Operation, Terminator};
+
+use super::opt::OptPass;
+
+/// `remove_critical_edges` as a pass, establishing `Algo::CriticalEdgeSplitting`.
+pub struct RemoveCriticalEdges;
+
+impl OptPass for RemoveCriticalEdges {
+    fn run(&mut self, module: &mut Module) {
+        remove_critical_edges(module);
+    }
+}
 
 pub fn remove_critical_edges(module: &mut Module) {
     module.algos_run.push(Algo::CriticalEdgeSplitting);
@@ -20,6 +31,7 @@ pub fn remove_critical_edges(module: &mut Module) {
                         .iter_mut()
                         .find(|x| **x == BlockId(block.id))
                         .unwrap() = BlockId(bb.id);
+                    redirect_phis(&mut func.blocks[bb_1.0], BlockId(block.id), BlockId(bb.id));
                     if let Terminator::Branch(_, ref mut bb, _) = func.blocks[id].terminator {
                         *bb = BlockId(blocks.len() + to_insert.len())
                     };
@@ -38,6 +50,7 @@ pub fn remove_critical_edges(module: &mut Module) {
                         .iter_mut()
                         .find(|x| **x == BlockId(block.id))
                         .unwrap() = BlockId(bb.id);
+                    redirect_phis(&mut func.blocks[bb_2.0], BlockId(block.id), BlockId(bb.id));
                     if let Terminator::Branch(_, _, ref mut bb) = func.blocks[id].terminator {
                         *bb = BlockId(blocks.len() + to_insert.len())
                     };
@@ -48,3 +61,15 @@ pub fn remove_critical_edges(module: &mut Module) {
         func.blocks.append(&mut to_insert)
     }
 }
+
+/// Makes the phis of `block` take the operand flowing in from `from` from `to`
+/// instead.
+fn redirect_phis(block: &mut BasicBlock, from: BlockId, to: BlockId) {
+    for instr in block.instructions.iter_mut() {
+        if let Operation::Phi(ref mut vals) = instr.operation {
+            if let Some(val) = vals.iter_mut().find(|(b, _)| *b == from) {
+                val.0 = to;
+            }
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Control flow graph simplification.
+//!
+//! Repeats the following until nothing changes, removing the unreachable
+//! blocks (and renumbering the remaining ones) in between:
+//! - branches on constants, or to the same block twice, become jumps,
+//! - a jump to a block that only branches on a Φ is threaded to the target
+//!   the Φ's incoming constant selects,
+//! - empty blocks that only jump on (and have no `par_moves`) are bypassed,
+//! - a block with a single predecessor ending in a jump to it is merged into
+//!   that predecessor.
+//!
+//! Bypassing blocks can create critical edges again.
+
+use std::collections::HashMap;
+
+use super::opt::{dce::remove_unreachable_blocks, FunctionPass};
+use crate::ir::{Algo, BlockId, Function, Operation, Terminator, ValueId};
+
+/// `simplify_cfg` as a pass.
+pub struct SimplifyCfg;
+
+impl FunctionPass for SimplifyCfg {
+    fn run_on_function(&mut self, func: &mut Function) {
+        simplify_cfg(func);
+    }
+
+    fn invalidates(&self) -> Vec<Algo> {
+        vec![Algo::CriticalEdgeSplitting]
+    }
+}
+
+pub fn simplify_cfg(func: &mut Function) {
+    if func.blocks.is_empty() {
+        return;
+    }
+    loop {
+        let mut changed = fold_branches(func);
+        changed |= thread_jumps(func);
+        changed |= bypass_forwarders(func);
+        // unreachable predecessors would keep blocks from being merged
+        let len = func.blocks.len();
+        remove_unreachable_blocks(func);
+        changed |= func.blocks.len() != len;
+        changed |= merge_blocks(func);
+        if !changed {
+            break;
+        }
+    }
+    func.rebuild_children();
+}
+
+/// Removes one edge from `from` to `to`, with its Φ operands.
+fn remove_edge(func: &mut Function, from: BlockId, to: BlockId) {
+    let target = &mut func.blocks[to.0];
+    if let Some(pos) = target.preds.iter().position(|p| *p == from) {
+        target.preds.remove(pos);
+    }
+    for instr in target.instructions.iter_mut() {
+        if let Operation::Phi(ref mut vals) = instr.operation {
+            if let Some(pos) = vals.iter().position(|(p, _)| *p == from) {
+                vals.remove(pos);
+            }
+        }
+    }
+}
+
+/// Adds an edge from `from` to `to`, the Φs of `to` taking the operand they
+/// take from `like` on it.
+fn add_edge(func: &mut Function, from: BlockId, to: BlockId, like: BlockId) {
+    let target = &mut func.blocks[to.0];
+    target.preds.push(from);
+    for instr in target.instructions.iter_mut() {
+        if let Operation::Phi(ref mut vals) = instr.operation {
+            let (_, val) = *vals.iter().find(|(p, _)| *p == like).unwrap();
+            vals.push((from, val));
+        }
+    }
+}
+
+fn has_phis(func: &Function, block: BlockId) -> bool {
+    func.blocks[block.0]
+        .instructions
+        .first()
+        .is_some_and(|i| matches!(i.operation, Operation::Phi(_)))
+}
+
+fn fold_branches(func: &mut Function) -> bool {
+    let constants = func.constants();
+    let mut changed = false;
+    for bi in 0..func.blocks.len() {
+        let Terminator::Branch(cond, t, f) = func.blocks[bi].terminator else {
+            continue;
+        };
+        let (taken, not_taken) = match constants.get(&cond) {
+            Some(&c) if c != 0 => (t, f),
+            Some(_) => (f, t),
+            None if t == f => (t, f),
+            None => continue,
+        };
+        func.blocks[bi].terminator = Terminator::Jump(taken);
+        remove_edge(func, BlockId(bi), not_taken);
+        changed = true;
+    }
+    changed
+}
+
+/// Returns how often `val` is used, terminators and `par_moves` included.
+fn use_count(func: &Function, val: ValueId) -> usize {
+    let mut count = 0;
+    for block in func.blocks.iter() {
+        for instr in block.instructions.iter() {
+            count += instr.operation.operands().iter().filter(|v| **v == val).count();
+        }
+        count += block.par_moves.iter().filter(|(_, src)| *src == val).count();
+        match block.terminator {
+            Terminator::Return(v) | Terminator::Branch(v, ..) if v == val => count += 1,
+            _ => {}
+        }
+    }
+    count
+}
+
+/// Redirects the jumps to blocks consisting of `%c = phi ...` and `br %c`
+/// from predecessors for which `%c` is a constant.
+fn thread_jumps(func: &mut Function) -> bool {
+    let constants = func.constants();
+    let mut changed = false;
+    for bi in 0..func.blocks.len() {
+        let block = &func.blocks[bi];
+        let Terminator::Branch(cond, t, f) = block.terminator else {
+            continue;
+        };
+        let [instr] = block.instructions.as_slice() else {
+            continue;
+        };
+        let Operation::Phi(ref vals) = instr.operation else {
+            continue;
+        };
+        if instr.yielded != Some(cond) || use_count(func, cond) != 1 {
+            continue;
+        }
+        let threaded: Vec<(BlockId, BlockId)> = vals
+            .iter()
+            .filter(|(p, _)| func.blocks[p.0].terminator == Terminator::Jump(BlockId(bi)))
+            .filter_map(|(p, v)| {
+                let target = if *constants.get(v)? != 0 { t } else { f };
+                Some((*p, target))
+            })
+            .filter(|(p, target)| target.0 != bi && !func.blocks[target.0].preds.contains(p))
+            .collect();
+        for (pred, target) in threaded {
+            func.blocks[pred.0].terminator = Terminator::Jump(target);
+            add_edge(func, pred, target, BlockId(bi));
+            remove_edge(func, pred, BlockId(bi));
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Makes the predecessors of empty blocks ending in a jump jump to its target
+/// directly.
+fn bypass_forwarders(func: &mut Function) -> bool {
+    let mut changed = false;
+    // the entry block can't be bypassed, as it is entered from nowhere
+    for bi in 1..func.blocks.len() {
+        let block = &func.blocks[bi];
+        let Terminator::Jump(target) = block.terminator else {
+            continue;
+        };
+        if target.0 == bi
+            || !block.instructions.is_empty()
+            || block.par_moves.iter().any(|(dst, src)| dst != src)
+        {
+            continue;
+        }
+        let mut preds = block.preds.clone();
+        preds.sort_by_key(|p| p.0);
+        preds.dedup();
+        for pred in preds {
+            // a Φ in the target can't tell the two edges from `pred` apart
+            if has_phis(func, target) && func.blocks[target.0].preds.contains(&pred) {
+                continue;
+            }
+            let mut edges = 0;
+            match func.blocks[pred.0].terminator {
+                Terminator::Jump(ref mut b) => {
+                    *b = target;
+                    edges = 1;
+                }
+                Terminator::Branch(_, ref mut t, ref mut f) => {
+                    for b in [t, f] {
+                        if b.0 == bi {
+                            *b = target;
+                            edges += 1;
+                        }
+                    }
+                }
+                Terminator::Return(_) | Terminator::NoTerm => {}
+            }
+            for _ in 0..edges {
+                add_edge(func, pred, target, BlockId(bi));
+                remove_edge(func, pred, BlockId(bi));
+            }
+            changed = true;
+        }
+        if func.blocks[bi].preds.is_empty() {
+            remove_edge(func, BlockId(bi), target);
+            func.blocks[bi].terminator = Terminator::NoTerm;
+        }
+    }
+    changed
+}
+
+/// Appends blocks with a single predecessor ending in a jump to them to that
+/// predecessor.
+fn merge_blocks(func: &mut Function) -> bool {
+    let mut changed = false;
+    for bi in 0..func.blocks.len() {
+        while let Terminator::Jump(next) = func.blocks[bi].terminator {
+            // the moves of the predecessor happen after its instructions
+            if next.0 == 0
+                || next.0 == bi
+                || func.blocks[next.0].preds != [BlockId(bi)]
+                || func.blocks[bi].par_moves.iter().any(|(dst, src)| dst != src)
+            {
+                break;
+            }
+            let mut merged = std::mem::take(&mut func.blocks[next.0].instructions);
+            let mut replaced = HashMap::new();
+            merged.retain(|instr| match instr.operation {
+                Operation::Phi(ref vals) => {
+                    replaced.insert(instr.yielded.unwrap(), vals[0].1);
+                    false
+                }
+                _ => true,
+            });
+            for instr in merged.iter() {
+                if let Some(val) = instr.yielded {
+                    func.values[val.0].owner = BlockId(bi);
+                }
+            }
+            let next_block = &mut func.blocks[next.0];
+            let terminator = std::mem::replace(&mut next_block.terminator, Terminator::NoTerm);
+            let par_moves = std::mem::take(&mut next_block.par_moves);
+            next_block.preds.clear();
+
+            for succ in terminator.successors() {
+                let succ = &mut func.blocks[succ.0];
+                for p in succ.preds.iter_mut().filter(|p| **p == next) {
+                    *p = BlockId(bi);
+                }
+                for instr in succ.instructions.iter_mut() {
+                    if let Operation::Phi(ref mut vals) = instr.operation {
+                        for (p, _) in vals.iter_mut().filter(|(p, _)| *p == next) {
+                            *p = BlockId(bi);
+                        }
+                    }
+                }
+            }
+            for var in func.variables.iter_mut() {
+                if var.bbs_assign_to.contains(&next) {
+                    var.bbs_assign_to.insert(BlockId(bi));
+                }
+            }
+            let block = &mut func.blocks[bi];
+            block.instructions.append(&mut merged);
+            block.par_moves = par_moves;
+            block.terminator = terminator;
+            if !replaced.is_empty() {
+                func.replace_uses(&replaced);
+            }
+            changed = true;
+        }
+    }
+    changed
+}
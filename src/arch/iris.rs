@@ -2,8 +2,10 @@
 //! - `R1`: return value
 //! - `R1` - `R8`: arguments / caller save
 //! - `R9` - `R25`: scratch register / caller save
+//! - `R26`: stack pointer, pointing at the bottom of the frame of the current
+//!   function; the stack grows down
 
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display};
 
 use crate::{
     algos::par_move::parallel_move,
@@ -12,6 +14,11 @@ use crate::{
     vcode::*,
 };
 
+/// The width of the registers, in bits.
+pub const IRIS_WORD_BITS: usize = 16;
+
+pub const IRIS_DATA_LAYOUT: DataLayout = DataLayout::new(IRIS_WORD_BITS, 1);
+
 pub const IRIS_REG_ZR: usize = 0;
 pub const IRIS_REG_1: usize = 1;
 pub const IRIS_REG_2: usize = 2;
@@ -40,6 +47,8 @@ pub const IRIS_REG_24: usize = 24;
 pub const IRIS_REG_25: usize = 25;
 pub const IRIS_REG_26: usize = 26;
 
+pub const IRIS_REG_SP: usize = IRIS_REG_26;
+
 pub const IRIS_REG_ARGS: &[usize] = &[
     IRIS_REG_1, IRIS_REG_2, IRIS_REG_3, IRIS_REG_4, IRIS_REG_5, IRIS_REG_6, IRIS_REG_7, IRIS_REG_8,
 ];
@@ -85,6 +94,11 @@ pub enum IrisInstr {
         src1: VReg,
         src2: VReg,
     },
+    UnaryOp {
+        op: IrisAluOp,
+        dst: VReg,
+        src: VReg,
+    },
     Jmp {
         dst: LabelDest,
     },
@@ -96,10 +110,23 @@ pub enum IrisInstr {
         dst: VReg,
         val: i64,
     },
+    /// `imm` of the address of a label
+    Addr {
+        dst: VReg,
+        label: LabelDest,
+    },
     Mov {
         dst: VReg,
         src: VReg,
     },
+    Lod {
+        dst: VReg,
+        ptr: VReg,
+    },
+    Str {
+        ptr: VReg,
+        src: VReg,
+    },
     Cal {
         dst: LabelDest,
     },
@@ -117,43 +144,60 @@ pub enum IrisAluOp {
     Add,
     Sub,
     Mul,
+    Umlt,
     Div,
+    Sdiv,
     Mod,
     And,
     Or,
     Xor,
     Not,
     Neg,
-    Rsh,
-    Lsh,
-    Ssete,
-    Ssetne,
+    Bsl,
+    Bsr,
+    Bss,
+    Sete,
+    Setne,
+    Setl,
+    Setle,
+    Setg,
+    Setge,
     Ssetl,
     Ssetle,
     Ssetg,
     Ssetge,
 }
 
-impl From<BinOp> for IrisAluOp {
-    fn from(op: BinOp) -> Self {
-        match op {
-            BinOp::Add => IrisAluOp::Add,
-            BinOp::Sub => IrisAluOp::Sub,
-            BinOp::Mul => IrisAluOp::Mul,
-            BinOp::Div => IrisAluOp::Div,
-            BinOp::Mod => IrisAluOp::Mod,
-            BinOp::And => IrisAluOp::And,
-            BinOp::Or => IrisAluOp::Or,
-            BinOp::Xor => IrisAluOp::Xor,
-            BinOp::Eq => IrisAluOp::Ssete,
-            BinOp::Ne => IrisAluOp::Ssetne,
-            BinOp::Lt => IrisAluOp::Ssetl,
-            BinOp::Le => IrisAluOp::Ssetle,
-            BinOp::Gt => IrisAluOp::Ssetg,
-            BinOp::Ge => IrisAluOp::Ssetge,
-            BinOp::Shl => IrisAluOp::Lsh,
-            BinOp::Shr => IrisAluOp::Rsh,
-        }
+impl IrisAluOp {
+    /// Returns the instruction computing `op` on operands of the given
+    /// signedness. Signed `Mod` has no instruction and returns `None`.
+    pub fn from_binop(op: BinOp, signed: bool) -> Option<Self> {
+        Some(match (op, signed) {
+            (BinOp::Add, _) => IrisAluOp::Add,
+            (BinOp::Sub, _) => IrisAluOp::Sub,
+            (BinOp::Mul, _) => IrisAluOp::Mul,
+            (BinOp::MulHi, _) => IrisAluOp::Umlt,
+            (BinOp::Div, false) => IrisAluOp::Div,
+            (BinOp::Div, true) => IrisAluOp::Sdiv,
+            (BinOp::Mod, false) => IrisAluOp::Mod,
+            (BinOp::Mod, true) => return None,
+            (BinOp::And, _) => IrisAluOp::And,
+            (BinOp::Or, _) => IrisAluOp::Or,
+            (BinOp::Xor, _) => IrisAluOp::Xor,
+            (BinOp::Eq, _) => IrisAluOp::Sete,
+            (BinOp::Ne, _) => IrisAluOp::Setne,
+            (BinOp::Lt, false) => IrisAluOp::Setl,
+            (BinOp::Le, false) => IrisAluOp::Setle,
+            (BinOp::Gt, false) => IrisAluOp::Setg,
+            (BinOp::Ge, false) => IrisAluOp::Setge,
+            (BinOp::Lt, true) => IrisAluOp::Ssetl,
+            (BinOp::Le, true) => IrisAluOp::Ssetle,
+            (BinOp::Gt, true) => IrisAluOp::Ssetg,
+            (BinOp::Ge, true) => IrisAluOp::Ssetge,
+            (BinOp::Shl, _) => IrisAluOp::Bsl,
+            (BinOp::Shr, false) => IrisAluOp::Bsr,
+            (BinOp::Shr, true) => IrisAluOp::Bss,
+        })
     }
 }
 
@@ -171,10 +215,14 @@ impl VCodeInstr for IrisInstr {
                 regalloc.add_use(*src1);
                 regalloc.add_use(*src2);
             }
+            Self::UnaryOp { dst, src, .. } => {
+                regalloc.add_def(*dst);
+                regalloc.add_use(*src);
+            }
             Self::Beq { cond, .. } => {
                 regalloc.add_use(*cond);
             }
-            Self::Imm { dst, .. } => {
+            Self::Imm { dst, .. } | Self::Addr { dst, .. } => {
                 regalloc.add_def(*dst);
             }
             Self::Mov { dst, src } => {
@@ -182,6 +230,14 @@ impl VCodeInstr for IrisInstr {
                 regalloc.add_use(*src);
                 regalloc.coalesce_move(*src, *dst);
             }
+            Self::Lod { dst, ptr } => {
+                regalloc.add_def(*dst);
+                regalloc.add_use(*ptr);
+            }
+            Self::Str { ptr, src } => {
+                regalloc.add_use(*ptr);
+                regalloc.add_use(*src);
+            }
             Self::HPsh { val } => {
                 regalloc.add_use(*val);
             }
@@ -201,16 +257,28 @@ impl VCodeInstr for IrisInstr {
                 apply_alloc(src1, allocs);
                 apply_alloc(src2, allocs);
             }
+            Self::UnaryOp { dst, src, .. } => {
+                apply_alloc(dst, allocs);
+                apply_alloc(src, allocs);
+            }
             Self::Beq { cond, .. } => {
                 apply_alloc(cond, allocs);
             }
-            Self::Imm { dst, .. } => {
+            Self::Imm { dst, .. } | Self::Addr { dst, .. } => {
                 apply_alloc(dst, allocs);
             }
             Self::Mov { dst, src } => {
                 apply_alloc(dst, allocs);
                 apply_alloc(src, allocs);
             }
+            Self::Lod { dst, ptr } => {
+                apply_alloc(dst, allocs);
+                apply_alloc(ptr, allocs);
+            }
+            Self::Str { ptr, src } => {
+                apply_alloc(ptr, allocs);
+                apply_alloc(src, allocs);
+            }
             Self::HPsh { val } => {
                 apply_alloc(val, allocs);
             }
@@ -230,32 +298,13 @@ impl VCodeInstr for IrisInstr {
                 b.instrs.clear();
 
                 for i in b_instrs.into_iter() {
-                    match i {
-                        _ => b.instrs.push(i),
-                    }
+                    b.instrs.push(i);
                 }
             }
         }
     }
 
     fn emit_assembly<T: std::io::Write>(w: &mut T, vcode: &VCode<Self>) -> std::io::Result<()> {
-        fn mangle<I: VCodeInstr>(vcode: &VCode<I>, f: &VCodeFunction<I>, l: &LabelDest) -> String {
-            fn mangle_string(s: &str) -> String {
-                use std::hash::*;
-                let mut h = DefaultHasher::new();
-                s.hash(&mut h);
-                format!("{s}_{:16x}", h.finish())
-            }
-
-            match l {
-                LabelDest::Block(li) => mangle_string(&format!(".__fn_{}{}_L{}", f.name, f.arg_count, li.0)),
-                LabelDest::Function(fi) => match vcode.functions[fi.0].linkage {
-                    Linkage::Private => format!(".{}", mangle_string(&vcode.functions[fi.0].name)),
-                    _ => format!(".{}", vcode.functions[fi.0].name.clone()),
-                },
-            }
-        }
-
         writeln!(w, "cal .main")?;
         writeln!(w, "hlt")?;
         writeln!(w)?;
@@ -265,17 +314,20 @@ impl VCodeInstr for IrisInstr {
                 continue;
             }
 
-            writeln!(w, "{}", mangle(vcode, &f, &LabelDest::Function(FunctionId(fi))))?;
+            writeln!(w, "{}", vcode.mangle(f, &LabelDest::Function(FunctionId(fi))))?;
             for (li, l) in f.instrs.iter().enumerate() {
                 if li != 0 {
-                    writeln!(w, "{}", mangle(vcode, &f, &LabelDest::Block(BlockId(li - 1))))?;
+                    writeln!(w, "{}", vcode.mangle(f, &LabelDest::Block(BlockId(li - 1))))?;
                 }
 
                 for i in l.instrs.iter() {
                     match i {
-                        IrisInstr::Jmp { dst } => writeln!(w, "jmp {}", mangle(vcode, &f, dst))?,
-                        IrisInstr::Beq { cond: src1, dst } => writeln!(w, "bnz {} {}", mangle(vcode, &f, dst), src1)?,
-                        IrisInstr::Cal { dst } => writeln!(w, "cal {}", mangle(vcode, &f, dst))?,
+                        IrisInstr::Jmp { dst } => writeln!(w, "jmp {}", vcode.mangle(f, dst))?,
+                        IrisInstr::Beq { cond: src1, dst } => writeln!(w, "bnz {} {}", vcode.mangle(f, dst), src1)?,
+                        IrisInstr::Cal { dst } => writeln!(w, "cal {}", vcode.mangle(f, dst))?,
+                        IrisInstr::Addr { dst, label } => {
+                            writeln!(w, "imm {} {}", dst, vcode.mangle(f, label))?
+                        }
                         _ => writeln!(w, "{i}")?,
                     }
                 }
@@ -284,6 +336,23 @@ impl VCodeInstr for IrisInstr {
             writeln!(w)?;
         }
 
+        let mut globals = vcode
+            .globals
+            .iter()
+            .filter(|g| !matches!(g.linkage, Linkage::External))
+            .peekable();
+        if globals.peek().is_some() {
+            writeln!(w, ".data")?;
+        }
+        for g in globals {
+            writeln!(w, "{}", symbol(&g.name, g.linkage))?;
+            let words = IRIS_DATA_LAYOUT.words(&g.ty, g.init.as_ref());
+            if !words.is_empty() {
+                let words: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+                writeln!(w, "dw {}", words.join(" "))?;
+            }
+        }
+
         Ok(())
     }
 }
@@ -299,10 +368,14 @@ impl Display for IrisInstr {
             } => {
                 write!(f, "{op} {dst} {src1} {src2}")
             }
+            IrisInstr::UnaryOp { op, dst, src } => write!(f, "{op} {dst} {src}"),
             IrisInstr::Jmp { dst } => write!(f, "jmp {dst}"),
             IrisInstr::Imm { dst, val } => write!(f, "imm {dst} {val}"),
+            IrisInstr::Addr { dst, label } => write!(f, "imm {dst} {label}"),
             IrisInstr::Beq { cond, dst } => write!(f, "bnz {dst} {cond}"),
             IrisInstr::Mov { dst, src } => write!(f, "mov {dst} {src}"),
+            IrisInstr::Lod { dst, ptr } => write!(f, "lod {dst} {ptr}"),
+            IrisInstr::Str { ptr, src } => write!(f, "str {ptr} {src}"),
             IrisInstr::Cal { dst } => write!(f, "cal {dst}"),
             IrisInstr::Ret => write!(f, "ret"),
             IrisInstr::PhiPlaceholder { dst, ops } => write!(
@@ -326,17 +399,24 @@ impl Display for IrisAluOp {
             IrisAluOp::Add => write!(f, "add"),
             IrisAluOp::Sub => write!(f, "sub"),
             IrisAluOp::Mul => write!(f, "mul"),
+            IrisAluOp::Umlt => write!(f, "umlt"),
             IrisAluOp::Div => write!(f, "div"),
+            IrisAluOp::Sdiv => write!(f, "sdiv"),
             IrisAluOp::Mod => write!(f, "mod"),
             IrisAluOp::And => write!(f, "and"),
             IrisAluOp::Or => write!(f, "or"),
             IrisAluOp::Xor => write!(f, "xor"),
             IrisAluOp::Not => write!(f, "not"),
             IrisAluOp::Neg => write!(f, "neg"),
-            IrisAluOp::Rsh => write!(f, "rsh"),
-            IrisAluOp::Lsh => write!(f, "lsh"),
-            IrisAluOp::Ssete => write!(f, "ssete"),
-            IrisAluOp::Ssetne => write!(f, "ssetne"),
+            IrisAluOp::Bsl => write!(f, "bsl"),
+            IrisAluOp::Bsr => write!(f, "bsr"),
+            IrisAluOp::Bss => write!(f, "bss"),
+            IrisAluOp::Sete => write!(f, "sete"),
+            IrisAluOp::Setne => write!(f, "setne"),
+            IrisAluOp::Setl => write!(f, "setl"),
+            IrisAluOp::Setle => write!(f, "setle"),
+            IrisAluOp::Setg => write!(f, "setg"),
+            IrisAluOp::Setge => write!(f, "setge"),
             IrisAluOp::Ssetl => write!(f, "ssetl"),
             IrisAluOp::Ssetle => write!(f, "ssetle"),
             IrisAluOp::Ssetg => write!(f, "ssetg"),
@@ -346,35 +426,205 @@ impl Display for IrisAluOp {
 }
 
 #[derive(Default)]
-pub struct IrisSelector;
+pub struct IrisSelector {
+    /// the types of the values of the current function
+    types: Vec<Type>,
+    /// the values of the current function known to be constant
+    constants: HashMap<ValueId, i64>,
+    frame: StackFrame,
+}
 
 impl InstrSelector for IrisSelector {
     type Instr = IrisInstr;
+    const SUPPORTS_MUL_HIGH: bool = true;
+    fn switch_to_function(&mut self, func: &Function) {
+        self.types = func.values.iter().map(|v| v.ty.clone()).collect();
+        self.constants = func.constants();
+        self.frame = StackFrame::new(func, &IRIS_DATA_LAYOUT);
+    }
+
     fn select(&mut self, gen: &mut VCodeGenerator<Self::Instr>, instr: &Instruction) {
         let dst = instr
             .yielded
             .map_or(VReg::Real(IRIS_REG_ZR), |val| self.get_vreg(val));
 
         match &instr.operation {
+            // umlt is the upper half of a word, not of the width of the type
+            Operation::BinOp(BinOp::MulHi, lhs, rhs) if self.width(*lhs) < IRIS_WORD_BITS => {
+                let width = self.width(*lhs);
+                let src1 = self.push_clean(gen, *lhs, false);
+                let src2 = self.push_clean(gen, *rhs, false);
+                self.push_mul_hi(gen, dst, src1, src2, width);
+            }
             Operation::BinOp(op, lhs, rhs) => {
-                let src1 = self.get_vreg(*lhs);
-                let src2 = self.get_vreg(*rhs);
+                let signed = self.types[lhs.0].is_signed();
+                // the bits above the width of the operands change the
+                // result of everything but the wrapping arithmetic
+                let (src1, src2) = match op {
+                    BinOp::Shl => (self.get_vreg(*lhs), self.push_clean(gen, *rhs, false)),
+                    BinOp::Shr => (
+                        self.push_clean(gen, *lhs, signed),
+                        self.push_clean(gen, *rhs, false),
+                    ),
+                    BinOp::Div | BinOp::Mod => (
+                        self.push_clean(gen, *lhs, signed),
+                        self.push_clean(gen, *rhs, signed),
+                    ),
+                    _ if op.is_comparison() => (
+                        self.push_clean(gen, *lhs, signed),
+                        self.push_clean(gen, *rhs, signed),
+                    ),
+                    _ => (self.get_vreg(*lhs), self.get_vreg(*rhs)),
+                };
+                match IrisAluOp::from_binop(*op, signed) {
+                    Some(op) => gen.push_instr(IrisInstr::AluOp {
+                        op,
+                        dst,
+                        src1,
+                        src2,
+                    }),
+                    // x - x / y * y
+                    None => {
+                        let quotient = gen.push_vreg();
+                        let product = gen.push_vreg();
+                        for (op, dst, src1, src2) in [
+                            (IrisAluOp::Sdiv, quotient, src1, src2),
+                            (IrisAluOp::Mul, product, quotient, src2),
+                            (IrisAluOp::Sub, dst, src1, product),
+                        ] {
+                            gen.push_instr(IrisInstr::AluOp {
+                                op,
+                                dst,
+                                src1,
+                                src2,
+                            });
+                        }
+                    }
+                }
+            }
+            Operation::UnOp(UnOp::LNot, src) => {
+                let src = self.push_clean(gen, *src, false);
                 gen.push_instr(IrisInstr::AluOp {
-                    op: (*op).into(),
+                    op: IrisAluOp::Sete,
+                    dst,
+                    src1: src,
+                    src2: VReg::Real(IRIS_REG_ZR),
+                });
+            }
+            Operation::UnOp(op, src) => {
+                gen.push_instr(IrisInstr::UnaryOp {
+                    op: if *op == UnOp::Neg { IrisAluOp::Neg } else { IrisAluOp::Not },
                     dst,
-                    src1,
-                    src2,
+                    src: self.get_vreg(*src),
                 });
             }
             Operation::Integer(val) => {
                 gen.push_instr(IrisInstr::Imm { dst, val: *val });
             }
+            // whatever is in the register will do
+            Operation::Undef => {}
+            Operation::Copy(src) => {
+                gen.push_instr(IrisInstr::Mov {
+                    dst,
+                    src: self.get_vreg(*src),
+                });
+            }
+            // the bits above the width of a value are ignored, so narrowing
+            // and reinterpreting it are free
+            Operation::Trunc(src) | Operation::PtrToInt(src) | Operation::IntToPtr(src) => {
+                gen.push_instr(IrisInstr::Mov {
+                    dst,
+                    src: self.get_vreg(*src),
+                });
+            }
+            Operation::ZExt(src) | Operation::SExt(src) => {
+                let width = self.width(*src);
+                let src = self.get_vreg(*src);
+                if width >= IRIS_WORD_BITS {
+                    gen.push_instr(IrisInstr::Mov { dst, src });
+                } else {
+                    let signed = matches!(instr.operation, Operation::SExt(_));
+                    self.push_extend(gen, dst, src, width, signed);
+                }
+            }
+            Operation::Alloca(_) => {
+                let offset = self.frame.offsets[&instr.yielded.unwrap()];
+                let offset = self.push_imm(gen, offset as i64);
+                gen.push_instr(IrisInstr::AluOp {
+                    op: IrisAluOp::Add,
+                    dst,
+                    src1: VReg::Real(IRIS_REG_SP),
+                    src2: offset,
+                });
+            }
+            Operation::ElementPtr(base, index, ty) => {
+                let base = self.get_vreg(*base);
+                let size = IRIS_DATA_LAYOUT.size_of(ty);
+                let offset = match self.constants.get(index) {
+                    Some(c) if c.wrapping_mul(size as i64) == 0 => None,
+                    Some(c) => Some(self.push_imm(gen, c.wrapping_mul(size as i64))),
+                    None if size == 0 => None,
+                    None => {
+                        let signed = self.types[index.0].is_signed();
+                        let index = self.push_clean(gen, *index, signed);
+                        Some(self.push_scale(gen, index, size))
+                    }
+                };
+                match offset {
+                    Some(offset) => gen.push_instr(IrisInstr::AluOp {
+                        op: IrisAluOp::Add,
+                        dst,
+                        src1: base,
+                        src2: offset,
+                    }),
+                    None => gen.push_instr(IrisInstr::Mov { dst, src: base }),
+                }
+            }
+            Operation::GlobalAddr(global) => {
+                gen.push_instr(IrisInstr::Addr {
+                    dst,
+                    label: LabelDest::Global(*global),
+                });
+            }
+            Operation::FieldPtr(base, index) => {
+                let Type::Pointer(ref pointee) = self.types[base.0] else {
+                    unreachable!("fieldptr of a non-pointer");
+                };
+                let offset = IRIS_DATA_LAYOUT.member_offset(pointee, *index).unwrap();
+                let base = self.get_vreg(*base);
+                if offset == 0 {
+                    gen.push_instr(IrisInstr::Mov { dst, src: base });
+                } else {
+                    let offset = self.push_imm(gen, offset as i64);
+                    gen.push_instr(IrisInstr::AluOp {
+                        op: IrisAluOp::Add,
+                        dst,
+                        src1: base,
+                        src2: offset,
+                    });
+                }
+            }
+            // removed in algos::lower_aggregates, along with loads and stores of
+            // aggregates
+            Operation::ExtractValue(..) | Operation::InsertValue(..) => unreachable!(),
+            Operation::Load(ptr) => {
+                gen.push_instr(IrisInstr::Lod {
+                    dst,
+                    ptr: self.get_vreg(*ptr),
+                });
+            }
+            Operation::Store(ptr, val) => {
+                gen.push_instr(IrisInstr::Str {
+                    ptr: self.get_vreg(*ptr),
+                    src: self.get_vreg(*val),
+                });
+            }
             Operation::LoadVar(..) | Operation::StoreVar(..) => unreachable!(),
             // THESE NEVER GET EXECUTED (removed in algos::lower_to_ssa::lower())
             Operation::Phi(vals) => {
                 gen.push_instr(IrisInstr::PhiPlaceholder {
                     dst,
-                    ops: vals.iter().map(|v| self.get_vreg(*v)).collect(),
+                    ops: vals.iter().map(|(_, v)| self.get_vreg(*v)).collect(),
                 });
             }
             Operation::Call(f, args) => {
@@ -428,6 +678,7 @@ impl InstrSelector for IrisSelector {
                 });
             }
             Terminator::Return(val) => {
+                self.push_adjust_sp(gen, IrisAluOp::Add);
                 gen.push_instr(IrisInstr::Mov {
                     dst: VReg::Real(IRIS_REG_1),
                     src: self.get_vreg(*val),
@@ -450,9 +701,10 @@ impl InstrSelector for IrisSelector {
         ) {
             gen.push_instr(IrisInstr::Mov { dst, src });
         }
+        self.push_adjust_sp(gen, IrisAluOp::Sub);
     }
 
-    fn get_post_function_instructions(&mut self, gen: &mut VCodeGenerator<Self::Instr>) {}
+    fn get_post_function_instructions(&mut self, _gen: &mut VCodeGenerator<Self::Instr>) {}
 }
 
 impl IrisSelector {
@@ -460,4 +712,155 @@ impl IrisSelector {
     pub fn get_vreg(&self, val: ValueId) -> VReg {
         VReg::Virtual(val.0)
     }
+
+    /// Returns the width of the integer `val`, or that of a register for other
+    /// types.
+    fn width(&self, val: ValueId) -> usize {
+        match self.types[val.0] {
+            Type::Integer(width, _) => width,
+            _ => IRIS_WORD_BITS,
+        }
+    }
+
+    /// Computes the upper half of the product of `src1` and `src2`, clean
+    /// integers of `width` bits narrower than a word: the product shifted
+    /// right by `width`, with the bits of it above the word from `umlt`
+    /// unless they are all zero.
+    fn push_mul_hi(
+        &self,
+        gen: &mut VCodeGenerator<IrisInstr>,
+        dst: VReg,
+        src1: VReg,
+        src2: VReg,
+        width: usize,
+    ) {
+        let low = gen.push_vreg();
+        gen.push_instr(IrisInstr::AluOp {
+            op: IrisAluOp::Mul,
+            dst: low,
+            src1,
+            src2,
+        });
+        let shift = self.push_imm(gen, width as i64);
+        if 2 * width <= IRIS_WORD_BITS {
+            gen.push_instr(IrisInstr::AluOp {
+                op: IrisAluOp::Bsr,
+                dst,
+                src1: low,
+                src2: shift,
+            });
+            return;
+        }
+        let high = gen.push_vreg();
+        let low_bits = gen.push_vreg();
+        let high_bits = gen.push_vreg();
+        let high_shift = self.push_imm(gen, (IRIS_WORD_BITS - width) as i64);
+        for (op, dst, src1, src2) in [
+            (IrisAluOp::Umlt, high, src1, src2),
+            (IrisAluOp::Bsr, low_bits, low, shift),
+            (IrisAluOp::Bsl, high_bits, high, high_shift),
+            (IrisAluOp::Or, dst, high_bits, low_bits),
+        ] {
+            gen.push_instr(IrisInstr::AluOp {
+                op,
+                dst,
+                src1,
+                src2,
+            });
+        }
+    }
+
+    fn push_imm(&self, gen: &mut VCodeGenerator<IrisInstr>, val: i64) -> VReg {
+        let dst = gen.push_vreg();
+        gen.push_instr(IrisInstr::Imm { dst, val });
+        dst
+    }
+
+    /// Moves the stack pointer by the size of the frame, `Sub` allocating it
+    /// and `Add` freeing it.
+    fn push_adjust_sp(&self, gen: &mut VCodeGenerator<IrisInstr>, op: IrisAluOp) {
+        if self.frame.size == 0 {
+            return;
+        }
+        let size = self.push_imm(gen, self.frame.size as i64);
+        gen.push_instr(IrisInstr::AluOp {
+            op,
+            dst: VReg::Real(IRIS_REG_SP),
+            src1: VReg::Real(IRIS_REG_SP),
+            src2: size,
+        });
+    }
+
+    /// Returns a register holding `index * size`, shifting if `size` is a
+    /// power of two.
+    fn push_scale(&self, gen: &mut VCodeGenerator<IrisInstr>, index: VReg, size: usize) -> VReg {
+        if size == 1 {
+            return index;
+        }
+        let (op, amount) = if size.is_power_of_two() {
+            (IrisAluOp::Bsl, size.trailing_zeros() as i64)
+        } else {
+            (IrisAluOp::Mul, size as i64)
+        };
+        let amount = self.push_imm(gen, amount);
+        let dst = gen.push_vreg();
+        gen.push_instr(IrisInstr::AluOp {
+            op,
+            dst,
+            src1: index,
+            src2: amount,
+        });
+        dst
+    }
+
+    /// Returns a register holding `val` with the bits above its width cleared
+    /// (or, if `signed`, set to its sign bit), extending it into a new one if
+    /// it's narrower than a register.
+    fn push_clean(&self, gen: &mut VCodeGenerator<IrisInstr>, val: ValueId, signed: bool) -> VReg {
+        let width = self.width(val);
+        let src = self.get_vreg(val);
+        if width >= IRIS_WORD_BITS {
+            return src;
+        }
+        let dst = gen.push_vreg();
+        self.push_extend(gen, dst, src, width, signed);
+        dst
+    }
+
+    /// Clears (or, if `signed`, sets to the sign bit) the bits of `src` above
+    /// `width`.
+    fn push_extend(
+        &self,
+        gen: &mut VCodeGenerator<IrisInstr>,
+        dst: VReg,
+        src: VReg,
+        width: usize,
+        signed: bool,
+    ) {
+        let mask = self.push_imm(gen, (1 << width) - 1);
+        let zext = if signed { gen.push_vreg() } else { dst };
+        gen.push_instr(IrisInstr::AluOp {
+            op: IrisAluOp::And,
+            dst: zext,
+            src1: src,
+            src2: mask,
+        });
+        if signed {
+            // (x ^ sign) - sign turns a set sign bit into a borrow
+            let sign = self.push_imm(gen, 1 << (width - 1));
+            let flipped = gen.push_vreg();
+            gen.push_instr(IrisInstr::AluOp {
+                op: IrisAluOp::Xor,
+                dst: flipped,
+                src1: zext,
+                src2: sign,
+            });
+            gen.push_instr(IrisInstr::AluOp {
+                op: IrisAluOp::Sub,
+                dst,
+                src1: flipped,
+                src2: sign,
+            });
+        }
+    }
 }
@@ -1,11 +1,19 @@
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display};
 
 use crate::{
-    ir::{BinOp, Instruction, Operation, Terminator, ValueId},
+    ir::{
+        BinOp, BlockId, DataLayout, Function, FunctionId, Instruction, Linkage, Operation,
+        Terminator, Type, UnOp, ValueId,
+    },
     regalloc::{apply_alloc, VReg},
     vcode::*,
 };
 
+/// The width of the registers, in bits.
+pub const URCL_WORD_BITS: usize = 16;
+
+pub const URCL_DATA_LAYOUT: DataLayout = DataLayout::new(URCL_WORD_BITS, 1);
+
 pub const URCL_REG_ZR: usize = 0;
 pub const URCL_REG_1: usize = 1;
 pub const URCL_REG_2: usize = 2;
@@ -16,8 +24,9 @@ pub const URCL_REG_6: usize = 6;
 pub const URCL_REG_7: usize = 7;
 pub const URCL_REG_8: usize = 8;
 
-/// URCL DEFAULT CALLING CONV:
-/// - r1: return value
+// URCL DEFAULT CALLING CONV:
+// - r1: return value
+// - sp: points at the bottom of the frame of the current function
 
 pub enum UrclInstr {
     PhiPlaceholder {
@@ -30,6 +39,11 @@ pub enum UrclInstr {
         src1: VReg,
         src2: VReg,
     },
+    UnaryOp {
+        op: UrclAluOp,
+        dst: VReg,
+        src: VReg,
+    },
     Jmp {
         dst: LabelDest,
     },
@@ -41,10 +55,33 @@ pub enum UrclInstr {
         dst: VReg,
         val: i64,
     },
+    /// `imm` of the address of a label
+    Addr {
+        dst: VReg,
+        label: LabelDest,
+    },
     Mov {
         dst: VReg,
         src: VReg,
     },
+    Lod {
+        dst: VReg,
+        ptr: VReg,
+    },
+    Str {
+        ptr: VReg,
+        src: VReg,
+    },
+    /// `dst = sp + offset`
+    StackAddr {
+        dst: VReg,
+        offset: usize,
+    },
+    /// `sp = sp op words`
+    AdjustStack {
+        op: UrclAluOp,
+        words: usize,
+    },
     Cal {
         dst: LabelDest,
     },
@@ -55,43 +92,60 @@ pub enum UrclAluOp {
     Add,
     Sub,
     Mul,
+    Umlt,
     Div,
+    Sdiv,
     Mod,
     And,
     Or,
     Xor,
     Not,
     Neg,
-    Rsh,
-    Lsh,
-    Ssete,
-    Ssetne,
+    Bsl,
+    Bsr,
+    Bss,
+    Sete,
+    Setne,
+    Setl,
+    Setle,
+    Setg,
+    Setge,
     Ssetl,
     Ssetle,
     Ssetg,
     Ssetge,
 }
 
-impl From<BinOp> for UrclAluOp {
-    fn from(op: BinOp) -> Self {
-        match op {
-            BinOp::Add => UrclAluOp::Add,
-            BinOp::Sub => UrclAluOp::Sub,
-            BinOp::Mul => UrclAluOp::Mul,
-            BinOp::Div => UrclAluOp::Div,
-            BinOp::Mod => UrclAluOp::Mod,
-            BinOp::And => UrclAluOp::And,
-            BinOp::Or => UrclAluOp::Or,
-            BinOp::Xor => UrclAluOp::Xor,
-            BinOp::Eq => UrclAluOp::Ssete,
-            BinOp::Ne => UrclAluOp::Ssetne,
-            BinOp::Lt => UrclAluOp::Ssetl,
-            BinOp::Le => UrclAluOp::Ssetle,
-            BinOp::Gt => UrclAluOp::Ssetg,
-            BinOp::Ge => UrclAluOp::Ssetge,
-            BinOp::Shl => UrclAluOp::Lsh,
-            BinOp::Shr => UrclAluOp::Rsh,
-        }
+impl UrclAluOp {
+    /// Returns the instruction computing `op` on operands of the given
+    /// signedness. Signed `Mod` has no instruction and returns `None`.
+    pub fn from_binop(op: BinOp, signed: bool) -> Option<Self> {
+        Some(match (op, signed) {
+            (BinOp::Add, _) => UrclAluOp::Add,
+            (BinOp::Sub, _) => UrclAluOp::Sub,
+            (BinOp::Mul, _) => UrclAluOp::Mul,
+            (BinOp::MulHi, _) => UrclAluOp::Umlt,
+            (BinOp::Div, false) => UrclAluOp::Div,
+            (BinOp::Div, true) => UrclAluOp::Sdiv,
+            (BinOp::Mod, false) => UrclAluOp::Mod,
+            (BinOp::Mod, true) => return None,
+            (BinOp::And, _) => UrclAluOp::And,
+            (BinOp::Or, _) => UrclAluOp::Or,
+            (BinOp::Xor, _) => UrclAluOp::Xor,
+            (BinOp::Eq, _) => UrclAluOp::Sete,
+            (BinOp::Ne, _) => UrclAluOp::Setne,
+            (BinOp::Lt, false) => UrclAluOp::Setl,
+            (BinOp::Le, false) => UrclAluOp::Setle,
+            (BinOp::Gt, false) => UrclAluOp::Setg,
+            (BinOp::Ge, false) => UrclAluOp::Setge,
+            (BinOp::Lt, true) => UrclAluOp::Ssetl,
+            (BinOp::Le, true) => UrclAluOp::Ssetle,
+            (BinOp::Gt, true) => UrclAluOp::Ssetg,
+            (BinOp::Ge, true) => UrclAluOp::Ssetge,
+            (BinOp::Shl, _) => UrclAluOp::Bsl,
+            (BinOp::Shr, false) => UrclAluOp::Bsr,
+            (BinOp::Shr, true) => UrclAluOp::Bss,
+        })
     }
 }
 
@@ -118,11 +172,15 @@ impl VCodeInstr for UrclInstr {
                 regalloc.add_use(*src1);
                 regalloc.add_use(*src2);
             }
+            Self::UnaryOp { dst, src, .. } => {
+                regalloc.add_def(*dst);
+                regalloc.add_use(*src);
+            }
             Self::Jmp { .. } => (),
             Self::Beq { src1, .. } => {
                 regalloc.add_use(*src1);
             }
-            Self::Imm { dst, .. } => {
+            Self::Imm { dst, .. } | Self::Addr { dst, .. } => {
                 regalloc.add_def(*dst);
             }
             Self::Mov { dst, src } => {
@@ -130,6 +188,17 @@ impl VCodeInstr for UrclInstr {
                 regalloc.add_use(*src);
                 regalloc.coalesce_move(*src, *dst);
             }
+            Self::Lod { dst, ptr } => {
+                regalloc.add_def(*dst);
+                regalloc.add_use(*ptr);
+            }
+            Self::Str { ptr, src } => {
+                regalloc.add_use(*ptr);
+                regalloc.add_use(*src);
+            }
+            Self::StackAddr { dst, .. } => {
+                regalloc.add_def(*dst);
+            }
             _ => (),
         }
     }
@@ -143,26 +212,87 @@ impl VCodeInstr for UrclInstr {
                 apply_alloc(src1, allocs);
                 apply_alloc(src2, allocs);
             }
+            Self::UnaryOp { dst, src, .. } => {
+                apply_alloc(dst, allocs);
+                apply_alloc(src, allocs);
+            }
             Self::Jmp { .. } => (),
             Self::Beq { src1, .. } => {
                 apply_alloc(src1, allocs);
             }
-            Self::Imm { dst, .. } => {
+            Self::Imm { dst, .. } | Self::Addr { dst, .. } => {
                 apply_alloc(dst, allocs);
             }
             Self::Mov { dst, src } => {
                 apply_alloc(dst, allocs);
                 apply_alloc(src, allocs);
             }
+            Self::Lod { dst, ptr } => {
+                apply_alloc(dst, allocs);
+                apply_alloc(ptr, allocs);
+            }
+            Self::Str { ptr, src } => {
+                apply_alloc(ptr, allocs);
+                apply_alloc(src, allocs);
+            }
+            Self::StackAddr { dst, .. } => {
+                apply_alloc(dst, allocs);
+            }
             _ => (),
         }
     }
 
-    fn apply_mandatory_transforms(vcode: &mut VCode<Self>) {
+    fn apply_mandatory_transforms(_vcode: &mut VCode<Self>) {
     }
 
     fn emit_assembly<T: std::io::Write>(w: &mut T, vcode: &VCode<Self>) -> std::io::Result<()> {
-        todo!();
+        writeln!(w, "cal .main")?;
+        writeln!(w, "hlt")?;
+        writeln!(w)?;
+
+        for (fi, f) in vcode.functions.iter().enumerate() {
+            if matches!(f.linkage, Linkage::External) {
+                continue;
+            }
+
+            writeln!(w, "{}", vcode.mangle(f, &LabelDest::Function(FunctionId(fi))))?;
+            for (li, l) in f.instrs.iter().enumerate() {
+                if li != 0 {
+                    writeln!(w, "{}", vcode.mangle(f, &LabelDest::Block(BlockId(li - 1))))?;
+                }
+
+                for i in l.instrs.iter() {
+                    match i {
+                        UrclInstr::Jmp { dst } => writeln!(w, "jmp {}", vcode.mangle(f, dst))?,
+                        UrclInstr::Beq { src1, dst } => {
+                            writeln!(w, "bgr {} {} 0", vcode.mangle(f, dst), src1)?
+                        }
+                        UrclInstr::Cal { dst } => writeln!(w, "cal {}", vcode.mangle(f, dst))?,
+                        UrclInstr::Addr { dst, label } => {
+                            writeln!(w, "imm {} {}", dst, vcode.mangle(f, label))?
+                        }
+                        _ => writeln!(w, "{i}")?,
+                    }
+                }
+            }
+
+            writeln!(w)?;
+        }
+
+        for g in vcode.globals.iter() {
+            if matches!(g.linkage, Linkage::External) {
+                continue;
+            }
+
+            writeln!(w, "{}", symbol(&g.name, g.linkage))?;
+            let words = URCL_DATA_LAYOUT.words(&g.ty, g.init.as_ref());
+            if !words.is_empty() {
+                let words: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+                writeln!(w, "dw [{}]", words.join(" "))?;
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -177,10 +307,16 @@ impl Display for UrclInstr {
             } => {
                 write!(f, "{} {} {} {}", op, dst, src1, src2)
             }
+            UrclInstr::UnaryOp { op, dst, src } => write!(f, "{} {} {}", op, dst, src),
             UrclInstr::Jmp { dst } => write!(f, "jmp {}", dst),
             UrclInstr::Imm { dst, val } => write!(f, "imm {} {}", dst, val),
+            UrclInstr::Addr { dst, label } => write!(f, "imm {} {}", dst, label),
             UrclInstr::Beq { src1, dst } => write!(f, "bgr {} {} 0", dst, src1),
             UrclInstr::Mov { dst, src } => write!(f, "mov {} {}", dst, src),
+            UrclInstr::Lod { dst, ptr } => write!(f, "lod {} {}", dst, ptr),
+            UrclInstr::Str { ptr, src } => write!(f, "str {} {}", ptr, src),
+            UrclInstr::StackAddr { dst, offset } => write!(f, "add {} sp {}", dst, offset),
+            UrclInstr::AdjustStack { op, words } => write!(f, "{} sp sp {}", op, words),
             UrclInstr::Cal { dst } => write!(f, "cal {}", dst),
             UrclInstr::Ret => write!(f, "ret"),
             UrclInstr::PhiPlaceholder { dst, ops } => write!(
@@ -202,17 +338,24 @@ impl Display for UrclAluOp {
             UrclAluOp::Add => write!(f, "add"),
             UrclAluOp::Sub => write!(f, "sub"),
             UrclAluOp::Mul => write!(f, "mul"),
+            UrclAluOp::Umlt => write!(f, "umlt"),
             UrclAluOp::Div => write!(f, "div"),
+            UrclAluOp::Sdiv => write!(f, "sdiv"),
             UrclAluOp::Mod => write!(f, "mod"),
             UrclAluOp::And => write!(f, "and"),
             UrclAluOp::Or => write!(f, "or"),
             UrclAluOp::Xor => write!(f, "xor"),
             UrclAluOp::Not => write!(f, "not"),
             UrclAluOp::Neg => write!(f, "neg"),
-            UrclAluOp::Rsh => write!(f, "rsh"),
-            UrclAluOp::Lsh => write!(f, "lsh"),
-            UrclAluOp::Ssete => write!(f, "ssete"),
-            UrclAluOp::Ssetne => write!(f, "ssetne"),
+            UrclAluOp::Bsl => write!(f, "bsl"),
+            UrclAluOp::Bsr => write!(f, "bsr"),
+            UrclAluOp::Bss => write!(f, "bss"),
+            UrclAluOp::Sete => write!(f, "sete"),
+            UrclAluOp::Setne => write!(f, "setne"),
+            UrclAluOp::Setl => write!(f, "setl"),
+            UrclAluOp::Setle => write!(f, "setle"),
+            UrclAluOp::Setg => write!(f, "setg"),
+            UrclAluOp::Setge => write!(f, "setge"),
             UrclAluOp::Ssetl => write!(f, "ssetl"),
             UrclAluOp::Ssetle => write!(f, "ssetle"),
             UrclAluOp::Ssetg => write!(f, "ssetg"),
@@ -222,10 +365,23 @@ impl Display for UrclAluOp {
 }
 
 #[derive(Default)]
-pub struct UrclSelector;
+pub struct UrclSelector {
+    /// the types of the values of the current function
+    types: Vec<Type>,
+    /// the values of the current function known to be constant
+    constants: HashMap<ValueId, i64>,
+    frame: StackFrame,
+}
 
 impl InstrSelector for UrclSelector {
     type Instr = UrclInstr;
+    const SUPPORTS_MUL_HIGH: bool = true;
+    fn switch_to_function(&mut self, func: &Function) {
+        self.types = func.values.iter().map(|v| v.ty.clone()).collect();
+        self.constants = func.constants();
+        self.frame = StackFrame::new(func, &URCL_DATA_LAYOUT);
+    }
+
     fn select(&mut self, gen: &mut VCodeGenerator<Self::Instr>, instr: &Instruction) {
         let dst = if let Some(val) = instr.yielded {
             self.get_vreg(val)
@@ -234,24 +390,175 @@ impl InstrSelector for UrclSelector {
         };
 
         match &instr.operation {
+            // umlt is the upper half of a word, not of the width of the type
+            Operation::BinOp(BinOp::MulHi, lhs, rhs) if self.width(*lhs) < URCL_WORD_BITS => {
+                let width = self.width(*lhs);
+                let src1 = self.push_clean(gen, *lhs, false);
+                let src2 = self.push_clean(gen, *rhs, false);
+                self.push_mul_hi(gen, dst, src1, src2, width);
+            }
             Operation::BinOp(op, lhs, rhs) => {
-                let src1 = self.get_vreg(*lhs);
-                let src2 = self.get_vreg(*rhs);
+                let signed = self.types[lhs.0].is_signed();
+                // the bits above the width of the operands change the
+                // result of everything but the wrapping arithmetic
+                let (src1, src2) = match op {
+                    BinOp::Shl => (self.get_vreg(*lhs), self.push_clean(gen, *rhs, false)),
+                    BinOp::Shr => (
+                        self.push_clean(gen, *lhs, signed),
+                        self.push_clean(gen, *rhs, false),
+                    ),
+                    BinOp::Div | BinOp::Mod => (
+                        self.push_clean(gen, *lhs, signed),
+                        self.push_clean(gen, *rhs, signed),
+                    ),
+                    _ if op.is_comparison() => (
+                        self.push_clean(gen, *lhs, signed),
+                        self.push_clean(gen, *rhs, signed),
+                    ),
+                    _ => (self.get_vreg(*lhs), self.get_vreg(*rhs)),
+                };
+                match UrclAluOp::from_binop(*op, signed) {
+                    Some(op) => gen.push_instr(UrclInstr::AluOp {
+                        op,
+                        dst,
+                        src1,
+                        src2,
+                    }),
+                    // x - x / y * y
+                    None => {
+                        let quotient = gen.push_vreg();
+                        let product = gen.push_vreg();
+                        for (op, dst, src1, src2) in [
+                            (UrclAluOp::Sdiv, quotient, src1, src2),
+                            (UrclAluOp::Mul, product, quotient, src2),
+                            (UrclAluOp::Sub, dst, src1, product),
+                        ] {
+                            gen.push_instr(UrclInstr::AluOp {
+                                op,
+                                dst,
+                                src1,
+                                src2,
+                            });
+                        }
+                    }
+                }
+            }
+            Operation::UnOp(UnOp::LNot, src) => {
+                let src = self.push_clean(gen, *src, false);
                 gen.push_instr(UrclInstr::AluOp {
-                    op: (*op).into(),
+                    op: UrclAluOp::Sete,
                     dst,
-                    src1,
-                    src2,
+                    src1: src,
+                    src2: VReg::Real(URCL_REG_ZR),
+                });
+            }
+            Operation::UnOp(op, src) => {
+                gen.push_instr(UrclInstr::UnaryOp {
+                    op: if *op == UnOp::Neg { UrclAluOp::Neg } else { UrclAluOp::Not },
+                    dst,
+                    src: self.get_vreg(*src),
                 });
             }
             Operation::Integer(val) => {
                 gen.push_instr(UrclInstr::Imm { dst, val: *val });
             }
+            // whatever is in the register will do
+            Operation::Undef => {}
+            Operation::Copy(src) => {
+                gen.push_instr(UrclInstr::Mov {
+                    dst,
+                    src: self.get_vreg(*src),
+                });
+            }
+            // the bits above the width of a value are ignored, so narrowing
+            // and reinterpreting it are free
+            Operation::Trunc(src) | Operation::PtrToInt(src) | Operation::IntToPtr(src) => {
+                gen.push_instr(UrclInstr::Mov {
+                    dst,
+                    src: self.get_vreg(*src),
+                });
+            }
+            Operation::ZExt(src) | Operation::SExt(src) => {
+                let width = self.width(*src);
+                let src = self.get_vreg(*src);
+                if width >= URCL_WORD_BITS {
+                    gen.push_instr(UrclInstr::Mov { dst, src });
+                } else {
+                    let signed = matches!(instr.operation, Operation::SExt(_));
+                    self.push_extend(gen, dst, src, width, signed);
+                }
+            }
+            Operation::Alloca(_) => {
+                let offset = self.frame.offsets[&instr.yielded.unwrap()];
+                gen.push_instr(UrclInstr::StackAddr { dst, offset });
+            }
+            Operation::ElementPtr(base, index, ty) => {
+                let base = self.get_vreg(*base);
+                let size = URCL_DATA_LAYOUT.size_of(ty);
+                let offset = match self.constants.get(index) {
+                    Some(c) if c.wrapping_mul(size as i64) == 0 => None,
+                    Some(c) => Some(self.push_imm(gen, c.wrapping_mul(size as i64))),
+                    None if size == 0 => None,
+                    None => {
+                        let signed = self.types[index.0].is_signed();
+                        let index = self.push_clean(gen, *index, signed);
+                        Some(self.push_scale(gen, index, size))
+                    }
+                };
+                match offset {
+                    Some(offset) => gen.push_instr(UrclInstr::AluOp {
+                        op: UrclAluOp::Add,
+                        dst,
+                        src1: base,
+                        src2: offset,
+                    }),
+                    None => gen.push_instr(UrclInstr::Mov { dst, src: base }),
+                }
+            }
+            Operation::GlobalAddr(global) => {
+                gen.push_instr(UrclInstr::Addr {
+                    dst,
+                    label: LabelDest::Global(*global),
+                });
+            }
+            Operation::FieldPtr(base, index) => {
+                let Type::Pointer(ref pointee) = self.types[base.0] else {
+                    unreachable!("fieldptr of a non-pointer");
+                };
+                let offset = URCL_DATA_LAYOUT.member_offset(pointee, *index).unwrap();
+                let base = self.get_vreg(*base);
+                if offset == 0 {
+                    gen.push_instr(UrclInstr::Mov { dst, src: base });
+                } else {
+                    let offset = self.push_imm(gen, offset as i64);
+                    gen.push_instr(UrclInstr::AluOp {
+                        op: UrclAluOp::Add,
+                        dst,
+                        src1: base,
+                        src2: offset,
+                    });
+                }
+            }
+            // removed in algos::lower_aggregates, along with loads and stores of
+            // aggregates
+            Operation::ExtractValue(..) | Operation::InsertValue(..) => unreachable!(),
+            Operation::Load(ptr) => {
+                gen.push_instr(UrclInstr::Lod {
+                    dst,
+                    ptr: self.get_vreg(*ptr),
+                });
+            }
+            Operation::Store(ptr, val) => {
+                gen.push_instr(UrclInstr::Str {
+                    ptr: self.get_vreg(*ptr),
+                    src: self.get_vreg(*val),
+                });
+            }
             Operation::LoadVar(_) | Operation::StoreVar(..) => (), // THESE NEVER GET EXECUTED (removed in algos::lower_to_ssa::lower())
             Operation::Phi(vals) => {
                 gen.push_instr(UrclInstr::PhiPlaceholder {
                     dst,
-                    ops: vals.iter().map(|v| self.get_vreg(*v)).collect(),
+                    ops: vals.iter().map(|(_, v)| self.get_vreg(*v)).collect(),
                 });
             }
             _ => todo!(),
@@ -275,6 +582,12 @@ impl InstrSelector for UrclSelector {
                 });
             }
             Terminator::Return(val) => {
+                if self.frame.size > 0 {
+                    gen.push_instr(UrclInstr::AdjustStack {
+                        op: UrclAluOp::Add,
+                        words: self.frame.size,
+                    });
+                }
                 gen.push_instr(UrclInstr::Mov {
                     dst: VReg::Real(URCL_REG_1),
                     src: self.get_vreg(*val),
@@ -285,9 +598,16 @@ impl InstrSelector for UrclSelector {
         }
     }
 
-    fn get_post_function_instructions(&mut self, gen: &mut VCodeGenerator<Self::Instr>) {}
+    fn get_post_function_instructions(&mut self, _gen: &mut VCodeGenerator<Self::Instr>) {}
 
-    fn get_pre_function_instructions(&mut self, gen: &mut VCodeGenerator<Self::Instr>) {}
+    fn get_pre_function_instructions(&mut self, gen: &mut VCodeGenerator<Self::Instr>) {
+        if self.frame.size > 0 {
+            gen.push_instr(UrclInstr::AdjustStack {
+                op: UrclAluOp::Sub,
+                words: self.frame.size,
+            });
+        }
+    }
 }
 
 impl UrclSelector {
@@ -295,4 +615,140 @@ impl UrclSelector {
     pub fn get_vreg(&self, val: ValueId) -> VReg {
         VReg::Virtual(val.0)
     }
+
+    /// Returns the width of the integer `val`, or that of a register for other
+    /// types.
+    fn width(&self, val: ValueId) -> usize {
+        match self.types[val.0] {
+            Type::Integer(width, _) => width,
+            _ => URCL_WORD_BITS,
+        }
+    }
+
+    /// Computes the upper half of the product of `src1` and `src2`, clean
+    /// integers of `width` bits narrower than a word: the product shifted
+    /// right by `width`, with the bits of it above the word from `umlt`
+    /// unless they are all zero.
+    fn push_mul_hi(
+        &self,
+        gen: &mut VCodeGenerator<UrclInstr>,
+        dst: VReg,
+        src1: VReg,
+        src2: VReg,
+        width: usize,
+    ) {
+        let low = gen.push_vreg();
+        gen.push_instr(UrclInstr::AluOp {
+            op: UrclAluOp::Mul,
+            dst: low,
+            src1,
+            src2,
+        });
+        let shift = self.push_imm(gen, width as i64);
+        if 2 * width <= URCL_WORD_BITS {
+            gen.push_instr(UrclInstr::AluOp {
+                op: UrclAluOp::Bsr,
+                dst,
+                src1: low,
+                src2: shift,
+            });
+            return;
+        }
+        let high = gen.push_vreg();
+        let low_bits = gen.push_vreg();
+        let high_bits = gen.push_vreg();
+        let high_shift = self.push_imm(gen, (URCL_WORD_BITS - width) as i64);
+        for (op, dst, src1, src2) in [
+            (UrclAluOp::Umlt, high, src1, src2),
+            (UrclAluOp::Bsr, low_bits, low, shift),
+            (UrclAluOp::Bsl, high_bits, high, high_shift),
+            (UrclAluOp::Or, dst, high_bits, low_bits),
+        ] {
+            gen.push_instr(UrclInstr::AluOp {
+                op,
+                dst,
+                src1,
+                src2,
+            });
+        }
+    }
+
+    fn push_imm(&self, gen: &mut VCodeGenerator<UrclInstr>, val: i64) -> VReg {
+        let dst = gen.push_vreg();
+        gen.push_instr(UrclInstr::Imm { dst, val });
+        dst
+    }
+
+    /// Returns a register holding `index * size`, shifting if `size` is a
+    /// power of two.
+    fn push_scale(&self, gen: &mut VCodeGenerator<UrclInstr>, index: VReg, size: usize) -> VReg {
+        if size == 1 {
+            return index;
+        }
+        let (op, amount) = if size.is_power_of_two() {
+            (UrclAluOp::Bsl, size.trailing_zeros() as i64)
+        } else {
+            (UrclAluOp::Mul, size as i64)
+        };
+        let amount = self.push_imm(gen, amount);
+        let dst = gen.push_vreg();
+        gen.push_instr(UrclInstr::AluOp {
+            op,
+            dst,
+            src1: index,
+            src2: amount,
+        });
+        dst
+    }
+
+    /// Returns a register holding `val` with the bits above its width cleared
+    /// (or, if `signed`, set to its sign bit), extending it into a new one if
+    /// it's narrower than a register.
+    fn push_clean(&self, gen: &mut VCodeGenerator<UrclInstr>, val: ValueId, signed: bool) -> VReg {
+        let width = self.width(val);
+        let src = self.get_vreg(val);
+        if width >= URCL_WORD_BITS {
+            return src;
+        }
+        let dst = gen.push_vreg();
+        self.push_extend(gen, dst, src, width, signed);
+        dst
+    }
+
+    /// Clears (or, if `signed`, sets to the sign bit) the bits of `src` above
+    /// `width`.
+    fn push_extend(
+        &self,
+        gen: &mut VCodeGenerator<UrclInstr>,
+        dst: VReg,
+        src: VReg,
+        width: usize,
+        signed: bool,
+    ) {
+        let mask = self.push_imm(gen, (1 << width) - 1);
+        let zext = if signed { gen.push_vreg() } else { dst };
+        gen.push_instr(UrclInstr::AluOp {
+            op: UrclAluOp::And,
+            dst: zext,
+            src1: src,
+            src2: mask,
+        });
+        if signed {
+            // (x ^ sign) - sign turns a set sign bit into a borrow
+            let sign = self.push_imm(gen, 1 << (width - 1));
+            let flipped = gen.push_vreg();
+            gen.push_instr(UrclInstr::AluOp {
+                op: UrclAluOp::Xor,
+                dst: flipped,
+                src1: zext,
+                src2: sign,
+            });
+            gen.push_instr(UrclInstr::AluOp {
+                op: UrclAluOp::Sub,
+                dst,
+                src1: flipped,
+                src2: sign,
+            });
+        }
+    }
 }
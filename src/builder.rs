@@ -1,8 +1,9 @@
 use std::collections::HashSet;
 
 use crate::ir::{
-    BasicBlock, BinOp, BlockId, Function, FunctionId, Instruction, Linkage, Module, Operation,
-    Terminator, Type, Value, ValueId, Variable, VariableId,
+    Attribute, BasicBlock, BinOp, BlockId, Constant, Function, FunctionId, Global, GlobalId,
+    Instruction, Linkage, Module, Operation, Terminator, Type, UnOp, Value, ValueId, Variable,
+    VariableId,
 };
 
 pub struct ModuleBuilder {
@@ -51,6 +52,33 @@ impl ModuleBuilder {
         (FunctionId(self.module.functions.len() - 1), a)
     }
 
+    /// Adds a global to the module, zero-initialized if `init` is `None`.
+    ///
+    /// # Panics
+    /// If `init` isn't a constant of type `ty`, or the global is external and
+    /// has one.
+    pub fn push_global(
+        &mut self,
+        name: &str,
+        ty: Type,
+        linkage: Linkage,
+        init: Option<Constant>,
+        mutable: bool,
+    ) -> GlobalId {
+        if let Some(ref init) = init {
+            assert!(linkage != Linkage::External, "external global {name} with an initializer");
+            assert!(init.has_type(&ty), "initializer {init} of global {name} isn't a {ty}");
+        }
+        self.module.globals.push(Global {
+            name: name.to_string(),
+            ty,
+            linkage,
+            init,
+            mutable,
+        });
+        GlobalId(self.module.globals.len() - 1)
+    }
+
     pub fn push_block(&mut self) -> BlockId {
         let id = self
             .module
@@ -71,6 +99,13 @@ impl ModuleBuilder {
         BlockId(id)
     }
 
+    pub fn add_attribute(&mut self, func: FunctionId, attr: Attribute) {
+        let attributes = &mut self.get_func_mut(func).attributes;
+        if !attributes.contains(&attr) {
+            attributes.push(attr);
+        }
+    }
+
     pub fn switch_to_fn(&mut self, id: FunctionId) {
         self.current_func = Some(id);
     }
@@ -94,6 +129,10 @@ impl ModuleBuilder {
         val
     }
 
+    pub fn build_unop(&mut self, op: UnOp, src: ValueId, ty: Type) -> ValueId {
+        self.build_unary(Operation::UnOp(op, src), ty)
+    }
+
     fn get_func(&self, id: FunctionId) -> &Function {
         &self.module.functions[id.0]
     }
@@ -130,6 +169,42 @@ impl ModuleBuilder {
         val
     }
 
+    /// Builds `operation`, a cast, unop, load or other operation of one value,
+    /// yielding a value of type `ty`.
+    fn build_unary(&mut self, operation: Operation, ty: Type) -> ValueId {
+        let src = operation.operands()[0];
+        let val = self.push_value(ty);
+        let cur_fn = self.get_func_mut(self.current_func.unwrap());
+        cur_fn.values[src.0].children.push(val);
+
+        let block = self.get_block_mut(self.current_block.unwrap());
+        block.instructions.push(Instruction {
+            yielded: Some(val),
+            operation,
+        });
+        val
+    }
+
+    pub fn build_zext(&mut self, src: ValueId, ty: Type) -> ValueId {
+        self.build_unary(Operation::ZExt(src), ty)
+    }
+
+    pub fn build_sext(&mut self, src: ValueId, ty: Type) -> ValueId {
+        self.build_unary(Operation::SExt(src), ty)
+    }
+
+    pub fn build_trunc(&mut self, src: ValueId, ty: Type) -> ValueId {
+        self.build_unary(Operation::Trunc(src), ty)
+    }
+
+    pub fn build_ptr_to_int(&mut self, src: ValueId, ty: Type) -> ValueId {
+        self.build_unary(Operation::PtrToInt(src), ty)
+    }
+
+    pub fn build_int_to_ptr(&mut self, src: ValueId, ty: Type) -> ValueId {
+        self.build_unary(Operation::IntToPtr(src), ty)
+    }
+
     pub fn build_store(&mut self, var: VariableId, value: ValueId) {
         let cur_blk = self.current_block.unwrap();
 
@@ -157,6 +232,106 @@ impl ModuleBuilder {
         val
     }
 
+    pub fn build_alloca(&mut self, ty: Type) -> ValueId {
+        let val = self.push_value(Type::Pointer(Box::new(ty.clone())));
+        let block = self.get_block_mut(self.current_block.unwrap());
+        block.instructions.push(Instruction {
+            yielded: Some(val),
+            operation: Operation::Alloca(ty),
+        });
+        val
+    }
+
+    pub fn build_global_addr(&mut self, global: GlobalId) -> ValueId {
+        let ty = self.module.globals[global.0].ty.clone();
+        let val = self.push_value(Type::Pointer(Box::new(ty)));
+        let block = self.get_block_mut(self.current_block.unwrap());
+        block.instructions.push(Instruction {
+            yielded: Some(val),
+            operation: Operation::GlobalAddr(global),
+        });
+        val
+    }
+
+    /// Builds a load through the pointer `ptr`, as opposed to `build_load`
+    /// which reads a variable.
+    ///
+    /// # Panics
+    /// If `ptr` isn't a pointer.
+    pub fn build_load_ptr(&mut self, ptr: ValueId) -> ValueId {
+        let Type::Pointer(ref pointee) = self.get_func(self.current_func.unwrap()).values[ptr.0].ty
+        else {
+            panic!("load through {ptr}, which isn't a pointer");
+        };
+        let pointee = (**pointee).clone();
+        self.build_unary(Operation::Load(ptr), pointee)
+    }
+
+    pub fn build_store_ptr(&mut self, ptr: ValueId, value: ValueId) {
+        let block = self.get_block_mut(self.current_block.unwrap());
+        block.instructions.push(Instruction {
+            yielded: None,
+            operation: Operation::Store(ptr, value),
+        });
+    }
+
+    /// Builds the address of element `index` of an array of `ty`s starting at
+    /// `base`.
+    pub fn build_element_ptr(&mut self, base: ValueId, index: ValueId, ty: Type) -> ValueId {
+        let val = self.push_value(Type::Pointer(Box::new(ty.clone())));
+        let cur_fn = self.get_func_mut(self.current_func.unwrap());
+        cur_fn.values[base.0].children.push(val);
+        cur_fn.values[index.0].children.push(val);
+
+        let block = self.get_block_mut(self.current_block.unwrap());
+        block.instructions.push(Instruction {
+            yielded: Some(val),
+            operation: Operation::ElementPtr(base, index, ty),
+        });
+        val
+    }
+
+    /// Builds the address of member `index` of the struct or array `base`
+    /// points to.
+    ///
+    /// # Panics
+    /// If `base` isn't a pointer to an aggregate with such a member.
+    pub fn build_field_ptr(&mut self, base: ValueId, index: usize) -> ValueId {
+        let member = match self.get_func(self.current_func.unwrap()).values[base.0].ty {
+            Type::Pointer(ref pointee) => pointee.member(index).cloned(),
+            _ => None,
+        };
+        let Some(member) = member else {
+            panic!("fieldptr of {base}, which doesn't point to a member {index}");
+        };
+        self.build_unary(Operation::FieldPtr(base, index), Type::Pointer(Box::new(member)))
+    }
+
+    /// # Panics
+    /// If `agg` isn't an aggregate with a member `index`.
+    pub fn build_extract_value(&mut self, agg: ValueId, index: usize) -> ValueId {
+        let ty = &self.get_func(self.current_func.unwrap()).values[agg.0].ty;
+        let Some(member) = ty.member(index).cloned() else {
+            panic!("extractvalue of {agg}, which has no member {index}");
+        };
+        self.build_unary(Operation::ExtractValue(agg, index), member)
+    }
+
+    pub fn build_insert_value(&mut self, agg: ValueId, value: ValueId, index: usize) -> ValueId {
+        let ty = self.get_func(self.current_func.unwrap()).values[agg.0].ty.clone();
+        let val = self.push_value(ty);
+        let cur_fn = self.get_func_mut(self.current_func.unwrap());
+        cur_fn.values[agg.0].children.push(val);
+        cur_fn.values[value.0].children.push(val);
+
+        let block = self.get_block_mut(self.current_block.unwrap());
+        block.instructions.push(Instruction {
+            yielded: Some(val),
+            operation: Operation::InsertValue(agg, value, index),
+        });
+        val
+    }
+
     pub fn build_call(&mut self, func: FunctionId, args: Vec<ValueId>) -> ValueId {
         let val = self.push_value(self.get_func(func).ret_type.clone());
         let block = self.get_block_mut(self.current_block.unwrap());
@@ -167,6 +342,32 @@ impl ModuleBuilder {
         val
     }
 
+    /// Builds a phi taking one value per predecessor of the current block. It
+    /// is placed after the other phis of the block, before any other
+    /// instruction.
+    pub fn build_phi(&mut self, incoming: Vec<(BlockId, ValueId)>, ty: Type) -> ValueId {
+        let val = self.push_value(ty);
+        let cur_fn = self.get_func_mut(self.current_func.unwrap());
+        for (_, v) in incoming.iter() {
+            cur_fn.values[v.0].children.push(val);
+        }
+
+        let block = self.get_block_mut(self.current_block.unwrap());
+        let pos = block
+            .instructions
+            .iter()
+            .take_while(|i| matches!(i.operation, Operation::Phi(_)))
+            .count();
+        block.instructions.insert(
+            pos,
+            Instruction {
+                yielded: Some(val),
+                operation: Operation::Phi(incoming),
+            },
+        );
+        val
+    }
+
     pub fn set_terminator(&mut self, terminator: Terminator) {
         let cur_blk = self.current_block.unwrap();
         match terminator {
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fmt::{Debug, Display},
     ops::Deref,
 };
@@ -9,14 +9,24 @@ use crate::{
     vcode::{InstrSelector, VCode, VCodeGenerator, VCodeInstr},
 };
 
-/// `Module` is the struct containing all the functions and info about the
-/// passes run on the SSA.
+pub mod apint;
+pub mod layout;
+pub mod parse;
+pub mod verify;
+
+pub use apint::APInt;
+pub use layout::DataLayout;
+pub use verify::{verify, VerifyError};
+
+/// `Module` is the struct containing all the functions and globals, and info
+/// about the passes run on the SSA.
 ///
 /// It is intended to be generated by the `ModuleBuilder` struct and then have
 /// `.apply_mandatory_transforms()` called on it to lower to SSA form
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Module {
     pub(crate) functions: Vec<Function>,
+    pub(crate) globals: Vec<Global>,
     pub name: String,
     pub(crate) algos_run: Vec<Algo>,
 }
@@ -29,6 +39,7 @@ pub enum Algo {
     PhiLowering,
     PhiRemoval,
     LowerParMoves,
+    AggregateLowering,
 }
 
 impl Module {
@@ -36,6 +47,7 @@ impl Module {
     pub fn new(name: &str, functions: Vec<Function>) -> Module {
         Module {
             functions,
+            globals: vec![],
             name: name.to_string(),
             algos_run: vec![],
         }
@@ -43,16 +55,43 @@ impl Module {
 
     /// Applies the mandatory transforms to the module and lowers it to SSA form
     pub fn apply_mandatory_transforms(&mut self) {
-        crate::algos::remove_critical_edges::remove_critical_edges(self);
-        crate::algos::lower_to_ssa::lower(self);
+        self.run_mandatory_transforms(false).unwrap();
+    }
 
-        crate::algos::phi_removal::remove_phis(self);
+    /// Like `.apply_mandatory_transforms()`, but verifies the module before
+    /// and after every pass, stopping at the first pass that leaves it
+    /// malformed.
+    pub fn apply_mandatory_transforms_verified(&mut self) -> Result<(), Vec<VerifyError>> {
+        self.run_mandatory_transforms(true)
+    }
+
+    fn run_mandatory_transforms(&mut self, verify: bool) -> Result<(), Vec<VerifyError>> {
+        let passes: [fn(&mut Module); 4] = [
+            crate::algos::remove_critical_edges::remove_critical_edges,
+            crate::algos::lower_to_ssa::lower,
+            crate::algos::lower_aggregates::lower_aggregates,
+            crate::algos::phi_removal::remove_phis,
+        ];
+
+        if verify {
+            self::verify(self)?;
+        }
+        for pass in passes {
+            pass(self);
+            if verify {
+                self::verify(self)?;
+            }
+        }
+        Ok(())
     }
 
     /// Lowers the module to vcode using the given instruction selector.
     /// The instruction selector may be defined outside of this crate and used,
     /// as long as you implement the `InstrSelector` trait for it and define
     /// registers avaliable for use.
+    ///
+    /// Aggregate values must have been moved to memory, as the mandatory
+    /// transforms do.
     pub fn lower_to_vcode<
         I: VCodeInstr,
         S: InstrSelector<Instr = I> + Default,
@@ -63,9 +102,11 @@ impl Module {
         let mut gen = VCodeGenerator::new();
         let mut selector = S::default();
         for func in self.functions.iter() {
-            let args = (0..func.args.len()).map(|a| ValueId(a)).collect();
+            let args = (0..func.args.len()).map(ValueId).collect();
             let f = gen.push_function(&func.name, func.linkage, args);
             gen.switch_to_func(f);
+            gen.reserve_vregs(func.values.len());
+            selector.switch_to_function(func);
 
             let init = gen.push_block();
             gen.switch_to_block(init);
@@ -84,6 +125,7 @@ impl Module {
             selector.get_post_function_instructions(&mut gen);
         }
         let mut v = gen.build();
+        v.globals = self.globals.clone();
         let mut regalloc = R::default();
         for func in v.functions.iter_mut() {
             for block in &func.instrs {
@@ -119,6 +161,7 @@ pub struct Function {
     pub(crate) variables: Vec<Variable>,
     pub(crate) id: usize,
     pub(crate) values: Vec<Value>,
+    pub(crate) attributes: Vec<Attribute>,
 }
 
 impl Function {
@@ -150,8 +193,9 @@ impl Function {
                 variables,
                 id,
                 values,
+                attributes: vec![],
             },
-            (0..arg_len).map(|a| ValueId(a)).collect(),
+            (0..arg_len).map(ValueId).collect(),
         )
     }
 
@@ -169,50 +213,25 @@ impl Function {
         ValueId(id)
     }
 
+    /// Replaces every use of `original` with `to_replace_to`. To replace many
+    /// values, `replace_uses` does it in a single walk over the function.
     pub(crate) fn replace_children_with(&mut self, original: ValueId, to_replace_to: ValueId) {
         for bb in self.blocks.iter_mut() {
-            for instr in bb.instructions.iter_mut() {
-                match &mut instr.operation {
-                    Operation::BinOp(_, ref mut lhs, ref mut rhs) => {
-                        if *lhs == original {
-                            *lhs = to_replace_to;
-                        }
-                        if *rhs == original {
-                            *rhs = to_replace_to;
-                        }
-                    }
-                    Operation::Call(_, ref mut args) => {
-                        for arg in args.iter_mut() {
-                            if *arg == original {
-                                *arg = to_replace_to;
-                            }
-                        }
-                    }
-                    Operation::StoreVar(.., ref mut val) => {
-                        if *val == original {
-                            *val = to_replace_to;
-                        }
-                    }
-                    Operation::Phi(ref mut vals) => {
-                        vals.iter_mut().for_each(|val| {
-                            if *val == original {
-                                *val = to_replace_to;
-                            }
-                        });
-                    }
-                    _ => (),
+            let uses = bb
+                .instructions
+                .iter_mut()
+                .flat_map(|instr| instr.operation.operands_mut())
+                .chain(bb.par_moves.iter_mut().map(|(_, src)| src));
+            for val in uses {
+                if *val == original {
+                    *val = to_replace_to;
                 }
             }
             match bb.terminator {
-                Terminator::Return(ref mut val) => {
-                    if *val == original {
-                        *val = to_replace_to;
-                    }
-                }
-                Terminator::Branch(ref mut val, ..) => {
-                    if *val == original {
-                        *val = to_replace_to;
-                    }
+                Terminator::Return(ref mut val) | Terminator::Branch(ref mut val, ..)
+                    if *val == original =>
+                {
+                    *val = to_replace_to;
                 }
                 _ => (),
             }
@@ -222,11 +241,177 @@ impl Function {
         self.values[original.0].children.clear();
     }
 
+    /// Replaces every use of a key of `map` with its value, following chains
+    /// of replacements, and recomputes the children of all values.
+    pub(crate) fn replace_uses(&mut self, map: &HashMap<ValueId, ValueId>) {
+        let resolve = |mut val: ValueId| {
+            while let Some(to) = map.get(&val) {
+                if *to == val {
+                    break;
+                }
+                val = *to;
+            }
+            val
+        };
+        for bb in self.blocks.iter_mut() {
+            for instr in bb.instructions.iter_mut() {
+                for val in instr.operation.operands_mut() {
+                    *val = resolve(*val);
+                }
+            }
+            for (_, src) in bb.par_moves.iter_mut() {
+                *src = resolve(*src);
+            }
+            match bb.terminator {
+                Terminator::Return(ref mut val) | Terminator::Branch(ref mut val, ..) => {
+                    *val = resolve(*val);
+                }
+                _ => (),
+            }
+        }
+        self.rebuild_children();
+    }
+
+    /// Returns the values defined once, by an `Integer`. After phi removal,
+    /// values may be defined several times and only these have a known value.
+    pub(crate) fn constants(&self) -> HashMap<ValueId, i64> {
+        let mut defs = vec![0; self.values.len()];
+        let mut constants = HashMap::new();
+        for block in self.blocks.iter() {
+            for instr in block.instructions.iter() {
+                let Some(val) = instr.yielded else {
+                    continue;
+                };
+                defs[val.0] += 1;
+                if let Operation::Integer(c) = instr.operation {
+                    constants.insert(val, c);
+                }
+            }
+            for (dst, _) in block.par_moves.iter() {
+                defs[dst.0] += 1;
+            }
+        }
+        constants.retain(|val, _| defs[val.0] == 1);
+        constants
+    }
+
+    /// Recomputes `Value::children` from the operands of every instruction.
+    pub(crate) fn rebuild_children(&mut self) {
+        for val in self.values.iter_mut() {
+            val.children.clear();
+        }
+        for bb in self.blocks.iter() {
+            for instr in bb.instructions.iter() {
+                if let Some(yielded) = instr.yielded {
+                    for op in instr.operation.operands() {
+                        if let Some(val) = self.values.get_mut(op.0) {
+                            val.children.push(yielded);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes every block `b` with `dead[b.0]` set and renumbers the rest,
+    /// dropping the edges (preds and Φ operands) coming from removed blocks.
+    /// The removed blocks must not be jumped to by the remaining ones.
+    pub(crate) fn remove_blocks(&mut self, dead: &[bool]) {
+        let mut new_ids = Vec::with_capacity(self.blocks.len());
+        let mut next = 0;
+        for b in 0..self.blocks.len() {
+            if dead.get(b).copied().unwrap_or(false) {
+                new_ids.push(None);
+            } else {
+                new_ids.push(Some(BlockId(next)));
+                next += 1;
+            }
+        }
+        let remap = |b: BlockId| new_ids.get(b.0).copied().flatten();
+
+        let blocks = std::mem::take(&mut self.blocks);
+        for mut block in blocks {
+            let Some(id) = remap(BlockId(block.id)) else {
+                continue;
+            };
+            block.id = id.0;
+            block.preds = block.preds.iter().filter_map(|p| remap(*p)).collect();
+            for instr in block.instructions.iter_mut() {
+                if let Operation::Phi(ref mut vals) = instr.operation {
+                    *vals = vals
+                        .iter()
+                        .filter_map(|(b, v)| remap(*b).map(|b| (b, *v)))
+                        .collect();
+                }
+            }
+            match block.terminator {
+                Terminator::Jump(ref mut b) => *b = remap(*b).unwrap(),
+                Terminator::Branch(_, ref mut t, ref mut f) => {
+                    *t = remap(*t).unwrap();
+                    *f = remap(*f).unwrap();
+                }
+                Terminator::Return(_) | Terminator::NoTerm => {}
+            }
+            self.blocks.push(block);
+        }
+        for val in self.values.iter_mut() {
+            val.owner = remap(val.owner).unwrap_or(BlockId(0));
+        }
+        for var in self.variables.iter_mut() {
+            var.bbs_assign_to = var.bbs_assign_to.iter().filter_map(|b| remap(*b)).collect();
+        }
+        self.rebuild_children();
+    }
+
     pub fn replace_instruction(&mut self, block: BlockId, instr: usize, new_instr: Instruction) {
         self.blocks[block.0].instructions[instr] = new_instr;
     }
 }
 
+/// A variable of the module, in memory for the whole run of the program and
+/// accessed through the pointer yielded by `Operation::GlobalAddr`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Global {
+    pub name: String,
+    pub(crate) ty: Type,
+    pub(crate) linkage: Linkage,
+    /// The value the global starts out with, zeros if `None`. External
+    /// globals are defined elsewhere and never have one.
+    pub(crate) init: Option<Constant>,
+    /// Whether the global may be stored to. Loads of immutable globals can be
+    /// replaced with their initializer.
+    pub(crate) mutable: bool,
+}
+
+/// The initial value of a global.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Constant {
+    Integer(i64),
+    /// The elements of an array, written `[c, ...]`.
+    Array(Vec<Constant>),
+    /// The fields of a struct, written `{c, ...}`.
+    Struct(Vec<Constant>),
+}
+
+impl Constant {
+    /// Returns whether the constant is a value of type `ty`: an integer for
+    /// an integer or pointer, and one constant of the right type per element
+    /// or field for an array or struct.
+    pub fn has_type(&self, ty: &Type) -> bool {
+        match (self, ty) {
+            (Constant::Integer(_), Type::Integer(..) | Type::Pointer(_)) => true,
+            (Constant::Array(elems), Type::Array(elem, len)) => {
+                elems.len() == *len && elems.iter().all(|c| c.has_type(elem))
+            }
+            (Constant::Struct(consts), Type::Struct(fields)) => {
+                consts.len() == fields.len()
+                    && consts.iter().zip(fields.iter()).all(|(c, ty)| c.has_type(ty))
+            }
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Variable {
     pub(crate) name: String,
@@ -249,6 +434,10 @@ pub enum Type {
     Void,
     Integer(usize, bool),
     Pointer(Box<Type>),
+    /// A fixed number of elements of a type, written `[ty; len]`.
+    Array(Box<Type>, usize),
+    /// Fields of the given types, in order, written `{ty, ...}`.
+    Struct(Vec<Type>),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -268,6 +457,34 @@ pub enum Terminator {
     NoTerm,
 }
 
+impl Terminator {
+    /// Returns the blocks this terminator may jump to.
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(b) => vec![*b],
+            Terminator::Branch(_, t, f) => vec![*t, *f],
+            Terminator::Return(_) | Terminator::NoTerm => vec![],
+        }
+    }
+}
+
+/// Hints about a function for the optimizer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Attribute {
+    /// Inline the function wherever possible, regardless of its size.
+    AlwaysInline,
+    NoInline,
+}
+
+impl Display for Attribute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Attribute::AlwaysInline => write!(f, "alwaysinline"),
+            Attribute::NoInline => write!(f, "noinline"),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Linkage {
     Public,
@@ -299,23 +516,158 @@ pub struct Instruction {
 pub enum Operation {
     Integer(i64),
     BinOp(BinOp, ValueId, ValueId),
+    UnOp(UnOp, ValueId),
     Call(FunctionId, Vec<ValueId>),
     LoadVar(VariableId),
     StoreVar(VariableId, ValueId),
-    Phi(Vec<ValueId>),
+    /// Reserves memory for a value of the given type in the stack frame of
+    /// the function, yielding a pointer to it.
+    Alloca(Type),
+    /// Reads the value a pointer points to.
+    Load(ValueId),
+    /// Writes the value of its second operand to the pointer in its first.
+    Store(ValueId, ValueId),
+    /// The address of the element at an index of an array of the given type
+    /// starting at a pointer: `base + index * size`, the size coming from
+    /// the `DataLayout` of the target. Yields a pointer to the element type.
+    ElementPtr(ValueId, ValueId, Type),
+    /// The address of a global, a pointer to its type.
+    GlobalAddr(GlobalId),
+    /// The address of a field of the struct, or an element of the array, a
+    /// pointer points to.
+    FieldPtr(ValueId, usize),
+    /// A field of a struct, or an element of an array, value.
+    ///
+    /// Like loads and stores of them, this keeps aggregates in registers,
+    /// which the backends don't support: `algos::lower_aggregates` moves them
+    /// to memory, copying them member by member, so a front-end should
+    /// rather use `FieldPtr` unless the optimizer can get rid of them.
+    ExtractValue(ValueId, usize),
+    /// The aggregate value of its first operand with a field or element
+    /// replaced by its second operand.
+    InsertValue(ValueId, ValueId, usize),
+    /// One incoming value per predecessor of the block.
+    Phi(Vec<(BlockId, ValueId)>),
+    /// A value that may be anything, such as a variable read before any store.
+    Undef,
+    Copy(ValueId),
+    /// Widens an integer to the yielded type, filling the new bits with zeros.
+    ZExt(ValueId),
+    /// Widens an integer to the yielded type, filling the new bits with its
+    /// sign bit.
+    SExt(ValueId),
+    /// Narrows an integer to the yielded type, dropping its upper bits.
+    Trunc(ValueId),
+    PtrToInt(ValueId),
+    IntToPtr(ValueId),
 }
 
+impl Operation {
+    /// Returns the values used by the operation, in order of appearance.
+    pub fn operands(&self) -> Vec<ValueId> {
+        match self {
+            Operation::Integer(_)
+            | Operation::LoadVar(_)
+            | Operation::Alloca(_)
+            | Operation::GlobalAddr(_)
+            | Operation::Undef => vec![],
+            Operation::BinOp(_, lhs, rhs)
+            | Operation::Store(lhs, rhs)
+            | Operation::ElementPtr(lhs, rhs, _)
+            | Operation::InsertValue(lhs, rhs, _) => vec![*lhs, *rhs],
+            Operation::Call(_, args) => args.clone(),
+            Operation::StoreVar(_, val)
+            | Operation::Load(val)
+            | Operation::FieldPtr(val, _)
+            | Operation::ExtractValue(val, _)
+            | Operation::UnOp(_, val)
+            | Operation::Copy(val)
+            | Operation::ZExt(val)
+            | Operation::SExt(val)
+            | Operation::Trunc(val)
+            | Operation::PtrToInt(val)
+            | Operation::IntToPtr(val) => vec![*val],
+            Operation::Phi(vals) => vals.iter().map(|(_, val)| *val).collect(),
+        }
+    }
+
+    pub fn is_cast(&self) -> bool {
+        matches!(
+            self,
+            Operation::ZExt(_)
+                | Operation::SExt(_)
+                | Operation::Trunc(_)
+                | Operation::PtrToInt(_)
+                | Operation::IntToPtr(_)
+        )
+    }
+
+    /// Computes the integer cast `self` of a constant of type `from` to type
+    /// `to`, the constants being the values of `Operation::Integer`s.
+    pub(crate) fn fold_cast(&self, from: &Type, to: &Type, value: i64) -> Option<i64> {
+        let Type::Integer(width, signed) = *to else {
+            return None;
+        };
+        let int = APInt::from_constant(from, value)?;
+        if width == 0 || width > APInt::MAX_WIDTH {
+            return None;
+        }
+        let result = match self {
+            Operation::ZExt(_) => int.zext(width),
+            Operation::SExt(_) => int.sext(width),
+            Operation::Trunc(_) => int.trunc(width),
+            _ => return None,
+        };
+        Some(result.to_i64(signed))
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut ValueId> {
+        match self {
+            Operation::Integer(_)
+            | Operation::LoadVar(_)
+            | Operation::Alloca(_)
+            | Operation::GlobalAddr(_)
+            | Operation::Undef => vec![],
+            Operation::BinOp(_, lhs, rhs)
+            | Operation::Store(lhs, rhs)
+            | Operation::ElementPtr(lhs, rhs, _)
+            | Operation::InsertValue(lhs, rhs, _) => vec![lhs, rhs],
+            Operation::Call(_, args) => args.iter_mut().collect(),
+            Operation::StoreVar(_, val)
+            | Operation::Load(val)
+            | Operation::FieldPtr(val, _)
+            | Operation::ExtractValue(val, _)
+            | Operation::UnOp(_, val)
+            | Operation::Copy(val)
+            | Operation::ZExt(val)
+            | Operation::SExt(val)
+            | Operation::Trunc(val)
+            | Operation::PtrToInt(val)
+            | Operation::IntToPtr(val) => vec![val],
+            Operation::Phi(vals) => vals.iter_mut().map(|(_, val)| val).collect(),
+        }
+    }
+}
+
+/// Operations whose result depends on how the operands are read (see
+/// `depends_on_signedness`) are signed if the operands have a signed integer
+/// type, and unsigned for unsigned integers and pointers.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum BinOp {
     Add,
     Sub,
     Mul,
+    /// The upper half of the unsigned product of the operands.
+    MulHi,
+    /// Division rounding towards zero.
     Div,
+    /// The remainder of `Div`, with the sign of the dividend.
     Mod,
     And,
     Or,
     Xor,
     Shl,
+    /// Arithmetic right shift if signed, logical otherwise.
     Shr,
     Eq,
     Ne,
@@ -326,24 +678,126 @@ pub enum BinOp {
 }
 
 impl BinOp {
-    pub(crate) fn operate(&self, a: i64, b: i64) -> Option<i64> {
+    /// Returns whether `a op b == b op a`.
+    pub fn is_commutative(&self) -> bool {
+        matches!(
+            self,
+            Self::Add | Self::Mul | Self::And | Self::Or | Self::Xor | Self::Eq | Self::Ne
+        )
+    }
+
+    /// Returns the operation giving the same result with the operands
+    /// swapped, if there is one.
+    pub fn swapped(&self) -> Option<BinOp> {
+        match self {
+            _ if self.is_commutative() => Some(*self),
+            Self::Lt => Some(Self::Gt),
+            Self::Le => Some(Self::Ge),
+            Self::Gt => Some(Self::Lt),
+            Self::Ge => Some(Self::Le),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the operation gives different results for signed and
+    /// unsigned operands.
+    pub fn depends_on_signedness(&self) -> bool {
+        matches!(
+            self,
+            Self::Div | Self::Mod | Self::Shr | Self::Lt | Self::Le | Self::Gt | Self::Ge
+        )
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge
+        )
+    }
+
+    /// Computes `a op b`, the signedness of the operands' type picking the
+    /// kind of division, right shift and comparison. Comparisons yield a
+    /// 1 bit integer. Returns `None` if the result is undefined (division by
+    /// zero) or can't be computed.
+    pub fn evaluate(&self, a: &APInt, b: &APInt, signed: bool) -> Option<APInt> {
+        Some(match self {
+            Self::Add => a.add(b),
+            Self::Sub => a.sub(b),
+            Self::Mul => a.mul(b),
+            Self::MulHi => a.mul_hi(b)?,
+            Self::Div if signed => a.sdiv(b)?,
+            Self::Div => a.udiv(b)?,
+            Self::Mod if signed => a.srem(b)?,
+            Self::Mod => a.urem(b)?,
+            Self::And => a.and(b),
+            Self::Or => a.or(b),
+            Self::Xor => a.xor(b),
+            Self::Shl => a.shl(b),
+            Self::Shr if signed => a.ashr(b),
+            Self::Shr => a.lshr(b),
+            Self::Eq => APInt::from_bool(a == b),
+            Self::Ne => APInt::from_bool(a != b),
+            Self::Lt | Self::Le | Self::Gt | Self::Ge => {
+                let lt = |x: &APInt, y: &APInt| if signed { x.slt(y) } else { x.ult(y) };
+                APInt::from_bool(match self {
+                    Self::Lt => lt(a, b),
+                    Self::Le => !lt(b, a),
+                    Self::Gt => lt(b, a),
+                    _ => !lt(a, b),
+                })
+            }
+        })
+    }
+
+    /// `evaluate` on the values of `Operation::Integer`s whose type is `ty`,
+    /// returning the value of the resulting `Operation::Integer`.
+    pub(crate) fn operate(&self, ty: &Type, a: i64, b: i64) -> Option<i64> {
+        let Type::Integer(_, signed) = *ty else {
+            return None;
+        };
+        let a = APInt::from_constant(ty, a)?;
+        let b = APInt::from_constant(ty, b)?;
+        let result = self.evaluate(&a, &b, signed)?;
+        Some(result.to_i64(signed && !self.is_comparison()))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum UnOp {
+    /// Two's complement negation.
+    Neg,
+    /// Bitwise complement.
+    Not,
+    /// 1 if the operand is zero, 0 otherwise, as a 1 bit integer.
+    LNot,
+}
+
+impl UnOp {
+    pub fn evaluate(&self, a: &APInt) -> APInt {
         match self {
-            Self::Add => Some(a.wrapping_add(b)),
-            Self::Sub => Some(a.wrapping_sub(b)),
-            Self::Mul => Some(a.wrapping_mul(b)),
-            Self::Div => a.checked_div(b),
-            Self::Mod => a.checked_rem(b),
-            Self::And => Some(a & b),
-            Self::Or => Some(a | b),
-            Self::Xor => Some(a ^ b),
-            Self::Shl => Some(a << b),
-            Self::Shr => Some(a >> b),
-            Self::Eq => Some((a == b) as _),
-            Self::Ne => Some((a != b) as _),
-            Self::Lt => Some((a < b) as _),
-            Self::Le => Some((a <= b) as _),
-            Self::Gt => Some((a > b) as _),
-            Self::Ge => Some((a >= b) as _),
+            Self::Neg => a.neg(),
+            Self::Not => a.not(),
+            Self::LNot => APInt::from_bool(a.is_zero()),
+        }
+    }
+
+    /// `evaluate` on the value of an `Operation::Integer` whose type is `ty`,
+    /// returning the value of the resulting `Operation::Integer`.
+    pub(crate) fn operate(&self, ty: &Type, a: i64) -> Option<i64> {
+        let Type::Integer(_, signed) = *ty else {
+            return None;
+        };
+        let result = self.evaluate(&APInt::from_constant(ty, a)?);
+        Some(result.to_i64(signed && *self != Self::LNot))
+    }
+}
+
+impl Display for UnOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnOp::Neg => write!(f, "neg"),
+            UnOp::Not => write!(f, "not"),
+            UnOp::LNot => write!(f, "lnot"),
         }
     }
 }
@@ -354,6 +808,7 @@ impl Display for BinOp {
             BinOp::Add => write!(f, "add"),
             BinOp::Sub => write!(f, "sub"),
             BinOp::Mul => write!(f, "mul"),
+            BinOp::MulHi => write!(f, "mulhi"),
             BinOp::Div => write!(f, "div"),
             BinOp::Mod => write!(f, "mod"),
             BinOp::And => write!(f, "and"),
@@ -376,6 +831,8 @@ pub struct BlockId(pub(crate) usize);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FunctionId(pub(crate) usize);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GlobalId(pub(crate) usize);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct VariableId(pub(crate) usize);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ValueId(pub(crate) usize);
@@ -394,6 +851,13 @@ impl Deref for FunctionId {
     }
 }
 
+impl Deref for GlobalId {
+    type Target = usize;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
 impl Deref for VariableId {
     type Target = usize;
     fn deref(&self) -> &Self::Target {
@@ -410,7 +874,11 @@ impl Deref for ValueId {
 
 impl Display for Module {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "/* {:?} module {} */", self.algos_run, self.name)?;
+        writeln!(f, "/* {:?} module {} */", self.algos_run, Name(&self.name))?;
+
+        for (i, global) in self.globals.iter().enumerate() {
+            writeln!(f, "@{}: {}", i, global)?;
+        }
 
         for func in &self.functions {
             writeln!(f, "{}", func)?;
@@ -420,6 +888,25 @@ impl Display for Module {
     }
 }
 
+/// A module, function, global, argument or variable name as the printer
+/// writes it: as is if it's an identifier, quoted and escaped otherwise, so
+/// the parser can read it back.
+pub(crate) struct Name<'a>(pub(crate) &'a str);
+
+impl Display for Name<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.0.is_empty() && self.0.chars().all(is_ident_char) {
+            write!(f, "{}", self.0)
+        } else {
+            write!(f, "{:?}", self.0)
+        }
+    }
+}
+
+pub(crate) fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.'
+}
+
 impl Debug for Algo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -435,6 +922,9 @@ impl Debug for Algo {
             Algo::LowerParMoves => {
                 write!(f, "@par_moves_lowered")
             }
+            Algo::AggregateLowering => {
+                write!(f, "@aggregates_lowered")
+            }
         }
     }
 }
@@ -443,20 +933,28 @@ impl Display for Function {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(
             f,
-            "${}: {} fn {}({}) {} {{",
+            "${}: {} fn {}({}) {}{} {{",
             self.id,
             self.linkage,
-            self.name,
+            Name(&self.name),
             self.args
                 .iter()
-                .map(|e| format!("{}: {}", e.0, e.1))
+                .map(|e| format!("{}: {}", Name(&e.0), e.1))
                 .collect::<Vec<String>>()
                 .join(", "),
-            self.ret_type
+            self.ret_type,
+            self.attributes
+                .iter()
+                .map(|a| format!(" {a}"))
+                .collect::<String>()
         )?;
 
+        for (i, var) in self.variables.iter().enumerate() {
+            writeln!(f, "    var #{} {}: {}", i, Name(&var.name), var.ty)?;
+        }
+
         for block in &self.blocks {
-            write!(f, "{}", block)?;
+            block.fmt_with_values(f, Some(&self.values))?;
         }
 
         write!(f, "}}")?;
@@ -464,6 +962,63 @@ impl Display for Function {
     }
 }
 
+impl Type {
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Type::Integer(_, true))
+    }
+
+    pub fn is_aggregate(&self) -> bool {
+        matches!(self, Type::Array(..) | Type::Struct(_))
+    }
+
+    /// Returns the type of field `index` of a struct or element `index` of an
+    /// array, or `None` if there's no such field or element.
+    pub fn member(&self, index: usize) -> Option<&Type> {
+        match self {
+            Type::Array(elem, len) if index < *len => Some(elem),
+            Type::Struct(fields) => fields.get(index),
+            _ => None,
+        }
+    }
+
+    /// Returns `value` the way an `Operation::Integer` of this type holds it:
+    /// truncated to the width, then sign or zero extended.
+    pub(crate) fn normalize(&self, value: i64) -> i64 {
+        match (self, APInt::from_constant(self, value)) {
+            (Type::Integer(_, signed), Some(int)) => int.to_i64(*signed),
+            _ => value,
+        }
+    }
+}
+
+impl Display for Global {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.mutable { "global" } else { "const" };
+        write!(f, "{} {} {}: {}", self.linkage, kind, Name(&self.name), self.ty)?;
+        if let Some(init) = &self.init {
+            write!(f, " = {}", init)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Constant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |consts: &[Constant]| {
+            consts
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        match self {
+            Constant::Integer(val) => write!(f, "{}", val),
+            Constant::Array(elems) => write!(f, "[{}]", join(elems)),
+            Constant::Struct(fields) => write!(f, "{{{}}}", join(fields)),
+        }
+    }
+}
+
 impl Display for Type {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -472,6 +1027,16 @@ impl Display for Type {
                 write!(f, "{}{}", if *signed { "s" } else { "u" }, size)?
             }
             Type::Pointer(ty) => write!(f, "{}*", ty)?,
+            Type::Array(elem, len) => write!(f, "[{}; {}]", elem, len)?,
+            Type::Struct(fields) => write!(
+                f,
+                "{{{}}}",
+                fields
+                    .iter()
+                    .map(|t| t.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )?,
         }
         Ok(())
     }
@@ -479,6 +1044,18 @@ impl Display for Type {
 
 impl Display for BasicBlock {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_with_values(f, None)
+    }
+}
+
+impl BasicBlock {
+    /// Prints the block, annotating yielded values with their types if the
+    /// values of the owning function are given.
+    fn fmt_with_values(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        values: Option<&[Value]>,
+    ) -> std::fmt::Result {
         writeln!(
             f,
             "${}: ; preds = {}",
@@ -490,7 +1067,12 @@ impl Display for BasicBlock {
                 .join(", ")
         )?;
         for instr in &self.instructions {
-            writeln!(f, "    {}", instr)?;
+            match (instr.yielded, values) {
+                (Some(val), Some(values)) => {
+                    writeln!(f, "    {}: {} = {}", val, values[val.0].ty, instr.operation)?
+                }
+                _ => writeln!(f, "    {}", instr)?,
+            }
         }
         if !self.par_moves.is_empty() {
             let tmp = self
@@ -538,6 +1120,12 @@ impl Display for ValueId {
     }
 }
 
+impl Display for GlobalId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "@{}", self.0)
+    }
+}
+
 impl Display for BlockId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "${}", self.0)
@@ -548,6 +1136,7 @@ impl Display for Operation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Operation::BinOp(op, lhs, rhs) => write!(f, "{} {} {}", op, lhs, rhs)?,
+            Operation::UnOp(op, val) => write!(f, "{} {}", op, val)?,
             Operation::Call(func, args) => write!(
                 f,
                 "call ${}({})",
@@ -559,12 +1148,31 @@ impl Display for Operation {
             )?,
             Operation::LoadVar(var) => write!(f, "load #{}", var.0)?,
             Operation::StoreVar(var, val) => write!(f, "store #{} {}", var.0, val)?,
+            Operation::Alloca(ty) => write!(f, "alloca {}", ty)?,
+            Operation::Load(ptr) => write!(f, "load {}", ptr)?,
+            Operation::Store(ptr, val) => write!(f, "store {} {}", ptr, val)?,
+            Operation::ElementPtr(base, index, ty) => {
+                write!(f, "elementptr {} {} {}", ty, base, index)?
+            }
+            Operation::GlobalAddr(global) => write!(f, "globaladdr {}", global)?,
+            Operation::FieldPtr(base, index) => write!(f, "fieldptr {} {}", base, index)?,
+            Operation::ExtractValue(agg, index) => write!(f, "extractvalue {} {}", agg, index)?,
+            Operation::InsertValue(agg, val, index) => {
+                write!(f, "insertvalue {} {} {}", agg, val, index)?
+            }
             Operation::Integer(val) => write!(f, "{}", val)?,
+            Operation::Undef => write!(f, "undef")?,
+            Operation::Copy(val) => write!(f, "copy {}", val)?,
+            Operation::ZExt(val) => write!(f, "zext {}", val)?,
+            Operation::SExt(val) => write!(f, "sext {}", val)?,
+            Operation::Trunc(val) => write!(f, "trunc {}", val)?,
+            Operation::PtrToInt(val) => write!(f, "ptrtoint {}", val)?,
+            Operation::IntToPtr(val) => write!(f, "inttoptr {}", val)?,
             Operation::Phi(vals) => write!(
                 f,
                 "Φ {}",
                 vals.iter()
-                    .map(|val| format!("{}", val))
+                    .map(|(block, val)| format!("[{}, {}]", block, val))
                     .collect::<Vec<String>>()
                     .join(", ")
             )?,
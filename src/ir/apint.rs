@@ -0,0 +1,238 @@
+//! Fixed width integer arithmetic for evaluating operations at compile time.
+//!
+//! An `APInt` is a bit pattern of a given width, with no signedness of its
+//! own: like in the IR, the operations that care (division, right shifts,
+//! comparisons) come in a signed and an unsigned flavour. Every result is
+//! truncated to the width of the operands, so arithmetic wraps the way it does
+//! on the target.
+
+use super::Type;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct APInt {
+    width: usize,
+    /// the value, zero extended
+    bits: u128,
+}
+
+impl APInt {
+    pub const MAX_WIDTH: usize = 128;
+
+    /// Makes an integer of `width` bits from the low bits of `bits`.
+    ///
+    /// # Panics
+    /// If `width` is 0 or more than `APInt::MAX_WIDTH`.
+    pub fn new(width: usize, bits: u128) -> APInt {
+        assert!(
+            width > 0 && width <= Self::MAX_WIDTH,
+            "invalid integer width {width}"
+        );
+        APInt {
+            width,
+            bits: bits & Self::mask(width),
+        }
+    }
+
+    /// Makes an integer of `width` bits from the low bits of `value`, sign
+    /// extended first if `width` is more than 64.
+    pub fn from_i64(width: usize, value: i64) -> APInt {
+        APInt::new(width, value as i128 as u128)
+    }
+
+    /// Makes an integer of type `ty` from the value of an `Operation::Integer`,
+    /// or returns `None` if `ty` isn't an integer type `APInt` can hold.
+    pub fn from_constant(ty: &Type, value: i64) -> Option<APInt> {
+        match *ty {
+            Type::Integer(width, _) if width > 0 && width <= Self::MAX_WIDTH => {
+                Some(APInt::from_i64(width, value))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn zero(width: usize) -> APInt {
+        APInt::new(width, 0)
+    }
+
+    pub fn from_bool(b: bool) -> APInt {
+        APInt::new(1, b as u128)
+    }
+
+    fn mask(width: usize) -> u128 {
+        if width >= 128 {
+            u128::MAX
+        } else {
+            (1 << width) - 1
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the value read as unsigned.
+    pub fn zext_value(&self) -> u128 {
+        self.bits
+    }
+
+    /// Returns the value read as two's complement.
+    pub fn sext_value(&self) -> i128 {
+        let unused = 128 - self.width as u32;
+        ((self.bits << unused) as i128) >> unused
+    }
+
+    /// Returns the value as stored in an `Operation::Integer`: sign extended
+    /// if `signed`, zero extended otherwise, and truncated to 64 bits.
+    pub fn to_i64(&self, signed: bool) -> i64 {
+        if signed {
+            self.sext_value() as i64
+        } else {
+            self.bits as i64
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.bits == 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.bits >> (self.width - 1) & 1 == 1
+    }
+
+    fn with_bits(&self, bits: u128) -> APInt {
+        APInt::new(self.width, bits)
+    }
+
+    fn check_width(&self, other: &APInt) {
+        assert_eq!(self.width, other.width, "operands of different widths");
+    }
+
+    pub fn add(&self, other: &APInt) -> APInt {
+        self.check_width(other);
+        self.with_bits(self.bits.wrapping_add(other.bits))
+    }
+
+    pub fn sub(&self, other: &APInt) -> APInt {
+        self.check_width(other);
+        self.with_bits(self.bits.wrapping_sub(other.bits))
+    }
+
+    pub fn neg(&self) -> APInt {
+        self.with_bits(self.bits.wrapping_neg())
+    }
+
+    pub fn not(&self) -> APInt {
+        self.with_bits(!self.bits)
+    }
+
+    pub fn mul(&self, other: &APInt) -> APInt {
+        self.check_width(other);
+        self.with_bits(self.bits.wrapping_mul(other.bits))
+    }
+
+    /// Returns the upper half of the unsigned product, or `None` for integers
+    /// wider than 64 bits.
+    pub fn mul_hi(&self, other: &APInt) -> Option<APInt> {
+        self.check_width(other);
+        (self.width <= 64).then(|| self.with_bits((self.bits * other.bits) >> self.width))
+    }
+
+    /// Returns `None` when dividing by zero.
+    pub fn udiv(&self, other: &APInt) -> Option<APInt> {
+        self.check_width(other);
+        self.bits.checked_div(other.bits).map(|q| self.with_bits(q))
+    }
+
+    /// Returns `None` when dividing by zero. The quotient is rounded towards
+    /// zero, and dividing the smallest value by -1 wraps around.
+    pub fn sdiv(&self, other: &APInt) -> Option<APInt> {
+        self.check_width(other);
+        let q = self.sext_value().checked_div(other.sext_value()).or_else(|| {
+            // i128::MIN / -1 only overflows for 128 bit integers
+            (!other.is_zero()).then_some(i128::MIN)
+        })?;
+        Some(self.with_bits(q as u128))
+    }
+
+    /// Returns `None` when dividing by zero.
+    pub fn urem(&self, other: &APInt) -> Option<APInt> {
+        self.check_width(other);
+        self.bits.checked_rem(other.bits).map(|r| self.with_bits(r))
+    }
+
+    /// Returns `None` when dividing by zero. The remainder has the sign of
+    /// the dividend.
+    pub fn srem(&self, other: &APInt) -> Option<APInt> {
+        self.check_width(other);
+        if other.is_zero() {
+            return None;
+        }
+        let r = self.sext_value().checked_rem(other.sext_value()).unwrap_or(0);
+        Some(self.with_bits(r as u128))
+    }
+
+    pub fn and(&self, other: &APInt) -> APInt {
+        self.check_width(other);
+        self.with_bits(self.bits & other.bits)
+    }
+
+    pub fn or(&self, other: &APInt) -> APInt {
+        self.check_width(other);
+        self.with_bits(self.bits | other.bits)
+    }
+
+    pub fn xor(&self, other: &APInt) -> APInt {
+        self.check_width(other);
+        self.with_bits(self.bits ^ other.bits)
+    }
+
+    /// Shifts left by the unsigned value of `amount`, giving 0 if that's at
+    /// least the width.
+    pub fn shl(&self, amount: &APInt) -> APInt {
+        match self.shift_amount(amount) {
+            Some(s) => self.with_bits(self.bits << s),
+            None => self.with_bits(0),
+        }
+    }
+
+    /// Shifts right by the unsigned value of `amount`, filling with zeros.
+    pub fn lshr(&self, amount: &APInt) -> APInt {
+        match self.shift_amount(amount) {
+            Some(s) => self.with_bits(self.bits >> s),
+            None => self.with_bits(0),
+        }
+    }
+
+    /// Shifts right by the unsigned value of `amount`, filling with the sign
+    /// bit.
+    pub fn ashr(&self, amount: &APInt) -> APInt {
+        let s = self.shift_amount(amount).unwrap_or(self.width as u32 - 1);
+        self.with_bits((self.sext_value() >> s) as u128)
+    }
+
+    fn shift_amount(&self, amount: &APInt) -> Option<u32> {
+        (amount.bits < self.width as u128).then_some(amount.bits as u32)
+    }
+
+    pub fn ult(&self, other: &APInt) -> bool {
+        self.check_width(other);
+        self.bits < other.bits
+    }
+
+    pub fn slt(&self, other: &APInt) -> bool {
+        self.check_width(other);
+        self.sext_value() < other.sext_value()
+    }
+
+    pub fn trunc(&self, width: usize) -> APInt {
+        APInt::new(width, self.bits)
+    }
+
+    pub fn zext(&self, width: usize) -> APInt {
+        APInt::new(width, self.bits)
+    }
+
+    pub fn sext(&self, width: usize) -> APInt {
+        APInt::new(width, self.sext_value() as u128)
+    }
+}
@@ -0,0 +1,119 @@
+//! The sizes and alignments of types in the memory of a target.
+//!
+//! Memory is addressed in words of `word_bits` bits, so every size is a
+//! number of words: a value narrower than a word takes up a whole one, and a
+//! wider one takes up as many as it needs, least significant word first.
+//!
+//! Fields of structs are laid out in order, each at the next offset that is a
+//! multiple of its alignment, and the size of a struct is rounded up to its
+//! alignment, the largest of its fields', so the elements of an array of
+//! them stay aligned.
+
+use super::{Constant, Type};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataLayout {
+    /// The width of a word of memory, and of a pointer.
+    pub word_bits: usize,
+    /// The largest alignment of a scalar, in words. Scalars are aligned to
+    /// their size rounded up to a power of two, up to this.
+    pub max_scalar_align: usize,
+}
+
+impl DataLayout {
+    pub const fn new(word_bits: usize, max_scalar_align: usize) -> DataLayout {
+        DataLayout {
+            word_bits,
+            max_scalar_align,
+        }
+    }
+
+    /// Returns the number of words a value of type `ty` takes up.
+    pub fn size_of(&self, ty: &Type) -> usize {
+        match ty {
+            Type::Void => 0,
+            Type::Integer(width, _) => width.div_ceil(self.word_bits),
+            Type::Pointer(_) => 1,
+            Type::Array(elem, len) => self.size_of(elem) * len,
+            Type::Struct(fields) => {
+                let end = match fields.last() {
+                    Some(last) => self.field_offset(fields, fields.len() - 1) + self.size_of(last),
+                    None => 0,
+                };
+                end.next_multiple_of(self.align_of(ty))
+            }
+        }
+    }
+
+    /// Returns the alignment of type `ty`, in words.
+    pub fn align_of(&self, ty: &Type) -> usize {
+        match ty {
+            Type::Void | Type::Pointer(_) => 1,
+            Type::Integer(..) => self
+                .size_of(ty)
+                .next_power_of_two()
+                .min(self.max_scalar_align)
+                .max(1),
+            Type::Array(elem, _) => self.align_of(elem),
+            Type::Struct(fields) => fields.iter().map(|f| self.align_of(f)).max().unwrap_or(1),
+        }
+    }
+
+    /// Returns the offset of field `index` of a struct with the given fields
+    /// from its start, in words.
+    pub fn field_offset(&self, fields: &[Type], index: usize) -> usize {
+        let mut offset: usize = 0;
+        for field in fields[..index].iter() {
+            offset = offset.next_multiple_of(self.align_of(field)) + self.size_of(field);
+        }
+        offset.next_multiple_of(self.align_of(&fields[index]))
+    }
+
+    /// Returns the offset of member `index` of the struct or array `ty` from
+    /// its start, in words, or `None` if `ty` has no such member.
+    pub fn member_offset(&self, ty: &Type, index: usize) -> Option<usize> {
+        match ty {
+            Type::Array(elem, len) if index < *len => Some(self.size_of(elem) * index),
+            Type::Struct(fields) if index < fields.len() => Some(self.field_offset(fields, index)),
+            _ => None,
+        }
+    }
+
+    /// Returns the words of memory holding a value of type `ty` that starts
+    /// out as `init`, or as zeros if it has none. Padding is zeroed.
+    ///
+    /// # Panics
+    /// If `init` isn't a constant of type `ty`.
+    pub fn words(&self, ty: &Type, init: Option<&Constant>) -> Vec<u64> {
+        let mut words = vec![0; self.size_of(ty)];
+        if let Some(init) = init {
+            self.write_words(&mut words, ty, init);
+        }
+        words
+    }
+
+    fn write_words(&self, words: &mut [u64], ty: &Type, init: &Constant) {
+        match (ty, init) {
+            (Type::Integer(..) | Type::Pointer(_), Constant::Integer(val)) => {
+                let val = ty.normalize(*val) as i128;
+                let mask = u64::MAX >> (64 - self.word_bits.min(64));
+                for (i, word) in words[..self.size_of(ty)].iter_mut().enumerate() {
+                    *word = (val >> (i * self.word_bits).min(127)) as u64 & mask;
+                }
+            }
+            (Type::Array(elem, len), Constant::Array(elems)) if elems.len() == *len => {
+                let size = self.size_of(elem);
+                for (i, c) in elems.iter().enumerate() {
+                    self.write_words(&mut words[i * size..], elem, c);
+                }
+            }
+            (Type::Struct(fields), Constant::Struct(consts)) if consts.len() == fields.len() => {
+                for (i, (field, c)) in fields.iter().zip(consts.iter()).enumerate() {
+                    let offset = self.field_offset(fields, i);
+                    self.write_words(&mut words[offset..], field, c);
+                }
+            }
+            _ => panic!("`{}` is not a constant of type {}", init, ty),
+        }
+    }
+}
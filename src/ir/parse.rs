@@ -0,0 +1,1124 @@
+//! Parser for the textual form of the IR.
+//!
+//! The accepted syntax is the one produced by the `Display` impl of `Module`,
+//! so a printed module can be read back in:
+//!
+//! ```text
+//! /* [] module example */
+//! @0: public global counter: u16 = 0
+//! @1: private const table: [u16; 3] = [1, 2, 4]
+//! $0: public fn main(n: u16) u16 {
+//!     var #0 x: u16
+//! $0: ; preds =
+//!     %1: u16 = 1
+//!     store #0 %1
+//!     %2: u16 = load #0
+//!     %3: u16 = add %0 %2
+//!     %4: u16* = alloca u16
+//!     store %4 %3
+//!     %5: u16 = load %4
+//!     %6: u16* = globaladdr @0
+//!     store %6 %5
+//!     ret %5
+//! }
+//! ```
+//!
+//! To make hand-written IR less tedious, a few parts of the printed form are
+//! optional:
+//! - the `/* [...] module name */` header,
+//! - the `; preds = ...` list of a block, which is then computed from the
+//!   terminators of the function,
+//! - the type annotation of a yielded value, as long as it can be inferred
+//!   from its operands (constants and casts always need one).
+//!
+//! Lines may also contain `//` comments. Names that aren't identifiers are
+//! written as quoted strings with the escapes of Rust string literals, like
+//! `$0: public fn "a//b"() void {`.
+
+use std::{collections::HashSet, fmt::Display};
+
+use super::{
+    Algo, Attribute, BasicBlock, BinOp, BlockId, Constant, Function, FunctionId, Global, GlobalId,
+    is_ident_char, Instruction, Linkage, Module, Operation, Terminator, Type, UnOp, ValueId,
+    Variable, VariableId,
+};
+
+/// An error encountered while parsing, with a 1-based line and column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses a module from its textual form.
+pub fn parse(src: &str) -> Result<Module, ParseError> {
+    let mut parser = Parser {
+        name: String::new(),
+        algos_run: Vec::new(),
+        globals: Vec::new(),
+        functions: Vec::new(),
+        current: None,
+    };
+
+    for (i, line) in src.lines().enumerate() {
+        let mut cursor = Cursor {
+            src: line,
+            pos: 0,
+            line: i + 1,
+        };
+        if cursor.is_eof() {
+            continue;
+        }
+        parser.parse_line(&mut cursor)?;
+    }
+
+    if let Some(func) = parser.current.take() {
+        return Err(ParseError {
+            line: func.line,
+            column: 1,
+            message: format!("function `{}` is never closed", func.func.name),
+        });
+    }
+
+    parser.finish()
+}
+
+struct Parser {
+    name: String,
+    algos_run: Vec<Algo>,
+    globals: Vec<Global>,
+    functions: Vec<FunctionState>,
+    current: Option<FunctionState>,
+}
+
+/// A function being parsed, along with the info that is only known once the
+/// whole module has been read.
+struct FunctionState {
+    func: Function,
+    line: usize,
+    tys: Vec<Option<Type>>,
+    // position of the first definition of each value
+    defs: Vec<Option<(usize, usize)>>,
+    explicit_preds: Vec<bool>,
+    // position of every use of a function, global and block, which may be
+    // defined after it
+    called: Vec<(FunctionId, usize, usize)>,
+    globals_used: Vec<(GlobalId, usize, usize)>,
+    jumped_to: Vec<(BlockId, usize, usize)>,
+}
+
+impl Parser {
+    fn parse_line(&mut self, c: &mut Cursor) -> Result<(), ParseError> {
+        if self.current.is_none() {
+            if c.peek_str("/*") {
+                if !self.functions.is_empty() || !self.globals.is_empty() {
+                    return c.error("module header must come before any global or function");
+                }
+                return self.parse_header(c);
+            }
+            if c.peek_str("@") {
+                return self.parse_global(c);
+            }
+            let func = self.parse_fn_header(c)?;
+            self.current = Some(func);
+            return Ok(());
+        }
+
+        if c.eat("}") {
+            c.end()?;
+            let func = self.current.take().unwrap();
+            self.functions.push(func);
+            return Ok(());
+        }
+
+        let func = self.current.as_mut().unwrap();
+        if c.keyword("var") {
+            return func.parse_variable(c);
+        }
+        if c.peek_str("$") {
+            return func.parse_block_header(c);
+        }
+        if func.func.blocks.is_empty() {
+            return c.error("expected a block header before any instruction");
+        }
+        if c.peek_str("[") {
+            return func.parse_par_moves(c);
+        }
+        if let Some(term) = func.parse_terminator(c)? {
+            let block = func.func.blocks.last_mut().unwrap();
+            block.terminator = term;
+            return Ok(());
+        }
+        func.parse_instruction(c)
+    }
+
+    fn parse_header(&mut self, c: &mut Cursor) -> Result<(), ParseError> {
+        c.expect("/*")?;
+        c.expect("[")?;
+        if !c.eat("]") {
+            loop {
+                let col = c.start();
+                c.expect("@")?;
+                let algo = match c.ident()? {
+                    "edges_splitted" => Algo::CriticalEdgeSplitting,
+                    "phis_lowered" => Algo::PhiLowering,
+                    "phis_removed" => Algo::PhiRemoval,
+                    "par_moves_lowered" => Algo::LowerParMoves,
+                    "aggregates_lowered" => Algo::AggregateLowering,
+                    other => return c.error_at(col, format!("unknown algo `@{}`", other)),
+                };
+                self.algos_run.push(algo);
+                if c.eat("]") {
+                    break;
+                }
+                c.expect(",")?;
+            }
+        }
+        if !c.keyword("module") {
+            return c.expected("`module`");
+        }
+        if c.peek_str("\"") {
+            self.name = c.name()?;
+            c.expect("*/")?;
+            return c.end();
+        }
+        c.skip_ws();
+        let rest = c.rest();
+        let Some(end) = rest.find("*/") else {
+            return c.error("expected `*/`");
+        };
+        self.name = rest[..end].trim().to_string();
+        c.pos += end + 2;
+        c.end()
+    }
+
+    fn parse_global(&mut self, c: &mut Cursor) -> Result<(), ParseError> {
+        let col = c.start();
+        let id = c.sigil('@', "global id")?;
+        if id != self.globals.len() {
+            return c.error_at(
+                col,
+                format!("expected global @{}, found @{}", self.globals.len(), id),
+            );
+        }
+        c.expect(":")?;
+        let linkage = c.linkage()?;
+        let mutable = if c.keyword("global") {
+            true
+        } else if c.keyword("const") {
+            false
+        } else {
+            return c.expected("`global` or `const`");
+        };
+        let name = c.name()?;
+        c.expect(":")?;
+        let ty = c.ty()?;
+        let init = if c.eat("=") {
+            if linkage == Linkage::External {
+                return c.error(format!("external global `{}` can't have an initializer", name));
+            }
+            Some(c.constant(&ty)?)
+        } else {
+            None
+        };
+        c.end()?;
+        self.globals.push(Global {
+            name,
+            ty,
+            linkage,
+            init,
+            mutable,
+        });
+        Ok(())
+    }
+
+    fn parse_fn_header(&mut self, c: &mut Cursor) -> Result<FunctionState, ParseError> {
+        let col = c.start();
+        let id = c.sigil('$', "function id")?;
+        if id != self.functions.len() {
+            return c.error_at(
+                col,
+                format!("expected function ${}, found ${}", self.functions.len(), id),
+            );
+        }
+        c.expect(":")?;
+        let linkage = c.linkage()?;
+        if !c.keyword("fn") {
+            return c.expected("`fn`");
+        }
+        let name = c.name()?;
+        c.expect("(")?;
+        let mut args = Vec::new();
+        if !c.eat(")") {
+            loop {
+                let arg = c.name()?;
+                c.expect(":")?;
+                args.push((arg, c.ty()?));
+                if c.eat(")") {
+                    break;
+                }
+                c.expect(",")?;
+            }
+        }
+        let ret_type = c.ty()?;
+        let mut attributes = Vec::new();
+        loop {
+            if c.keyword("alwaysinline") {
+                attributes.push(Attribute::AlwaysInline);
+            } else if c.keyword("noinline") {
+                attributes.push(Attribute::NoInline);
+            } else {
+                break;
+            }
+        }
+        c.expect("{")?;
+        c.end()?;
+
+        let tys = args.iter().map(|a| Some(a.1.clone())).collect();
+        let defs = args.iter().map(|_| Some((c.line, col))).collect();
+        let (mut func, _) = Function::new(&name, ret_type, args, linkage, vec![], id);
+        func.attributes = attributes;
+        Ok(FunctionState {
+            func,
+            line: c.line,
+            tys,
+            defs,
+            explicit_preds: Vec::new(),
+            called: Vec::new(),
+            globals_used: Vec::new(),
+            jumped_to: Vec::new(),
+        })
+    }
+
+    fn finish(mut self) -> Result<Module, ParseError> {
+        for state in self.functions.iter() {
+            for &(f, line, column) in state.called.iter() {
+                if f.0 >= self.functions.len() {
+                    return Err(ParseError {
+                        line,
+                        column,
+                        message: format!("call to undefined function ${}", f.0),
+                    });
+                }
+            }
+            for &(g, line, column) in state.globals_used.iter() {
+                if g.0 >= self.globals.len() {
+                    return Err(ParseError {
+                        line,
+                        column,
+                        message: format!("use of undefined global {}", g),
+                    });
+                }
+            }
+        }
+
+        // infer the types of unannotated values until nothing changes anymore
+        let ret_types: Vec<Type> = self
+            .functions
+            .iter()
+            .map(|f| f.func.ret_type.clone())
+            .collect();
+        for state in self.functions.iter_mut() {
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for block in state.func.blocks.iter() {
+                    for instr in block.instructions.iter() {
+                        let Some(val) = instr.yielded else {
+                            continue;
+                        };
+                        if state.tys[val.0].is_some() {
+                            continue;
+                        }
+                        let ty = match &instr.operation {
+                            Operation::Call(f, _) => Some(ret_types[f.0].clone()),
+                            Operation::GlobalAddr(g) => self
+                                .globals
+                                .get(g.0)
+                                .map(|g| Type::Pointer(Box::new(g.ty.clone()))),
+                            Operation::LoadVar(var) => {
+                                Some(state.func.variables[var.0].ty.clone())
+                            }
+                            op if op.is_cast() => None,
+                            Operation::Alloca(ty) | Operation::ElementPtr(.., ty) => {
+                                Some(Type::Pointer(Box::new(ty.clone())))
+                            }
+                            Operation::Load(ptr) => match state.tys.get(ptr.0) {
+                                Some(Some(Type::Pointer(pointee))) => Some((**pointee).clone()),
+                                _ => None,
+                            },
+                            Operation::FieldPtr(base, index) => match state.tys.get(base.0) {
+                                Some(Some(Type::Pointer(pointee))) => pointee
+                                    .member(*index)
+                                    .map(|m| Type::Pointer(Box::new(m.clone()))),
+                                _ => None,
+                            },
+                            Operation::ExtractValue(agg, index) => state
+                                .tys
+                                .get(agg.0)
+                                .and_then(|t| t.as_ref()?.member(*index).cloned()),
+                            Operation::InsertValue(agg, ..) => {
+                                state.tys.get(agg.0).cloned().flatten()
+                            }
+                            op => op
+                                .operands()
+                                .iter()
+                                .find_map(|v| state.tys.get(v.0).cloned().flatten()),
+                        };
+                        if ty.is_some() {
+                            state.tys[val.0] = ty;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut functions = Vec::new();
+        for state in self.functions {
+            functions.push(state.finish()?);
+        }
+
+        let mut module = Module::new(&self.name, functions);
+        module.globals = self.globals;
+        module.algos_run = self.algos_run;
+        Ok(module)
+    }
+}
+
+impl FunctionState {
+    fn parse_variable(&mut self, c: &mut Cursor) -> Result<(), ParseError> {
+        let col = c.start();
+        let id = c.sigil('#', "variable")?;
+        if id != self.func.variables.len() {
+            return c.error_at(
+                col,
+                format!(
+                    "expected variable #{}, found #{}",
+                    self.func.variables.len(),
+                    id
+                ),
+            );
+        }
+        let name = c.name()?;
+        c.expect(":")?;
+        let ty = c.ty()?;
+        c.end()?;
+        self.func.variables.push(Variable {
+            name,
+            ty,
+            bbs_assign_to: HashSet::new(),
+        });
+        Ok(())
+    }
+
+    fn parse_block_header(&mut self, c: &mut Cursor) -> Result<(), ParseError> {
+        let col = c.start();
+        let id = c.sigil('$', "block")?;
+        if id != self.func.blocks.len() {
+            return c.error_at(
+                col,
+                format!("expected block ${}, found ${}", self.func.blocks.len(), id),
+            );
+        }
+        c.expect(":")?;
+
+        let mut preds = Vec::new();
+        let explicit = c.eat(";");
+        if explicit {
+            if !c.keyword("preds") {
+                return c.expected("`preds`");
+            }
+            c.expect("=")?;
+            if !c.is_eof() {
+                loop {
+                    preds.push(c.block()?);
+                    if !c.eat(",") {
+                        break;
+                    }
+                }
+            }
+        }
+        c.end()?;
+
+        self.func.blocks.push(BasicBlock {
+            instructions: vec![],
+            terminator: Terminator::NoTerm,
+            preds,
+            id,
+            par_moves: vec![],
+        });
+        self.explicit_preds.push(explicit);
+        Ok(())
+    }
+
+    fn parse_par_moves(&mut self, c: &mut Cursor) -> Result<(), ParseError> {
+        let col = c.start();
+        let dsts = self.parse_value_list(c)?;
+        c.expect("<-")?;
+        let srcs = self.parse_value_list(c)?;
+        c.end()?;
+        if dsts.len() != srcs.len() {
+            return c.error_at(
+                col,
+                format!(
+                    "parallel move has {} destinations but {} sources",
+                    dsts.len(),
+                    srcs.len()
+                ),
+            );
+        }
+        for &dst in dsts.iter() {
+            self.define(dst, None, c.line, col)?;
+        }
+        let block = self.func.blocks.last_mut().unwrap();
+        block.par_moves.extend(dsts.into_iter().zip(srcs));
+        Ok(())
+    }
+
+    /// Parses a `[%1, %2]` list, also accepting the quoted `["%1", "%2"]`
+    /// form printed for parallel moves.
+    fn parse_value_list(&mut self, c: &mut Cursor) -> Result<Vec<ValueId>, ParseError> {
+        c.expect("[")?;
+        let mut vals = Vec::new();
+        if c.eat("]") {
+            return Ok(vals);
+        }
+        loop {
+            let quoted = c.eat("\"");
+            vals.push(self.use_value(c)?);
+            if quoted {
+                c.expect("\"")?;
+            }
+            if c.eat("]") {
+                return Ok(vals);
+            }
+            c.expect(",")?;
+        }
+    }
+
+    fn parse_terminator(&mut self, c: &mut Cursor) -> Result<Option<Terminator>, ParseError> {
+        let term = if c.keyword("ret") {
+            Terminator::Return(self.use_value(c)?)
+        } else if c.keyword("jmp") {
+            Terminator::Jump(self.jump_target(c)?)
+        } else if c.keyword("br") {
+            let cond = self.use_value(c)?;
+            c.expect(",")?;
+            let t = self.jump_target(c)?;
+            c.expect(",")?;
+            let f = self.jump_target(c)?;
+            Terminator::Branch(cond, t, f)
+        } else if c.keyword("noterm") {
+            Terminator::NoTerm
+        } else {
+            return Ok(None);
+        };
+        c.end()?;
+        Ok(Some(term))
+    }
+
+    fn jump_target(&mut self, c: &mut Cursor) -> Result<BlockId, ParseError> {
+        let col = c.start();
+        let block = c.block()?;
+        self.jumped_to.push((block, c.line, col));
+        Ok(block)
+    }
+
+    fn parse_instruction(&mut self, c: &mut Cursor) -> Result<(), ParseError> {
+        let col = c.start();
+        let mut yielded = None;
+        if c.peek_str("%") {
+            let val = c.value()?;
+            let ty = if c.eat(":") { Some(c.ty()?) } else { None };
+            c.expect("=")?;
+            yielded = Some((val, ty));
+        }
+
+        let op_col = c.start();
+        let operation = if c.peek_str("-") || c.peek_digit() {
+            Operation::Integer(c.integer()?)
+        } else if c.keyword("undef") {
+            Operation::Undef
+        } else if c.keyword("copy") {
+            Operation::Copy(self.use_value(c)?)
+        } else if c.keyword("zext") {
+            Operation::ZExt(self.use_value(c)?)
+        } else if c.keyword("sext") {
+            Operation::SExt(self.use_value(c)?)
+        } else if c.keyword("trunc") {
+            Operation::Trunc(self.use_value(c)?)
+        } else if c.keyword("ptrtoint") {
+            Operation::PtrToInt(self.use_value(c)?)
+        } else if c.keyword("inttoptr") {
+            Operation::IntToPtr(self.use_value(c)?)
+        } else if c.keyword("call") {
+            let func_col = c.start();
+            let func = FunctionId(c.sigil('$', "function")?);
+            self.called.push((func, c.line, func_col));
+            c.expect("(")?;
+            let mut args = Vec::new();
+            if !c.eat(")") {
+                loop {
+                    args.push(self.use_value(c)?);
+                    if c.eat(")") {
+                        break;
+                    }
+                    c.expect(",")?;
+                }
+            }
+            Operation::Call(func, args)
+        } else if c.keyword("alloca") {
+            Operation::Alloca(c.ty()?)
+        } else if c.keyword("elementptr") {
+            let ty = c.ty()?;
+            let base = self.use_value(c)?;
+            Operation::ElementPtr(base, self.use_value(c)?, ty)
+        } else if c.keyword("globaladdr") {
+            let global_col = c.start();
+            let global = GlobalId(c.sigil('@', "global")?);
+            self.globals_used.push((global, c.line, global_col));
+            Operation::GlobalAddr(global)
+        } else if c.keyword("fieldptr") {
+            let base = self.use_value(c)?;
+            Operation::FieldPtr(base, c.number("index")?)
+        } else if c.keyword("extractvalue") {
+            let agg = self.use_value(c)?;
+            Operation::ExtractValue(agg, c.number("index")?)
+        } else if c.keyword("insertvalue") {
+            let agg = self.use_value(c)?;
+            let val = self.use_value(c)?;
+            Operation::InsertValue(agg, val, c.number("index")?)
+        } else if c.keyword("load") {
+            if c.peek_str("#") {
+                Operation::LoadVar(self.variable(c)?)
+            } else {
+                Operation::Load(self.use_value(c)?)
+            }
+        } else if c.keyword("store") {
+            if c.peek_str("#") {
+                let var = self.variable(c)?;
+                Operation::StoreVar(var, self.use_value(c)?)
+            } else {
+                let ptr = self.use_value(c)?;
+                Operation::Store(ptr, self.use_value(c)?)
+            }
+        } else if c.keyword("Φ") || c.keyword("phi") {
+            let mut vals = Vec::new();
+            if !c.is_eof() {
+                loop {
+                    c.expect("[")?;
+                    let block = c.block()?;
+                    c.expect(",")?;
+                    vals.push((block, self.use_value(c)?));
+                    c.expect("]")?;
+                    if !c.eat(",") {
+                        break;
+                    }
+                }
+            }
+            Operation::Phi(vals)
+        } else {
+            let name = c.ident()?;
+            if let Some(op) = unop(name) {
+                Operation::UnOp(op, self.use_value(c)?)
+            } else if let Some(op) = binop(name) {
+                let lhs = self.use_value(c)?;
+                let rhs = self.use_value(c)?;
+                Operation::BinOp(op, lhs, rhs)
+            } else {
+                return c.error_at(op_col, format!("unknown operation `{}`", name));
+            }
+        };
+        c.end()?;
+
+        match (&operation, &yielded) {
+            (Operation::StoreVar(..) | Operation::Store(..), Some(_)) => {
+                return c.error_at(col, "`store` does not yield a value")
+            }
+            (Operation::StoreVar(..) | Operation::Store(..) | Operation::Call(..), None) => {}
+            (_, None) => return c.error_at(op_col, "expected a value to assign to"),
+            _ => {}
+        }
+
+        if let Some((val, ty)) = yielded.clone() {
+            self.define(val, ty, c.line, col)?;
+        }
+        let block = self.func.blocks.last_mut().unwrap();
+        block.instructions.push(Instruction {
+            yielded: yielded.map(|y| y.0),
+            operation,
+        });
+        Ok(())
+    }
+
+    fn variable(&mut self, c: &mut Cursor) -> Result<VariableId, ParseError> {
+        let col = c.start();
+        let var = c.sigil('#', "variable")?;
+        if var >= self.func.variables.len() {
+            return c.error_at(col, format!("undeclared variable #{}", var));
+        }
+        Ok(VariableId(var))
+    }
+
+    fn use_value(&mut self, c: &mut Cursor) -> Result<ValueId, ParseError> {
+        let val = c.value()?;
+        self.reserve(val);
+        Ok(val)
+    }
+
+    fn define(
+        &mut self,
+        val: ValueId,
+        ty: Option<Type>,
+        line: usize,
+        col: usize,
+    ) -> Result<(), ParseError> {
+        self.reserve(val);
+        if val.0 < self.func.args.len() {
+            return Err(ParseError {
+                line,
+                column: col,
+                message: format!("{} is a function argument and cannot be redefined", val),
+            });
+        }
+        if let Some(ty) = ty {
+            match &self.tys[val.0] {
+                Some(old) if *old != ty => {
+                    return Err(ParseError {
+                        line,
+                        column: col,
+                        message: format!("{} was already defined with type {}", val, old),
+                    })
+                }
+                _ => self.tys[val.0] = Some(ty),
+            }
+        }
+        if self.defs[val.0].is_none() {
+            self.defs[val.0] = Some((line, col));
+            self.func.values[val.0].owner = BlockId(self.func.blocks.len() - 1);
+        }
+        Ok(())
+    }
+
+    fn reserve(&mut self, val: ValueId) {
+        while self.func.values.len() <= val.0 {
+            self.func.push_value(Type::Void);
+            self.tys.push(None);
+            self.defs.push(None);
+        }
+    }
+
+    fn finish(mut self) -> Result<Function, ParseError> {
+        for (i, ty) in self.tys.iter().enumerate() {
+            match (ty, self.defs[i]) {
+                (Some(ty), _) => self.func.values[i].ty = ty.clone(),
+                (None, Some((line, column))) => {
+                    return Err(ParseError {
+                        line,
+                        column,
+                        message: format!("cannot infer the type of %{}", i),
+                    })
+                }
+                (None, None) => {}
+            }
+        }
+
+        for &(block, line, column) in self.jumped_to.iter() {
+            if block.0 >= self.func.blocks.len() {
+                return Err(ParseError {
+                    line,
+                    column,
+                    message: format!("jump to undefined block {}", block),
+                });
+            }
+        }
+
+        let mut preds = vec![Vec::new(); self.func.blocks.len()];
+        for (i, block) in self.func.blocks.iter().enumerate() {
+            for t in block.terminator.successors() {
+                preds[t.0].push(BlockId(i));
+            }
+        }
+        for (i, preds) in preds.into_iter().enumerate() {
+            if !self.explicit_preds[i] {
+                self.func.blocks[i].preds = preds;
+            }
+        }
+
+        for (bi, block) in self.func.blocks.iter().enumerate() {
+            for instr in block.instructions.iter() {
+                if let Operation::StoreVar(var, _) = instr.operation {
+                    self.func.variables[var.0]
+                        .bbs_assign_to
+                        .insert(BlockId(bi));
+                }
+                if let Some(val) = instr.yielded {
+                    for op in instr.operation.operands() {
+                        self.func.values[op.0].children.push(val);
+                    }
+                }
+            }
+        }
+
+        Ok(self.func)
+    }
+}
+
+fn unop(name: &str) -> Option<UnOp> {
+    Some(match name {
+        "neg" => UnOp::Neg,
+        "not" => UnOp::Not,
+        "lnot" => UnOp::LNot,
+        _ => return None,
+    })
+}
+
+fn binop(name: &str) -> Option<BinOp> {
+    Some(match name {
+        "add" => BinOp::Add,
+        "sub" => BinOp::Sub,
+        "mul" => BinOp::Mul,
+        "mulhi" => BinOp::MulHi,
+        "div" => BinOp::Div,
+        "mod" => BinOp::Mod,
+        "and" => BinOp::And,
+        "or" => BinOp::Or,
+        "xor" => BinOp::Xor,
+        "shl" => BinOp::Shl,
+        "shr" => BinOp::Shr,
+        "eq" => BinOp::Eq,
+        "ne" => BinOp::Ne,
+        "lt" => BinOp::Lt,
+        "le" => BinOp::Le,
+        "gt" => BinOp::Gt,
+        "ge" => BinOp::Ge,
+        _ => return None,
+    })
+}
+
+/// A position in a single line of the source.
+struct Cursor<'a> {
+    src: &'a str,
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn column(&self) -> usize {
+        self.src[..self.pos].chars().count() + 1
+    }
+
+    /// Skips whitespace and returns the column of the next token.
+    fn start(&mut self) -> usize {
+        self.skip_ws();
+        self.column()
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        self.error_at(self.column(), message)
+    }
+
+    fn error_at<T>(&self, column: usize, message: impl Into<String>) -> Result<T, ParseError> {
+        Err(ParseError {
+            line: self.line,
+            column,
+            message: message.into(),
+        })
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    /// Describes the token at the cursor for error messages.
+    fn found(&mut self) -> String {
+        self.skip_ws();
+        match self.rest().split_whitespace().next() {
+            Some(tok) => format!("`{}`", tok),
+            None => "end of line".to_string(),
+        }
+    }
+
+    fn expected<T>(&mut self, what: &str) -> Result<T, ParseError> {
+        let found = self.found();
+        self.error(format!("expected {}, found {}", what, found))
+    }
+
+    /// Skips whitespace and a `//` comment running to the end of the line.
+    fn skip_ws(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+        if self.rest().starts_with("//") {
+            self.pos = self.src.len();
+        }
+    }
+
+    fn is_eof(&mut self) -> bool {
+        self.skip_ws();
+        self.pos == self.src.len()
+    }
+
+    fn end(&mut self) -> Result<(), ParseError> {
+        if self.is_eof() {
+            Ok(())
+        } else {
+            let found = self.found();
+            self.error(format!("unexpected {}", found))
+        }
+    }
+
+    fn peek_str(&mut self, s: &str) -> bool {
+        self.skip_ws();
+        self.rest().starts_with(s)
+    }
+
+    fn peek_digit(&mut self) -> bool {
+        self.skip_ws();
+        self.rest().starts_with(|c: char| c.is_ascii_digit())
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if self.peek_str(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), ParseError> {
+        if self.eat(s) {
+            Ok(())
+        } else {
+            self.expected(&format!("`{}`", s))
+        }
+    }
+
+    /// Eats `kw` if it is not immediately followed by an identifier character.
+    fn keyword(&mut self, kw: &str) -> bool {
+        if !self.peek_str(kw) {
+            return false;
+        }
+        if self.rest()[kw.len()..].starts_with(is_ident_char) {
+            return false;
+        }
+        self.pos += kw.len();
+        true
+    }
+
+    fn linkage(&mut self) -> Result<Linkage, ParseError> {
+        if self.keyword("public") {
+            Ok(Linkage::Public)
+        } else if self.keyword("private") {
+            Ok(Linkage::Private)
+        } else if self.keyword("external") {
+            Ok(Linkage::External)
+        } else {
+            self.expected("linkage")
+        }
+    }
+
+    fn ident(&mut self) -> Result<&'a str, ParseError> {
+        self.skip_ws();
+        let rest = self.rest();
+        let len = rest.find(|c| !is_ident_char(c)).unwrap_or(rest.len());
+        if len == 0 {
+            return self.expected("identifier");
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    /// Parses an identifier or a quoted name, as written by `ir::Name`.
+    fn name(&mut self) -> Result<String, ParseError> {
+        let col = self.start();
+        if !self.eat("\"") {
+            return self.ident().map(str::to_string);
+        }
+        let mut name = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            let c = match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(name);
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('0') => '\0',
+                    Some(c @ ('\\' | '"' | '\'')) => c,
+                    Some('u') => {
+                        let rest = &self.rest()[i + 2..];
+                        let code = rest
+                            .strip_prefix('{')
+                            .and_then(|r| r.split_once('}'))
+                            .and_then(|(hex, _)| u32::from_str_radix(hex, 16).ok())
+                            .and_then(char::from_u32);
+                        let Some(c) = code else {
+                            return self.error_at(col, "invalid unicode escape in name");
+                        };
+                        // skip the braces and hex digits
+                        chars.nth(rest.find('}').unwrap());
+                        c
+                    }
+                    _ => return self.error_at(col, "invalid escape in name"),
+                },
+                c => c,
+            };
+            name.push(c);
+        }
+        self.error_at(col, "unterminated name")
+    }
+
+    fn number(&mut self, what: &str) -> Result<usize, ParseError> {
+        self.skip_ws();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        match rest[..len].parse() {
+            Ok(n) => {
+                self.pos += len;
+                Ok(n)
+            }
+            Err(_) => self.expected(what),
+        }
+    }
+
+    fn integer(&mut self) -> Result<i64, ParseError> {
+        let col = self.start();
+        let neg = self.eat("-");
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let digits = if neg {
+            format!("-{}", &rest[..len])
+        } else {
+            rest[..len].to_string()
+        };
+        match digits.parse() {
+            Ok(n) => {
+                self.pos += len;
+                Ok(n)
+            }
+            Err(_) => self.error_at(col, format!("invalid integer `{}`", digits)),
+        }
+    }
+
+    fn sigil(&mut self, sigil: char, what: &str) -> Result<usize, ParseError> {
+        self.skip_ws();
+        if !self.rest().starts_with(sigil) {
+            return self.expected(what);
+        }
+        self.pos += sigil.len_utf8();
+        if self.rest().starts_with(char::is_whitespace) {
+            return self.error(format!("expected {} number", what));
+        }
+        self.number(what)
+    }
+
+    fn value(&mut self) -> Result<ValueId, ParseError> {
+        self.sigil('%', "value").map(ValueId)
+    }
+
+    fn block(&mut self) -> Result<BlockId, ParseError> {
+        self.sigil('$', "block").map(BlockId)
+    }
+
+    fn ty(&mut self) -> Result<Type, ParseError> {
+        let mut ty = if self.eat("[") {
+            let elem = self.ty()?;
+            self.expect(";")?;
+            let len = self.number("array length")?;
+            self.expect("]")?;
+            Type::Array(Box::new(elem), len)
+        } else if self.eat("{") {
+            let mut fields = Vec::new();
+            if !self.eat("}") {
+                loop {
+                    fields.push(self.ty()?);
+                    if self.eat("}") {
+                        break;
+                    }
+                    self.expect(",")?;
+                }
+            }
+            Type::Struct(fields)
+        } else {
+            self.scalar_ty()?
+        };
+        while self.eat("*") {
+            ty = Type::Pointer(Box::new(ty));
+        }
+        Ok(ty)
+    }
+
+    /// Parses a constant of type `ty`, with the brackets of an array or the
+    /// braces of a struct around the constants of its members.
+    fn constant(&mut self, ty: &Type) -> Result<Constant, ParseError> {
+        let col = self.start();
+        let (open, close, members) = match ty {
+            Type::Integer(..) | Type::Pointer(_) => return Ok(Constant::Integer(self.integer()?)),
+            Type::Void => return self.error("a void global can't have an initializer"),
+            Type::Array(elem, len) => ("[", "]", vec![&**elem; *len]),
+            Type::Struct(fields) => ("{", "}", fields.iter().collect()),
+        };
+        self.expect(open)?;
+        let mut consts = Vec::new();
+        if !self.eat(close) {
+            loop {
+                let Some(member) = members.get(consts.len()) else {
+                    return self.error_at(col, format!("too many members for {}", ty));
+                };
+                consts.push(self.constant(member)?);
+                if self.eat(close) {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        if consts.len() != members.len() {
+            return self.error_at(
+                col,
+                format!("expected {} members for {}, found {}", members.len(), ty, consts.len()),
+            );
+        }
+        Ok(match ty {
+            Type::Array(..) => Constant::Array(consts),
+            _ => Constant::Struct(consts),
+        })
+    }
+
+    fn scalar_ty(&mut self) -> Result<Type, ParseError> {
+        let col = self.start();
+        let name = self.ident()?;
+        let ty = match name {
+            "void" => Type::Void,
+            _ => {
+                let signed = match name.as_bytes()[0] {
+                    b's' => true,
+                    b'u' => false,
+                    _ => return self.error_at(col, format!("unknown type `{}`", name)),
+                };
+                match name[1..].parse() {
+                    Ok(size) => Type::Integer(size, signed),
+                    Err(_) => return self.error_at(col, format!("unknown type `{}`", name)),
+                }
+            }
+        };
+        Ok(ty)
+    }
+}
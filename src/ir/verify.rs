@@ -0,0 +1,564 @@
+//! Structural checks of a module, so that malformed IR is reported up front
+//! instead of tripping an `unwrap()` in a later pass.
+
+use std::fmt::Display;
+
+use crate::algos::analysis::dominators::DominatorTree;
+
+use super::{
+    Algo, BinOp, BlockId, Function, FunctionId, GlobalId, Module, Operation, Terminator, Type,
+    UnOp, ValueId, VariableId,
+};
+
+/// A problem found by `verify`, located by function and, if applicable, block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    pub function: FunctionId,
+    pub block: Option<BlockId>,
+    pub kind: VerifyErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyErrorKind {
+    /// The `id` of a block does not match its position in the function.
+    BlockIdMismatch(usize),
+    NoTerminator,
+    InvalidBlock(BlockId),
+    /// `preds` does not list exactly the blocks jumping to the block.
+    PredsMismatch {
+        expected: Vec<BlockId>,
+        found: Vec<BlockId>,
+    },
+    UndefinedValue(ValueId),
+    UseBeforeDef(ValueId),
+    MultipleDefinitions(ValueId),
+    InvalidFunction(FunctionId),
+    InvalidGlobal(GlobalId),
+    InvalidVariable(VariableId),
+    ArgCountMismatch {
+        callee: FunctionId,
+        expected: usize,
+        found: usize,
+    },
+    TypeMismatch {
+        value: ValueId,
+        expected: Type,
+        found: Type,
+    },
+    /// A binop on a value that is neither an integer nor a pointer.
+    InvalidOperandType(ValueId),
+    /// A cast that can't convert between the types of its operand and result,
+    /// like a `zext` to a narrower integer.
+    InvalidCast {
+        value: ValueId,
+        from: Type,
+        to: Type,
+    },
+    /// A field or element of a value (or of what it points to) that its
+    /// struct or array type doesn't have.
+    InvalidMember {
+        value: ValueId,
+        index: usize,
+    },
+    /// A phi does not have exactly one operand per predecessor; holds the
+    /// blocks the phi has operands for.
+    PhiPredsMismatch {
+        value: ValueId,
+        incoming: Vec<BlockId>,
+    },
+    /// An operation that should have been removed by a pass that already ran.
+    UnexpectedOperation(Algo),
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "in function ${}", self.function.0)?;
+        if let Some(block) = self.block {
+            write!(f, ", block {}", block)?;
+        }
+        write!(f, ": ")?;
+        match &self.kind {
+            VerifyErrorKind::BlockIdMismatch(id) => write!(f, "block has id ${}", id),
+            VerifyErrorKind::NoTerminator => write!(f, "block has no terminator"),
+            VerifyErrorKind::InvalidBlock(b) => write!(f, "jump to nonexistent block {}", b),
+            VerifyErrorKind::PredsMismatch { expected, found } => write!(
+                f,
+                "preds are {:?} but the block is jumped to from {:?}",
+                found.iter().map(|b| b.0).collect::<Vec<_>>(),
+                expected.iter().map(|b| b.0).collect::<Vec<_>>()
+            ),
+            VerifyErrorKind::UndefinedValue(v) => write!(f, "{} is never defined", v),
+            VerifyErrorKind::UseBeforeDef(v) => write!(f, "{} is used before its definition", v),
+            VerifyErrorKind::MultipleDefinitions(v) => write!(f, "{} is defined more than once", v),
+            VerifyErrorKind::InvalidFunction(func) => {
+                write!(f, "call to nonexistent function ${}", func.0)
+            }
+            VerifyErrorKind::InvalidGlobal(global) => {
+                write!(f, "address of nonexistent global {}", global)
+            }
+            VerifyErrorKind::InvalidVariable(var) => {
+                write!(f, "use of nonexistent variable #{}", var.0)
+            }
+            VerifyErrorKind::ArgCountMismatch {
+                callee,
+                expected,
+                found,
+            } => write!(
+                f,
+                "call to ${} with {} arguments, expected {}",
+                callee.0, found, expected
+            ),
+            VerifyErrorKind::TypeMismatch {
+                value,
+                expected,
+                found,
+            } => write!(f, "{} has type {}, expected {}", value, found, expected),
+            VerifyErrorKind::InvalidOperandType(v) => {
+                write!(f, "{} is not an integer or a pointer", v)
+            }
+            VerifyErrorKind::InvalidCast { value, from, to } => {
+                write!(f, "{} can't be cast from {} to {}", value, from, to)
+            }
+            VerifyErrorKind::InvalidMember { value, index } => {
+                write!(f, "{} has no field or element {}", value, index)
+            }
+            VerifyErrorKind::PhiPredsMismatch { value, incoming } => write!(
+                f,
+                "phi {} has operands for {:?}, which are not the predecessors of the block",
+                value,
+                incoming.iter().map(|b| b.0).collect::<Vec<_>>()
+            ),
+            VerifyErrorKind::UnexpectedOperation(algo) => {
+                write!(f, "operation should have been removed by {:?}", algo)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Checks that the module is well formed, returning every problem found.
+pub fn verify(module: &Module) -> Result<(), Vec<VerifyError>> {
+    let mut errors = Vec::new();
+    for (fi, func) in module.functions.iter().enumerate() {
+        let mut v = FunctionVerifier {
+            module,
+            func,
+            id: FunctionId(fi),
+            block: None,
+            errors: &mut errors,
+        };
+        v.verify();
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+struct FunctionVerifier<'a> {
+    module: &'a Module,
+    func: &'a Function,
+    id: FunctionId,
+    block: Option<BlockId>,
+    errors: &'a mut Vec<VerifyError>,
+}
+
+impl FunctionVerifier<'_> {
+    fn error(&mut self, kind: VerifyErrorKind) {
+        self.errors.push(VerifyError {
+            function: self.id,
+            block: self.block,
+            kind,
+        });
+    }
+
+    fn verify(&mut self) {
+        let func = self.func;
+        let phis_lowered = self.module.algos_run.contains(&Algo::PhiLowering);
+        let phis_removed = self.module.algos_run.contains(&Algo::PhiRemoval);
+        let aggregates_lowered = self.module.algos_run.contains(&Algo::AggregateLowering);
+        let dom = DominatorTree::new(func);
+
+        // where each value is defined, as (block, instruction index)
+        let mut defs: Vec<Option<(usize, usize)>> = vec![None; func.values.len()];
+        for def in defs.iter_mut().take(func.args.len()) {
+            *def = Some((0, 0));
+        }
+        let mut expected_preds = vec![Vec::new(); func.blocks.len()];
+        for (bi, block) in func.blocks.iter().enumerate() {
+            self.block = Some(BlockId(bi));
+            for (ii, instr) in block.instructions.iter().enumerate() {
+                if let Some(val) = instr.yielded {
+                    self.define(&mut defs, val, (bi, ii), phis_removed);
+                }
+            }
+            for &(dst, _) in block.par_moves.iter() {
+                self.define(&mut defs, dst, (bi, block.instructions.len()), phis_removed);
+            }
+            for succ in block.terminator.successors() {
+                match expected_preds.get_mut(succ.0) {
+                    Some(preds) => preds.push(BlockId(bi)),
+                    None => self.error(VerifyErrorKind::InvalidBlock(succ)),
+                }
+            }
+        }
+
+        for (bi, block) in func.blocks.iter().enumerate() {
+            self.block = Some(BlockId(bi));
+            if block.id != bi {
+                self.error(VerifyErrorKind::BlockIdMismatch(block.id));
+            }
+
+            let mut found = block.preds.clone();
+            let mut expected = std::mem::take(&mut expected_preds[bi]);
+            found.sort_by_key(|b| b.0);
+            expected.sort_by_key(|b| b.0);
+            if found != expected {
+                self.error(VerifyErrorKind::PredsMismatch { expected, found });
+            }
+
+            // the end of the block, where par_moves and the terminator read
+            let end = (!phis_removed).then_some((bi, usize::MAX));
+            for (ii, instr) in block.instructions.iter().enumerate() {
+                if let Operation::Phi(vals) = &instr.operation {
+                    // phi operands flow in from predecessors, so they only
+                    // need to be available at the end of the incoming block
+                    for &(pred, val) in vals.iter() {
+                        let pos = (!phis_removed).then_some((pred.0, usize::MAX));
+                        self.use_value(&defs, &dom, val, pos);
+                    }
+                } else {
+                    for val in instr.operation.operands() {
+                        let pos = (!phis_removed).then_some((bi, ii));
+                        self.use_value(&defs, &dom, val, pos);
+                    }
+                }
+                self.verify_operation(instr.yielded, &instr.operation, &block.preds);
+
+                match instr.operation {
+                    Operation::LoadVar(_) | Operation::StoreVar(..) if phis_lowered => {
+                        self.error(VerifyErrorKind::UnexpectedOperation(Algo::PhiLowering))
+                    }
+                    Operation::Phi(_) if phis_removed => {
+                        self.error(VerifyErrorKind::UnexpectedOperation(Algo::PhiRemoval))
+                    }
+                    Operation::ExtractValue(..) | Operation::InsertValue(..)
+                        if aggregates_lowered =>
+                    {
+                        self.error(VerifyErrorKind::UnexpectedOperation(Algo::AggregateLowering))
+                    }
+                    _ if aggregates_lowered
+                        && instr.yielded.is_some_and(|v| {
+                            self.ty(v).is_some_and(Type::is_aggregate)
+                        }) =>
+                    {
+                        self.error(VerifyErrorKind::UnexpectedOperation(Algo::AggregateLowering))
+                    }
+                    _ => {}
+                }
+            }
+            for &(_, src) in block.par_moves.iter() {
+                self.use_value(&defs, &dom, src, end);
+            }
+
+            match block.terminator {
+                Terminator::NoTerm => self.error(VerifyErrorKind::NoTerminator),
+                Terminator::Return(val) => {
+                    // `ret` always has a value, which is ignored in a void
+                    // function
+                    if self.use_value(&defs, &dom, val, end) && func.ret_type != Type::Void {
+                        self.expect_type(val, &func.ret_type);
+                    }
+                }
+                Terminator::Branch(val, ..) => {
+                    if self.use_value(&defs, &dom, val, end) {
+                        self.expect_scalar(val);
+                    }
+                }
+                Terminator::Jump(_) => {}
+            }
+        }
+    }
+
+    fn define(
+        &mut self,
+        defs: &mut [Option<(usize, usize)>],
+        val: ValueId,
+        pos: (usize, usize),
+        phis_removed: bool,
+    ) {
+        match defs.get_mut(val.0) {
+            None => self.error(VerifyErrorKind::UndefinedValue(val)),
+            // moves inserted by phi removal define values more than once
+            Some(Some(_)) if !phis_removed => {
+                self.error(VerifyErrorKind::MultipleDefinitions(val))
+            }
+            Some(def) => {
+                def.get_or_insert(pos);
+            }
+        }
+    }
+
+    /// Checks that `val` is defined, and that its definition dominates `pos`
+    /// if given, and returns whether it is a valid value at all.
+    fn use_value(
+        &mut self,
+        defs: &[Option<(usize, usize)>],
+        dom: &DominatorTree,
+        val: ValueId,
+        pos: Option<(usize, usize)>,
+    ) -> bool {
+        match defs.get(val.0) {
+            None | Some(None) => {
+                self.error(VerifyErrorKind::UndefinedValue(val));
+                false
+            }
+            Some(Some((bi, ii))) => {
+                if let Some((use_bi, use_ii)) = pos {
+                    // anything goes in unreachable blocks, as they never run
+                    let before = if *bi == use_bi {
+                        *ii < use_ii
+                    } else {
+                        !dom.is_reachable(BlockId(use_bi))
+                            || dom.dominates(BlockId(*bi), BlockId(use_bi))
+                    };
+                    if !before && val.0 >= self.func.args.len() {
+                        self.error(VerifyErrorKind::UseBeforeDef(val));
+                    }
+                }
+                true
+            }
+        }
+    }
+
+    fn ty(&self, val: ValueId) -> Option<&Type> {
+        self.func.values.get(val.0).map(|v| &v.ty)
+    }
+
+    fn expect_type(&mut self, val: ValueId, expected: &Type) {
+        if let Some(found) = self.ty(val) {
+            if found != expected {
+                let found = found.clone();
+                self.error(VerifyErrorKind::TypeMismatch {
+                    value: val,
+                    expected: expected.clone(),
+                    found,
+                });
+            }
+        }
+    }
+
+    fn expect_scalar(&mut self, val: ValueId) -> bool {
+        match self.ty(val) {
+            Some(Type::Integer(..) | Type::Pointer(_)) => true,
+            Some(_) => {
+                self.error(VerifyErrorKind::InvalidOperandType(val));
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the type `ptr` points to, reporting an error if it isn't a
+    /// pointer.
+    fn pointee(&mut self, ptr: ValueId) -> Option<Type> {
+        match self.ty(ptr) {
+            Some(Type::Pointer(pointee)) => Some((**pointee).clone()),
+            Some(_) => {
+                self.error(VerifyErrorKind::InvalidOperandType(ptr));
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn verify_operation(&mut self, yielded: Option<ValueId>, op: &Operation, preds: &[BlockId]) {
+        match op {
+            Operation::Integer(_) | Operation::Undef => {}
+            Operation::Copy(src) => {
+                if let Some(val) = yielded {
+                    let ty = self.ty(val).unwrap().clone();
+                    self.expect_type(*src, &ty);
+                }
+            }
+            Operation::ZExt(src)
+            | Operation::SExt(src)
+            | Operation::Trunc(src)
+            | Operation::PtrToInt(src)
+            | Operation::IntToPtr(src) => {
+                let (Some(val), Some(from)) = (yielded, self.ty(*src)) else {
+                    return;
+                };
+                let from = from.clone();
+                let to = self.ty(val).unwrap().clone();
+                let widths = match (&from, &to) {
+                    (Type::Integer(a, _), Type::Integer(b, _)) => Some((*a, *b)),
+                    _ => None,
+                };
+                let valid = match op {
+                    Operation::ZExt(_) | Operation::SExt(_) => widths.is_some_and(|(a, b)| a < b),
+                    Operation::Trunc(_) => widths.is_some_and(|(a, b)| a > b),
+                    Operation::PtrToInt(_) => {
+                        matches!((&from, &to), (Type::Pointer(_), Type::Integer(..)))
+                    }
+                    _ => matches!((&from, &to), (Type::Integer(..), Type::Pointer(_))),
+                };
+                if !valid {
+                    self.error(VerifyErrorKind::InvalidCast { value: val, from, to });
+                }
+            }
+            Operation::BinOp(op, lhs, rhs) => {
+                if !self.expect_scalar(*lhs) || !self.expect_scalar(*rhs) {
+                    return;
+                }
+                let lhs_ty = self.ty(*lhs).unwrap().clone();
+                self.expect_type(*rhs, &lhs_ty);
+                let is_cmp = matches!(
+                    op,
+                    BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge
+                );
+                if let (Some(val), false) = (yielded, is_cmp) {
+                    self.expect_type(val, &lhs_ty);
+                }
+            }
+            Operation::UnOp(op, src) => {
+                if !self.expect_scalar(*src) {
+                    return;
+                }
+                // like comparisons, `lnot` may yield any integer type
+                if let (Some(val), false) = (yielded, *op == UnOp::LNot) {
+                    let ty = self.ty(*src).unwrap().clone();
+                    self.expect_type(val, &ty);
+                }
+            }
+            Operation::Call(callee, args) => {
+                let Some(callee_fn) = self.module.functions.get(callee.0) else {
+                    self.error(VerifyErrorKind::InvalidFunction(*callee));
+                    return;
+                };
+                if callee_fn.args.len() != args.len() {
+                    self.error(VerifyErrorKind::ArgCountMismatch {
+                        callee: *callee,
+                        expected: callee_fn.args.len(),
+                        found: args.len(),
+                    });
+                    return;
+                }
+                for (arg, (_, ty)) in args.iter().zip(callee_fn.args.iter()) {
+                    self.expect_type(*arg, ty);
+                }
+                if let Some(val) = yielded {
+                    self.expect_type(val, &callee_fn.ret_type);
+                }
+            }
+            Operation::LoadVar(var) => match self.func.variables.get(var.0) {
+                Some(v) => {
+                    if let Some(val) = yielded {
+                        self.expect_type(val, &v.ty);
+                    }
+                }
+                None => self.error(VerifyErrorKind::InvalidVariable(*var)),
+            },
+            Operation::StoreVar(var, val) => match self.func.variables.get(var.0) {
+                Some(v) => self.expect_type(*val, &v.ty),
+                None => self.error(VerifyErrorKind::InvalidVariable(*var)),
+            },
+            Operation::Alloca(ty) => {
+                if let Some(val) = yielded {
+                    self.expect_type(val, &Type::Pointer(Box::new(ty.clone())));
+                }
+            }
+            Operation::Load(ptr) => {
+                if let (Some(pointee), Some(val)) = (self.pointee(*ptr), yielded) {
+                    self.expect_type(val, &pointee);
+                }
+            }
+            Operation::Store(ptr, val) => {
+                if let Some(pointee) = self.pointee(*ptr) {
+                    self.expect_type(*val, &pointee);
+                }
+            }
+            Operation::ElementPtr(base, index, ty) => {
+                self.pointee(*base);
+                if matches!(self.ty(*index), Some(Type::Pointer(_))) {
+                    self.error(VerifyErrorKind::InvalidOperandType(*index));
+                } else {
+                    self.expect_scalar(*index);
+                }
+                if let Some(val) = yielded {
+                    self.expect_type(val, &Type::Pointer(Box::new(ty.clone())));
+                }
+            }
+            Operation::GlobalAddr(global) => {
+                let Some(g) = self.module.globals.get(global.0) else {
+                    self.error(VerifyErrorKind::InvalidGlobal(*global));
+                    return;
+                };
+                if let Some(val) = yielded {
+                    self.expect_type(val, &Type::Pointer(Box::new(g.ty.clone())));
+                }
+            }
+            Operation::FieldPtr(base, index) => {
+                let Some(pointee) = self.pointee(*base) else {
+                    return;
+                };
+                match (pointee.member(*index), yielded) {
+                    (Some(member), Some(val)) => {
+                        self.expect_type(val, &Type::Pointer(Box::new(member.clone())));
+                    }
+                    (Some(_), None) => {}
+                    (None, _) => self.error(VerifyErrorKind::InvalidMember {
+                        value: *base,
+                        index: *index,
+                    }),
+                }
+            }
+            Operation::ExtractValue(agg, index) | Operation::InsertValue(agg, _, index) => {
+                let Some(agg_ty) = self.ty(*agg).cloned() else {
+                    return;
+                };
+                let Some(member) = agg_ty.member(*index) else {
+                    self.error(VerifyErrorKind::InvalidMember {
+                        value: *agg,
+                        index: *index,
+                    });
+                    return;
+                };
+                match (op, yielded) {
+                    (Operation::InsertValue(_, val, _), yielded) => {
+                        self.expect_type(*val, member);
+                        if let Some(yielded) = yielded {
+                            self.expect_type(yielded, &agg_ty);
+                        }
+                    }
+                    (_, Some(val)) => self.expect_type(val, member),
+                    _ => {}
+                }
+            }
+            Operation::Phi(vals) => {
+                if let Some(val) = yielded {
+                    let mut incoming: Vec<BlockId> = vals.iter().map(|(b, _)| *b).collect();
+                    let mut preds = preds.to_vec();
+                    incoming.sort_by_key(|b| b.0);
+                    preds.sort_by_key(|b| b.0);
+                    if incoming != preds {
+                        let incoming = vals.iter().map(|(b, _)| *b).collect();
+                        self.error(VerifyErrorKind::PhiPredsMismatch {
+                            value: val,
+                            incoming,
+                        });
+                    }
+                    let ty = self.ty(val).cloned();
+                    for (_, op) in vals.iter() {
+                        if let Some(ty) = &ty {
+                            self.expect_type(*op, ty);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
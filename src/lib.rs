@@ -10,8 +10,11 @@ pub mod vcode;
 #[cfg(test)]
 mod tests {
     use crate::{
+        algos::analysis::dominators::DominatorTree,
+        arch::iris::IrisSelector,
         builder::ModuleBuilder,
-        ir::{Terminator, Type},
+        regalloc::linear_scan::LinearScanRegAlloc,
+        ir::{parse::parse, verify, verify::VerifyErrorKind, BinOp, Terminator, Type},
     };
 
     #[test]
@@ -37,13 +40,13 @@ mod tests {
             .instructions
             .push(crate::ir::Instruction {
                 yielded: Some(x),
-                operation: crate::ir::Operation::Phi(vec![one, y]),
+                operation: crate::ir::Operation::Phi(vec![(a, one), (b, y)]),
             });
         builder.module.functions[0].blocks[1]
             .instructions
             .push(crate::ir::Instruction {
                 yielded: Some(y),
-                operation: crate::ir::Operation::Phi(vec![zero, x]),
+                operation: crate::ir::Operation::Phi(vec![(a, zero), (b, x)]),
             });
         builder.set_terminator(Terminator::Jump(b));
 
@@ -70,4 +73,2100 @@ mod tests {
         module.apply_mandatory_transforms();
         println!("{}", module);
     }
+
+    #[test]
+    fn parse_round_trip() {
+        const INT: Type = Type::Integer(16, false);
+        let mut builder = ModuleBuilder::new("round_trip");
+        let (f, args) = builder.push_function("count", INT, vec![("n".to_string(), INT)], None);
+        builder.switch_to_fn(f);
+        let entry = builder.push_block();
+        let body = builder.push_block();
+        let end = builder.push_block();
+        let cnt = builder.push_variable("cnt", INT);
+
+        builder.switch_to_block(entry);
+        builder.build_store(cnt, args[0]);
+        builder.set_terminator(Terminator::Jump(body));
+
+        builder.switch_to_block(body);
+        let c = builder.build_load(cnt);
+        let one = builder.build_integer(1, INT);
+        let nc = builder.build_binop(BinOp::Sub, c, one, INT);
+        builder.build_store(cnt, nc);
+        builder.set_terminator(Terminator::Branch(nc, body, end));
+
+        builder.switch_to_block(end);
+        builder.set_terminator(Terminator::Return(nc));
+
+        let mut module = builder.build();
+        for _ in 0..2 {
+            let text = module.to_string();
+            let parsed = parse(&text).unwrap();
+            assert_eq!(text, parsed.to_string());
+            module.apply_mandatory_transforms();
+        }
+    }
+
+    #[test]
+    fn parse_round_trip_names() {
+        const INT: Type = Type::Integer(16, false);
+        let mut builder = ModuleBuilder::new("a */ b");
+        builder.push_global("http://x", INT, crate::ir::Linkage::Public, None, true);
+        let arg = ("the \"n\"".to_string(), INT);
+        let (f, args) = builder.push_function("a//b", INT, vec![arg], None);
+        builder.switch_to_fn(f);
+        let entry = builder.push_block();
+        let var = builder.push_variable("tab\tand é\u{7f}", INT);
+        builder.switch_to_block(entry);
+        builder.build_store(var, args[0]);
+        let x = builder.build_load(var);
+        builder.set_terminator(Terminator::Return(x));
+
+        let module = builder.build();
+        let text = module.to_string();
+        let parsed = parse(&text).unwrap();
+        assert_eq!(text, parsed.to_string());
+        assert_eq!(parsed.name, "a */ b");
+        assert_eq!(parsed.globals[0].name, "http://x");
+        let func = &parsed.functions[0];
+        assert_eq!(func.name, "a//b");
+        assert_eq!(func.args[0].0, "the \"n\"");
+        assert_eq!(func.variables[0].name, "tab\tand é\u{7f}");
+    }
+
+    #[test]
+    fn parse_hand_written() {
+        let module = parse(
+            "
+            $0: public fn main(a: u16) u16 {
+            $0:
+                %1: u16 = 2
+                %2 = mul %0 %1 // inferred from %0
+                br %2, $1, $1
+            $1:
+                ret %2
+            }
+            ",
+        )
+        .unwrap();
+        assert_eq!(module.functions[0].blocks[1].preds.len(), 2);
+        assert_eq!(module.functions[0].values[2].ty, Type::Integer(16, false));
+
+        let err = parse("$0: public fn main() void {\n$0:\n    %0 = frob %1 %2\n}").unwrap_err();
+        assert_eq!((err.line, err.column), (3, 10));
+
+        let err = parse("$0: public fn main() void {\n$0:\n    %0 = 1\n    ret %0\n}").unwrap_err();
+        assert_eq!((err.line, err.column), (3, 5));
+
+        // functions and blocks may be used before they are defined, but must
+        // exist by the end
+        let err = parse("$0: public fn main() u16 {\n$0:\n    %0 = call $1()\n    ret %0\n}")
+            .unwrap_err();
+        assert_eq!((err.line, err.column), (3, 15));
+        let err = parse("$0: public fn main(c: u1) u1 {\n$0:\n    br %0, $0, $2\n}").unwrap_err();
+        assert_eq!((err.line, err.column), (3, 16));
+    }
+
+    #[test]
+    fn verify_reports_errors() {
+        let module = parse(
+            "
+            $0: public fn main(a: u16) u16 {
+            $0: ; preds = $1
+                %1: u16 = add %0 %2
+                %2: u16 = 1
+                %3: u16 = call $0(%1, %2)
+                %4: u8 = 2
+                %5: u16 = mul %1 %4
+                br %1, $1, $1
+            $1:
+            }
+            ",
+        )
+        .unwrap();
+        // the parser rejects jumps to undefined blocks
+        let mut module = module;
+        let b = crate::ir::BlockId;
+        module.functions[0].blocks[0].terminator =
+            Terminator::Branch(crate::ir::ValueId(1), b(1), b(3));
+        let kinds: Vec<_> = verify(&module)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| e.kind)
+            .collect();
+        assert!(kinds.contains(&VerifyErrorKind::UseBeforeDef(crate::ir::ValueId(2))));
+        assert!(kinds.contains(&VerifyErrorKind::InvalidBlock(crate::ir::BlockId(3))));
+        assert!(kinds.contains(&VerifyErrorKind::NoTerminator));
+        assert!(kinds.iter().any(|k| matches!(k, VerifyErrorKind::PredsMismatch { .. })));
+        assert!(kinds.iter().any(|k| matches!(k, VerifyErrorKind::ArgCountMismatch { .. })));
+        assert!(kinds.iter().any(|k| matches!(k, VerifyErrorKind::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn verify_checks_return_type() {
+        let module = parse(
+            "
+            $0: public fn f(a: u16) u8 {
+            $0:
+                ret %0
+            }
+            ",
+        )
+        .unwrap();
+        let kinds: Vec<_> = verify(&module)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| e.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [VerifyErrorKind::TypeMismatch {
+                value: crate::ir::ValueId(0),
+                expected: Type::Integer(8, false),
+                found: Type::Integer(16, false),
+            }]
+        );
+    }
+
+    #[test]
+    fn verify_checks_dominance() {
+        let module = parse(
+            "
+            $0: public fn f(a: u16) u16 {
+            $0:
+                br %0, $1, $2
+            $1: ; preds = $0
+                %1: u16 = 1
+                jmp $3
+            $2: ; preds = $0
+                %2: u16 = add %0 %1
+                jmp $3
+            $3: ; preds = $1, $2
+                %3: u16 = phi [$1, %1], [$2, %1]
+                %4: u16 = add %1 %2
+                ret %4
+            }
+            ",
+        )
+        .unwrap();
+        let kinds: Vec<_> = verify(&module)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| e.kind)
+            .collect();
+        let v = crate::ir::ValueId;
+        // %1 in $2, the phi operand from $2 and both operands of %4
+        assert_eq!(kinds, [v(1), v(1), v(1), v(2)].map(VerifyErrorKind::UseBeforeDef));
+
+        // defined in a dominating block, or at the end of the incoming block
+        let module = parse(
+            "
+            $0: public fn g(a: u16) u16 {
+            $0:
+                %1: u16 = 1
+                br %0, $1, $2
+            $1: ; preds = $0, $1
+                %2: u16 = phi [$0, %1], [$1, %3]
+                %3: u16 = add %2 %1
+                br %3, $1, $2
+            $2: ; preds = $0, $1
+                ret %1
+            }
+            ",
+        )
+        .unwrap();
+        verify(&module).unwrap();
+    }
+
+    #[test]
+    fn verified_mandatory_transforms() {
+        let mut module = parse(
+            "
+            $0: public fn max(a: s16, b: s16) s16 {
+                var #0 m: s16
+            $0:
+                store #0 %0
+                %2: s16 = lt %0 %1
+                br %2, $1, $2
+            $1:
+                store #0 %1
+                jmp $2
+            $2:
+                %3 = load #0
+                ret %3
+            }
+            ",
+        )
+        .unwrap();
+        module.apply_mandatory_transforms_verified().unwrap();
+    }
+
+    #[test]
+    fn dominators() {
+        // the CFG of figure 3.1 in the SSA book, plus an exit block
+        let module = parse(
+            "
+            $0: public fn main(c: u1) void {
+            $0:
+                jmp $1
+            $1:
+                br %0, $2, $3
+            $2:
+                jmp $4
+            $3:
+                br %0, $4, $5
+            $4:
+                br %0, $1, $5
+            $5:
+                jmp $6
+            $6:
+                ret %0
+            }
+            ",
+        )
+        .unwrap();
+        let func = &module.functions[0];
+        let b = crate::ir::BlockId;
+
+        let dom = DominatorTree::new(func);
+        let idoms: Vec<_> = (0..7).map(|i| dom.idom(b(i))).collect();
+        assert_eq!(
+            idoms,
+            vec![None, Some(b(0)), Some(b(1)), Some(b(1)), Some(b(1)), Some(b(1)), Some(b(5))]
+        );
+        assert!(dom.dominates(b(1), b(6)));
+        assert!(!dom.dominates(b(3), b(4)));
+        assert_eq!(dom.frontier(b(2)), &[b(4)]);
+        let mut df = dom.frontier(b(4)).to_vec();
+        df.sort_by_key(|b| b.0);
+        assert_eq!(df, vec![b(1), b(5)]);
+        assert_eq!(dom.frontier(b(1)), &[b(1)]);
+        assert_eq!(dom.rpo()[0], b(0));
+        assert_eq!(dom.rpo().len(), 7);
+
+        let pdom = DominatorTree::new_post(func);
+        assert_eq!(pdom.roots(), &[b(6)]);
+        assert_eq!(pdom.idom(b(1)), Some(b(5)));
+        assert_eq!(pdom.idom(b(2)), Some(b(4)));
+        assert!(pdom.dominates(b(5), b(0)));
+        assert!(!pdom.dominates(b(4), b(1)));
+    }
+
+    #[test]
+    fn ssa_construction_nested_loops() {
+        let mut module = parse(
+            "
+            $0: public fn main(n: u16) u16 {
+                var #0 i: u16
+                var #1 sum: u16
+                var #2 j: u16
+                var #3 never: u16
+            $0:
+                %1: u16 = 0
+                %2: u16 = 1
+                store #0 %0
+                store #1 %1
+                jmp $1
+            $1:
+                store #2 %0
+                jmp $2
+            $2:
+                %3 = load #1
+                %4 = load #2
+                %5 = add %3 %4
+                store #1 %5
+                %6 = sub %4 %2
+                store #2 %6
+                br %6, $2, $3
+            $3:
+                %7 = load #0
+                %8 = sub %7 %2
+                store #0 %8
+                br %8, $1, $4
+            $4:
+                %9 = load #1
+                %10 = load #3
+                %11 = add %9 %10
+                ret %11
+            }
+            ",
+        )
+        .unwrap();
+        crate::algos::remove_critical_edges::remove_critical_edges(&mut module);
+        crate::algos::lower_to_ssa::lower(&mut module);
+        verify(&module).unwrap();
+
+        let func = &module.functions[0];
+        let phis = |b: usize| {
+            let block = &func.blocks[b];
+            let mut preds: Vec<_> = block.preds.iter().map(|p| p.0).collect();
+            preds.sort();
+            let mut count = 0;
+            for instr in block.instructions.iter() {
+                if let crate::ir::Operation::Phi(vals) = &instr.operation {
+                    // one operand from each of the entry and the latch
+                    let mut incoming: Vec<_> = vals.iter().map(|(b, _)| b.0).collect();
+                    incoming.sort();
+                    assert_eq!(incoming, preds);
+                    assert_eq!(incoming.len(), 2);
+                    count += 1;
+                }
+            }
+            count
+        };
+        // sum and i in the outer header, sum and j in the inner one
+        assert_eq!(phis(1), 2);
+        assert_eq!(phis(2), 2);
+        assert_eq!(phis(3), 0);
+        assert_eq!(phis(4), 0);
+        assert!(func.blocks[0]
+            .instructions
+            .iter()
+            .any(|i| i.operation == crate::ir::Operation::Undef));
+    }
+
+    #[test]
+    fn phi_moves_on_edges() {
+        const INT: Type = Type::Integer(16, false);
+        let mut builder = ModuleBuilder::new("phi_edges");
+        let (f, args) = builder.push_function("select", INT, vec![("c".to_string(), INT)], None);
+        builder.switch_to_fn(f);
+        let entry = builder.push_block();
+        let left = builder.push_block();
+        let right = builder.push_block();
+        let join = builder.push_block();
+
+        builder.switch_to_block(entry);
+        let one = builder.build_integer(1, INT);
+        builder.set_terminator(Terminator::Branch(args[0], left, right));
+        builder.switch_to_block(left);
+        builder.set_terminator(Terminator::Jump(join));
+        builder.switch_to_block(right);
+        let two = builder.build_integer(2, INT);
+        builder.set_terminator(Terminator::Jump(join));
+        builder.switch_to_block(join);
+        let phi = builder.build_phi(vec![(left, one), (right, two)], INT);
+        builder.set_terminator(Terminator::Return(phi));
+
+        let mut module = builder.build();
+        module.apply_mandatory_transforms_verified().unwrap();
+        let func = &module.functions[0];
+        let moves_to = |b: crate::ir::BlockId| {
+            func.blocks[b.0]
+                .instructions
+                .iter()
+                .filter(|i| i.yielded == Some(phi))
+                .count()
+        };
+        // `one` is defined in the entry, but the move belongs on the edge
+        assert_eq!(moves_to(entry), 0);
+        assert_eq!(moves_to(left), 1);
+        assert_eq!(moves_to(right), 1);
+    }
+
+    #[test]
+    fn phi_copies() {
+        let mut module = parse(
+            "
+            $0: public fn main(n: u16) u16 {
+            $0:
+                %1: u16 = 1
+                jmp $1
+            $1:
+                %2: u16 = phi [$0, %1], [$1, %3]
+                %3 = sub %2 %1
+                br %3, $1, $2
+            $2:
+                ret %3
+            }
+            ",
+        )
+        .unwrap();
+        module.apply_mandatory_transforms();
+        let copies = module.functions[0]
+            .blocks
+            .iter()
+            .flat_map(|b| b.instructions.iter())
+            .filter(|i| matches!(i.operation, crate::ir::Operation::Copy(_)))
+            .count();
+        assert_eq!(copies, 2);
+
+        // the copy of the constant in the entry folds, the one in the loop
+        // must not, as %2 is defined twice
+        crate::algos::opt::OptPass::run(
+            &mut crate::algos::opt::constant_folding::ConstantFolding,
+            &mut module,
+        );
+        let func = &module.functions[0];
+        assert!(matches!(
+            func.blocks[0].instructions[1].operation,
+            crate::ir::Operation::Integer(1)
+        ));
+        assert!(func.blocks[1]
+            .instructions
+            .iter()
+            .any(|i| matches!(i.operation, crate::ir::Operation::BinOp(..))));
+
+        let vcode = module.lower_to_vcode::<_, IrisSelector, LinearScanRegAlloc>();
+        let asm = vcode.to_string();
+        assert!(!asm.contains("and"));
+    }
+
+    #[test]
+    fn pass_manager() {
+        use crate::algos::{
+            opt::FunctionPass,
+            pass_manager::{OptLevel, PassError, PassManager},
+            phi_removal::RemovePhis,
+        };
+        use crate::ir::{Algo, Function};
+
+        const SRC: &str = "
+            $0: public fn main(n: u16) u16 {
+                var #0 x: u16
+            $0:
+                %1: u16 = 1
+                store #0 %1
+                br %0, $1, $2
+            $1:
+                %2: u16 = load #0
+                %3 = add %2 %1
+                store #0 %3
+                br %3, $1, $2
+            $2:
+                %4: u16 = load #0
+                ret %4
+            }
+            ";
+
+        struct Counter(usize, Vec<Algo>);
+        impl FunctionPass for Counter {
+            fn run_on_function(&mut self, _func: &mut Function) {
+                self.0 += 1;
+            }
+            fn requires(&self) -> Vec<Algo> {
+                self.1.clone()
+            }
+            fn invalidates(&self) -> Vec<Algo> {
+                vec![Algo::CriticalEdgeSplitting]
+            }
+        }
+
+        // the mandatory transforms are inserted in front of the passes needing them
+        for level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            let mut module = parse(SRC).unwrap();
+            let mut pm = PassManager::with_opt_level(level);
+            pm.set_verify(true);
+            pm.run(&mut module).unwrap();
+            for algo in [Algo::CriticalEdgeSplitting, Algo::PhiLowering, Algo::PhiRemoval] {
+                assert!(module.algos_run.contains(&algo));
+            }
+            module.lower_to_vcode::<_, IrisSelector, LinearScanRegAlloc>();
+        }
+
+        // invalidated properties are established again when needed
+        let mut module = parse(SRC).unwrap();
+        let mut pm = PassManager::new();
+        pm.add_function_pass(Counter(0, vec![Algo::PhiLowering]))
+            .add_pass(RemovePhis)
+            .set_verify(true);
+        pm.run(&mut module).unwrap();
+        assert!(module.algos_run.contains(&Algo::CriticalEdgeSplitting));
+
+        // SSA form can't be rebuilt once phis are removed
+        let mut module = parse(SRC).unwrap();
+        let mut pm = PassManager::new();
+        pm.add_pass(RemovePhis)
+            .add_pass(crate::algos::lower_to_ssa::LowerToSsa);
+        assert!(matches!(
+            pm.run(&mut module),
+            Err(PassError::Conflict {
+                property: Algo::PhiRemoval,
+                ..
+            })
+        ));
+        let mut module = parse(SRC).unwrap();
+        let mut pm = PassManager::new();
+        pm.add_function_pass(Counter(0, vec![Algo::LowerParMoves]));
+        assert!(matches!(
+            pm.run(&mut module),
+            Err(PassError::Unsatisfiable {
+                property: Algo::LowerParMoves,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn dead_code_elimination() {
+        use crate::algos::{
+            opt::{
+                dce::{AggressiveDce, DeadCodeElimination},
+                ForEachFunction, OptPass,
+            },
+            pass_manager::{OptLevel, PassManager},
+        };
+        use crate::ir::Operation;
+
+        const SRC: &str = "
+            $0: public fn main(n: u16) u16 {
+            $0:
+                %1: u16 = 1
+                %2: u16 = 2
+                %3 = add %1 %1
+                %4 = call $0(%3)
+                jmp $1
+            $1: ; preds = $0, $1
+                %5: u16 = phi [$0, %1], [$1, %6]
+                %6 = add %5 %1
+                %7: u16 = phi [$0, %0], [$1, %7]
+                br %6, $1, $3
+            $2:
+                %8 = mul %0 %0
+                jmp $3
+            $3: ; preds = $1, $2
+                %9: u16 = phi [$1, %6], [$2, %8]
+                ret %9
+            }
+            ";
+        let count = |module: &crate::ir::Module| -> usize {
+            module.functions[0]
+                .blocks
+                .iter()
+                .map(|b| b.instructions.len())
+                .sum()
+        };
+
+        // %2 is dead, the call must stay even though %4 is unused
+        let mut module = parse(SRC).unwrap();
+        ForEachFunction(DeadCodeElimination).run(&mut module);
+        verify(&module).unwrap();
+        assert_eq!(count(&module), 8);
+
+        // the unused phi %7 and the unreachable block $2 go away too
+        let mut module = parse(SRC).unwrap();
+        ForEachFunction(AggressiveDce).run(&mut module);
+        verify(&module).unwrap();
+        let func = &module.functions[0];
+        assert_eq!(func.blocks.len(), 3);
+        assert_eq!(func.blocks[2].preds, vec![crate::ir::BlockId(1)]);
+        assert_eq!(count(&module), 6);
+        assert!(func.values[1].children.contains(&crate::ir::ValueId(6)));
+
+        // folded constants don't survive -O1
+        let mut module = parse(
+            "
+            $0: public fn main() u16 {
+            $0:
+                %0: u16 = 3
+                %1: u16 = 4
+                %2 = mul %0 %1
+                ret %2
+            }
+            ",
+        )
+        .unwrap();
+        PassManager::with_opt_level(OptLevel::O1)
+            .run(&mut module)
+            .unwrap();
+        let instrs = &module.functions[0].blocks[0].instructions;
+        assert_eq!(instrs.len(), 1);
+        assert_eq!(instrs[0].operation, Operation::Integer(12));
+    }
+
+    #[test]
+    fn sccp() {
+        use crate::algos::{
+            opt::{sccp::Sccp, ForEachFunction, OptPass},
+            pass_manager::{OptLevel, PassManager},
+        };
+        use crate::ir::{BlockId, Operation};
+
+        const SRC: &str = "
+            $0: public fn main(n: u16) u16 {
+            $0:
+                %1: u16 = 1
+                %2: u16 = 0
+                jmp $1
+            $1: ; preds = $0, $2
+                %3: u16 = phi [$0, %1], [$2, %5]
+                %4 = ne %3 %2
+                br %4, $2, $3
+            $2:
+                %5 = mul %3 %1
+                br %0, $1, $4
+            $3:
+                %6 = add %0 %1
+                ret %6
+            $4:
+                ret %5
+            }
+            ";
+
+        let mut module = parse(SRC).unwrap();
+        module.algos_run.push(crate::ir::Algo::PhiLowering);
+        ForEachFunction(Sccp).run(&mut module);
+        verify(&module).unwrap();
+        let func = &module.functions[0];
+        // $3 is never reached, so %3 stays 1 around the loop
+        assert_eq!(func.blocks.len(), 4);
+        assert_eq!(func.blocks[1].terminator, Terminator::Jump(BlockId(2)));
+        assert_eq!(func.blocks[1].instructions[0].operation, Operation::Integer(1));
+        assert_eq!(func.blocks[2].instructions[0].operation, Operation::Integer(1));
+        assert_eq!(func.blocks[3].preds, vec![BlockId(2)]);
+
+        let mut module = parse(SRC).unwrap();
+        let mut pm = PassManager::with_opt_level(OptLevel::O2);
+        pm.set_verify(true);
+        pm.run(&mut module).unwrap();
+        module.lower_to_vcode::<_, IrisSelector, LinearScanRegAlloc>();
+    }
+
+    #[test]
+    fn gvn() {
+        use crate::algos::opt::{gvn::Gvn, ForEachFunction, OptPass};
+        use crate::ir::{Operation, ValueId};
+
+        let mut module = parse(
+            "
+            $0: public fn main(a: u16, b: u16) u16 {
+            $0:
+                %2 = add %0 %1
+                %3: u1 = lt %0 %1
+                br %3, $1, $2
+            $1:
+                %4 = add %1 %0
+                %5: u1 = gt %1 %0
+                %6: u1 = and %5 %3
+                ret %4
+            $2:
+                %7 = sub %0 %1
+                jmp $3
+            $3: ; preds = $2
+                %8 = sub %0 %1
+                %9 = sub %1 %0
+                %10 = add %8 %9
+                %11 = add %9 %7
+                ret %11
+            }
+            ",
+        )
+        .unwrap();
+        module.algos_run.push(crate::ir::Algo::PhiLowering);
+        ForEachFunction(Gvn).run(&mut module);
+        verify(&module).unwrap();
+        let func = &module.functions[0];
+        // %4 and %5 are %2 and %3 with the operands swapped
+        assert_eq!(func.blocks[1].instructions.len(), 1);
+        assert_eq!(
+            func.blocks[1].instructions[0].operation,
+            Operation::BinOp(BinOp::And, ValueId(3), ValueId(3))
+        );
+        assert_eq!(func.blocks[1].terminator, Terminator::Return(ValueId(2)));
+        // sub isn't commutative, so only %8 is replaced by %7, after which
+        // %11 is the same as %10
+        assert_eq!(func.blocks[3].instructions.len(), 2);
+        assert_eq!(
+            func.blocks[3].instructions[1].operation,
+            Operation::BinOp(BinOp::Add, ValueId(7), ValueId(9))
+        );
+        assert_eq!(func.blocks[3].terminator, Terminator::Return(ValueId(10)));
+    }
+
+    #[test]
+    fn loops() {
+        use crate::algos::analysis::loops::{simplify_loops, LoopInfo};
+        use crate::ir::BlockId;
+
+        let mut module = parse(
+            "
+            $0: public fn main(n: u16) u16 {
+            $0:
+                %1: u16 = 1
+                br %0, $1, $4
+            $1: ; preds = $0, $3
+                %2: u16 = phi [$0, %0], [$3, %5]
+                jmp $2
+            $2: ; preds = $1, $2
+                %3: u16 = phi [$1, %2], [$2, %4]
+                %4 = sub %3 %1
+                br %4, $2, $3
+            $3:
+                %5 = sub %2 %1
+                br %5, $1, $4
+            $4: ; preds = $0, $3
+                %6: u16 = phi [$0, %1], [$3, %5]
+                ret %6
+            }
+            ",
+        )
+        .unwrap();
+        let b = BlockId;
+        let func = &mut module.functions[0];
+        let info = LoopInfo::new(func, &DominatorTree::new(func));
+        assert_eq!(info.loops().len(), 2);
+        let outer = &info.loops()[0];
+        assert_eq!(outer.header(), b(1));
+        assert_eq!(outer.blocks(), &[b(1), b(2), b(3)]);
+        assert_eq!(outer.latches(), &[b(3)]);
+        assert_eq!(outer.exits(), &[b(4)]);
+        assert_eq!(outer.preheader(func), None);
+        let inner = info.get(outer.children()[0]);
+        assert_eq!((inner.header(), inner.depth()), (b(2), 2));
+        assert_eq!(inner.preheader(func), Some(b(1)));
+        assert_eq!(info.depth(b(2)), 2);
+        assert_eq!(info.depth(b(3)), 1);
+        assert_eq!(info.depth(b(4)), 0);
+
+        let info = simplify_loops(func);
+        let outer = &info.loops()[0];
+        assert_eq!(outer.preheader(func), Some(b(5)));
+        assert_eq!(outer.exits(), &[b(6)]);
+        assert_eq!(func.blocks[6].preds, vec![b(3)]);
+        verify(&module).unwrap();
+    }
+
+    #[test]
+    fn licm() {
+        use crate::algos::opt::{licm::Licm, ForEachFunction, OptPass};
+        use crate::ir::ValueId;
+
+        let mut module = parse(
+            "
+            $0: public fn main(n: u16, m: u16) u16 {
+            $0:
+                jmp $1
+            $1: ; preds = $0, $1
+                %2: u16 = phi [$0, %0], [$1, %6]
+                %3: u16 = 3
+                %4 = mul %1 %3
+                %5 = div %1 %0
+                %6 = sub %2 %5
+                %7 = add %6 %4
+                br %6, $1, $2
+            $2:
+                ret %7
+            }
+            ",
+        )
+        .unwrap();
+        module.algos_run.push(crate::ir::Algo::PhiLowering);
+        ForEachFunction(Licm).run(&mut module);
+        verify(&module).unwrap();
+        let yielded = |b: usize| -> Vec<ValueId> {
+            module.functions[0].blocks[b]
+                .instructions
+                .iter()
+                .map(|i| i.yielded.unwrap())
+                .collect()
+        };
+        let v = ValueId;
+        // the division stays in the loop, as %0 may be zero
+        assert_eq!(yielded(0), vec![v(3), v(4)]);
+        assert_eq!(yielded(1), vec![v(2), v(5), v(6)]);
+        assert_eq!(yielded(2), vec![v(7)]);
+
+        // a constant -1 only keeps a signed division in the loop
+        let mut module = parse(
+            "
+            $0: public fn main(n: s16, m: u16) s16 {
+            $0:
+                %2: s16 = -1
+                %3: u16 = 65535
+                jmp $1
+            $1: ; preds = $0, $1
+                %4: s16 = phi [$0, %0], [$1, %7]
+                %5 = div %0 %2
+                %6 = div %1 %3
+                %7 = sub %4 %5
+                br %6, $1, $2
+            $2:
+                ret %7
+            }
+            ",
+        )
+        .unwrap();
+        module.algos_run.push(crate::ir::Algo::PhiLowering);
+        ForEachFunction(Licm).run(&mut module);
+        verify(&module).unwrap();
+        let yielded = |b: usize| -> Vec<ValueId> {
+            module.functions[0].blocks[b]
+                .instructions
+                .iter()
+                .map(|i| i.yielded.unwrap())
+                .collect()
+        };
+        assert_eq!(yielded(0), vec![v(2), v(3), v(6)]);
+        assert_eq!(yielded(1), vec![v(4), v(5), v(7)]);
+    }
+
+    #[test]
+    fn inlining() {
+        use crate::algos::{
+            opt::{inline::Inline, OptPass},
+            pass_manager::{OptLevel, PassManager},
+        };
+        use crate::ir::{Attribute, Operation};
+
+        const SRC: &str = "
+            $0: external fn print(n: u16) void {
+            }
+            $1: private fn abs_diff(a: u16, b: u16) u16 {
+            $0:
+                %2: u1 = lt %0 %1
+                br %2, $1, $2
+            $1:
+                %3 = sub %1 %0
+                ret %3
+            $2:
+                %4 = sub %0 %1
+                ret %4
+            }
+            $2: private fn twice(a: u16) u16 noinline {
+            $0:
+                %1 = add %0 %0
+                ret %1
+            }
+            $3: public fn main(a: u16) u16 {
+            $0:
+                %1 = call $1(%0, %0)
+                %2 = call $2(%1)
+                %3: void = call $0(%2)
+                %4 = call $1(%2, %1)
+                ret %4
+            }
+            ";
+        let mut module = parse(SRC).unwrap();
+        assert_eq!(module.functions[2].attributes, vec![Attribute::NoInline]);
+        assert_eq!(parse(&module.to_string()).unwrap(), module);
+
+        module.algos_run.push(crate::ir::Algo::PhiLowering);
+        Inline::default().run(&mut module);
+        verify(&module).unwrap();
+        let main = &module.functions[3];
+        let callees: Vec<usize> = main
+            .blocks
+            .iter()
+            .flat_map(|b| b.instructions.iter())
+            .filter_map(|i| match i.operation {
+                Operation::Call(f, _) => Some(f.0),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(callees, vec![2, 0]);
+        // both calls of abs_diff add its 3 blocks and a join block, where its
+        // returns meet in a phi
+        assert_eq!(main.blocks.len(), 9);
+        let phis = main
+            .blocks
+            .iter()
+            .flat_map(|b| b.instructions.iter())
+            .filter(|i| matches!(i.operation, Operation::Phi(_)))
+            .count();
+        assert_eq!(phis, 2);
+
+        let mut module = parse(SRC).unwrap();
+        let mut pm = PassManager::with_opt_level(OptLevel::O2);
+        pm.set_verify(true);
+        pm.run(&mut module).unwrap();
+
+        // calls without a value are inlined too, along with those after them
+        // in the block
+        let mut module = parse(
+            "
+            $0: private fn bump(p: u16*) void {
+            $0:
+                %1 = load %0
+                %2: u16 = 1
+                %3 = add %1 %2
+                store %0 %3
+                ret %3
+            }
+            $1: public fn main(a: u16) u16 {
+            $0:
+                %1 = alloca u16
+                store %1 %0
+                call $0(%1)
+                call $0(%1)
+                %2 = load %1
+                ret %2
+            }
+            ",
+        )
+        .unwrap();
+        verify(&module).unwrap();
+        module.algos_run.push(crate::ir::Algo::PhiLowering);
+        Inline::default().run(&mut module);
+        verify(&module).unwrap();
+        let main = &module.functions[1];
+        let instrs = main.blocks.iter().flat_map(|b| b.instructions.iter());
+        assert!(!instrs.clone().any(|i| matches!(i.operation, Operation::Call(..))));
+        assert_eq!(instrs.filter(|i| matches!(i.operation, Operation::Store(..))).count(), 3);
+    }
+
+    #[test]
+    fn instcombine() {
+        use crate::algos::opt::{instcombine::InstCombine, OptPass};
+        use crate::ir::{Algo, Operation, ValueId};
+
+        const SRC: &str = "
+            $0: public fn main(x: u16, y: u16) u16 {
+            $0:
+                %2: u16 = 0
+                %3: u16 = 1
+                %4: u16 = 2
+                %5 = add %0 %2
+                %6 = mul %5 %3
+                %7 = add %3 %6
+                %8 = add %7 %4
+                %9 = sub %2 %0
+                %10 = sub %2 %9
+                %11 = xor %10 %10
+                %12 = add %8 %11
+                %13: u1 = gt %1 %0
+                br %13, $1, $2
+            $1:
+                ret %12
+            $2:
+                %14 = and %1 %1
+                ret %14
+            }
+            ";
+        let v = ValueId;
+
+        let mut module = parse(SRC).unwrap();
+        module.algos_run.push(Algo::PhiLowering);
+        InstCombine.run(&mut module);
+        verify(&module).unwrap();
+        let func = &module.functions[0];
+        let def = |val: ValueId| {
+            func.blocks
+                .iter()
+                .flat_map(|b| b.instructions.iter())
+                .find(|i| i.yielded == Some(val))
+                .map(|i| i.operation.clone())
+        };
+        let Terminator::Return(ret) = func.blocks[1].terminator else {
+            panic!()
+        };
+        let Some(Operation::BinOp(BinOp::Add, x, three)) = def(ret) else {
+            panic!()
+        };
+        assert_eq!((x, def(three)), (v(0), Some(Operation::Integer(3))));
+        assert_eq!(def(v(13)), Some(Operation::BinOp(BinOp::Lt, v(0), v(1))));
+        assert_eq!(func.blocks[2].terminator, Terminator::Return(v(1)));
+
+        // once phis are removed, copies are kept and nothing is reassociated
+        let mut module = parse(SRC).unwrap();
+        module.algos_run.extend([
+            Algo::CriticalEdgeSplitting,
+            Algo::PhiLowering,
+            Algo::PhiRemoval,
+        ]);
+        InstCombine.run(&mut module);
+        verify(&module).unwrap();
+        let instrs = &module.functions[0].blocks[0].instructions;
+        assert_eq!(instrs[3].operation, Operation::Copy(v(0)));
+        assert_eq!(instrs[6].operation, Operation::BinOp(BinOp::Add, v(7), v(4)));
+    }
+
+    #[test]
+    fn strength_reduction() {
+        use crate::algos::{
+            opt::{
+                strength_reduction::{IvStrengthReduction, StrengthReduction},
+                FunctionPass,
+            },
+            pass_manager::{OptLevel, PassManager},
+        };
+        use crate::ir::{Operation, ValueId};
+
+        let mut module = parse(
+            "
+            $0: public fn main(x: u8) u8 {
+            $0:
+                %1: u8 = 8
+                %2: u8 = 7
+                %3 = mul %1 %0
+                %4 = div %0 %1
+                %5 = mod %0 %1
+                %6 = div %0 %2
+                %7 = mod %0 %2
+                %8 = add %3 %4
+                %9 = add %5 %6
+                %10 = add %8 %9
+                %11 = add %10 %7
+                ret %11
+            }
+            ",
+        )
+        .unwrap();
+        let func = &mut module.functions[0];
+        StrengthReduction { mul_high: true }.run_on_function(func);
+        verify(&module).unwrap();
+        let func = &module.functions[0];
+        let def = |val: ValueId| {
+            func.blocks[0]
+                .instructions
+                .iter()
+                .find(|i| i.yielded == Some(val))
+                .map(|i| i.operation.clone())
+                .unwrap()
+        };
+        let shift = |val: ValueId| match def(val) {
+            Operation::BinOp(op, a, b) => (op, a, def(b)),
+            op => panic!("{op:?}"),
+        };
+        let v = ValueId;
+        assert_eq!(shift(v(3)), (BinOp::Shl, v(0), Operation::Integer(3)));
+        assert_eq!(shift(v(4)), (BinOp::Shr, v(0), Operation::Integer(3)));
+        assert_eq!(shift(v(5)), (BinOp::And, v(0), Operation::Integer(7)));
+
+        // the division by 7 is exact for every u8
+        let eval = |x: i64| {
+            let mut vals = vec![x];
+            for instr in func.blocks[0].instructions.iter() {
+                let val = instr.yielded.unwrap();
+                vals.resize(vals.len().max(val.0 + 1), 0);
+                vals[val.0] = match instr.operation {
+                    Operation::Integer(c) => c,
+                    Operation::BinOp(op, a, b) => {
+                        op.operate(&Type::Integer(8, false), vals[a.0], vals[b.0]).unwrap()
+                    }
+                    ref op => panic!("{op:?}"),
+                };
+            }
+            (vals[6], vals[7])
+        };
+        assert!(!func.blocks[0]
+            .instructions
+            .iter()
+            .any(|i| matches!(i.operation, Operation::BinOp(BinOp::Div | BinOp::Mod, ..))));
+        for x in 0..256 {
+            assert_eq!(eval(x), (x / 7, x % 7));
+        }
+
+        // the mask of a mod by 2^63 doesn't fit an i64 before the subtraction
+        let mut module = parse(
+            "
+            $0: public fn main(x: u64) u64 {
+            $0:
+                %1: u64 = -9223372036854775808
+                %2 = mod %0 %1
+                ret %2
+            }
+            ",
+        )
+        .unwrap();
+        StrengthReduction { mul_high: true }.run_on_function(&mut module.functions[0]);
+        verify(&module).unwrap();
+        let instrs = &module.functions[0].blocks[0].instructions;
+        let Operation::BinOp(BinOp::And, _, mask) = instrs.last().unwrap().operation else {
+            panic!("the mod isn't a mask");
+        };
+        let mask = instrs.iter().find(|i| i.yielded == Some(mask)).unwrap();
+        assert_eq!(mask.operation, Operation::Integer(i64::MAX));
+
+        // runs the selected Iris code of a function without branches or
+        // memory accesses on 16-bit registers
+        fn run_iris(module: &crate::ir::Module, args: &[u16]) -> u16 {
+            use crate::arch::iris::{IrisAluOp, IrisInstr};
+            use crate::regalloc::VReg;
+
+            let vcode = module.lower_to_vcode::<_, IrisSelector, LinearScanRegAlloc>();
+            let mut regs = [0u16; 32];
+            regs[1..=args.len()].copy_from_slice(args);
+            let reg = |r: &VReg| match r {
+                VReg::Real(r) => *r,
+                r => panic!("{r} isn't allocated"),
+            };
+            let instrs = vcode.functions[0].instrs.iter().flat_map(|b| b.instrs.iter());
+            for instr in instrs {
+                let (dst, val) = match instr {
+                    IrisInstr::Imm { dst, val } => (dst, *val as u16),
+                    IrisInstr::Mov { dst, src } => (dst, regs[reg(src)]),
+                    IrisInstr::AluOp { op, dst, src1, src2 } => {
+                        let (a, b) = (regs[reg(src1)], regs[reg(src2)]);
+                        let val = match op {
+                            IrisAluOp::Add => a.wrapping_add(b),
+                            IrisAluOp::Sub => a.wrapping_sub(b),
+                            IrisAluOp::Mul => a.wrapping_mul(b),
+                            IrisAluOp::Umlt => ((a as u32 * b as u32) >> 16) as u16,
+                            IrisAluOp::And => a & b,
+                            IrisAluOp::Or => a | b,
+                            IrisAluOp::Xor => a ^ b,
+                            IrisAluOp::Bsl => a.checked_shl(b as u32).unwrap_or(0),
+                            IrisAluOp::Bsr => a.checked_shr(b as u32).unwrap_or(0),
+                            op => panic!("unexpected {op}"),
+                        };
+                        (dst, val)
+                    }
+                    IrisInstr::Ret => break,
+                    instr => panic!("unexpected {instr}"),
+                };
+                regs[reg(dst)] = val;
+                regs[0] = 0;
+            }
+            regs[1]
+        }
+
+        // umlt is the upper half of a 16-bit product, so narrower mulhis
+        // need shifts
+        let mut module = parse(
+            "
+            $0: public fn main(x: u8) u8 {
+            $0:
+                %1: u8 = 7
+                %2 = div %0 %1
+                ret %2
+            }
+            ",
+        )
+        .unwrap();
+        StrengthReduction { mul_high: true }.run_on_function(&mut module.functions[0]);
+        for x in 0..256 {
+            // garbage above the width of the argument must not matter
+            assert_eq!(run_iris(&module, &[x | 0xab00]) & 0xff, x / 7);
+        }
+        let module = parse(
+            "
+            $0: public fn main(x: u12, y: u12) u12 {
+            $0:
+                %2 = mulhi %0 %1
+                ret %2
+            }
+            ",
+        )
+        .unwrap();
+        for (x, y) in [(0xfff, 0xfff), (0x800, 0x3), (0x123, 0xabc), (5, 7)] {
+            let hi = ((x as u32 * y as u32) >> 12) as u16;
+            assert_eq!(run_iris(&module, &[x | 0xf000, y]) & 0xfff, hi);
+        }
+
+        // Iris selects mulhi, so its O2 pipeline uses it for divisions
+        let mut module = parse(
+            "
+            $0: public fn main(x: u16) u16 {
+            $0:
+                %1: u16 = 7
+                %2 = div %0 %1
+                ret %2
+            }
+            ",
+        )
+        .unwrap();
+        PassManager::for_target::<IrisSelector>(OptLevel::O2)
+            .run(&mut module)
+            .unwrap();
+        let ops = || module.functions[0].blocks[0].instructions.iter().map(|i| &i.operation);
+        assert!(ops().any(|op| matches!(op, Operation::BinOp(BinOp::MulHi, ..))));
+        assert!(!ops().any(|op| matches!(op, Operation::BinOp(BinOp::Div, ..))));
+        for x in [0, 6, 7, 1000, 0x7fff, 0xffff] {
+            assert_eq!(run_iris(&module, &[x]), x / 7);
+        }
+
+        // sum of 6 * i for i = 0, 2, 4, ... below n
+        let mut module = parse(
+            "
+            $0: public fn main(n: u16) u16 {
+            $0:
+                %1: u16 = 0
+                jmp $1
+            $1: ; preds = $0, $2
+                %2: u16 = phi [$0, %1], [$2, %6]
+                %3: u16 = phi [$0, %1], [$2, %8]
+                %4: u1 = lt %2 %0
+                br %4, $2, $3
+            $2:
+                %5: u16 = 6
+                %7 = mul %2 %5
+                %8 = add %3 %7
+                %9: u16 = 2
+                %6 = add %2 %9
+                jmp $1
+            $3:
+                ret %3
+            }
+            ",
+        )
+        .unwrap();
+        let func = &mut module.functions[0];
+        IvStrengthReduction.run_on_function(func);
+        verify(&module).unwrap();
+        let func = &module.functions[0];
+        let Operation::Phi(ref vals) = func.blocks[1].instructions[0].operation else {
+            panic!()
+        };
+        let j = func.blocks[1].instructions[0].yielded.unwrap();
+        assert_eq!(vals.len(), 2);
+        assert!(func.values[v(7).0].children.is_empty());
+        let (_, next) = vals.iter().find(|(p, _)| p.0 == 2).unwrap();
+        let Some(Operation::BinOp(BinOp::Add, from, step)) = func.blocks[2]
+            .instructions
+            .iter()
+            .find(|i| i.yielded == Some(*next))
+            .map(|i| i.operation.clone())
+        else {
+            panic!()
+        };
+        assert_eq!(from, j);
+        assert!(func.blocks[2]
+            .instructions
+            .iter()
+            .any(|i| i.yielded == Some(step) && i.operation == Operation::Integer(12)));
+
+        // the scaled step wraps to the width of the type, like the mul would
+        let mut module = parse(
+            "
+            $0: public fn main(n: s8) s8 {
+            $0:
+                %1: s8 = 0
+                jmp $1
+            $1: ; preds = $0, $2
+                %2: s8 = phi [$0, %1], [$2, %5]
+                %3: u1 = lt %2 %0
+                br %3, $2, $3
+            $2:
+                %4: s8 = 100
+                %5 = add %2 %4
+                %6: s8 = 3
+                %7 = mul %2 %6
+                jmp $1
+            $3:
+                ret %2
+            }
+            ",
+        )
+        .unwrap();
+        let func = &mut module.functions[0];
+        IvStrengthReduction.run_on_function(func);
+        verify(&module).unwrap();
+        // 300 wraps to 44
+        assert!(module.functions[0].blocks[2]
+            .instructions
+            .iter()
+            .any(|i| i.operation == Operation::Integer(44)));
+    }
+
+    #[test]
+    fn simplify_cfg() {
+        use crate::algos::simplify_cfg::simplify_cfg;
+        use crate::ir::ValueId;
+
+        let mut module = parse(
+            "
+            $0: public fn main(x: u16) u16 {
+            $0:
+                %1: u16 = 1
+                jmp $1
+            $1:
+                jmp $2
+            $2:
+                %2 = add %0 %1
+                br %1, $3, $4
+            $3:
+                jmp $5
+            $4:
+                jmp $5
+            $5: ; preds = $3, $4
+                %3: u16 = phi [$3, %2], [$4, %0]
+                ret %3
+            }
+            ",
+        )
+        .unwrap();
+        simplify_cfg(&mut module.functions[0]);
+        verify(&module).unwrap();
+        let func = &module.functions[0];
+        assert_eq!(func.blocks.len(), 1);
+        assert_eq!(func.blocks[0].instructions.len(), 2);
+        assert_eq!(func.blocks[0].terminator, Terminator::Return(ValueId(2)));
+
+        // the branch on the Φ is decided in both predecessors
+        let mut module = parse(
+            "
+            $0: public fn main(x: u1) u1 {
+            $0:
+                br %0, $1, $2
+            $1:
+                %1: u1 = 1
+                jmp $3
+            $2:
+                %2: u1 = 0
+                jmp $3
+            $3: ; preds = $1, $2
+                %3: u1 = phi [$1, %1], [$2, %2]
+                br %3, $4, $5
+            $4:
+                ret %0
+            $5:
+                %4: u1 = 0
+                ret %4
+            }
+            ",
+        )
+        .unwrap();
+        simplify_cfg(&mut module.functions[0]);
+        verify(&module).unwrap();
+        let func = &module.functions[0];
+        assert_eq!(func.blocks.len(), 3);
+        assert_eq!(func.blocks[1].terminator, Terminator::Return(ValueId(0)));
+        assert_eq!(func.blocks[2].terminator, Terminator::Return(ValueId(4)));
+    }
+
+    #[test]
+    fn apint() {
+        use crate::algos::opt::{constant_folding::ConstantFolding, OptPass};
+        use crate::ir::{APInt, Algo, Operation};
+
+        let u8 = |v: u128| APInt::new(8, v);
+        assert_eq!(u8(200).add(&u8(100)), u8(44));
+        assert_eq!(u8(3).sub(&u8(5)), u8(254));
+        assert_eq!(u8(16).mul(&u8(17)), u8(16));
+        assert_eq!(u8(16).mul_hi(&u8(17)), Some(u8(1)));
+        assert_eq!(u8(1).shl(&u8(8)), u8(0));
+        assert_eq!(u8(0x80).lshr(&u8(7)), u8(1));
+        assert_eq!(u8(0x80).ashr(&u8(7)), u8(0xff));
+        assert_eq!(u8(0x80).ashr(&u8(200)), u8(0xff));
+        assert_eq!(u8(0xfe).udiv(&u8(2)), Some(u8(0x7f)));
+        assert_eq!(u8(0xfe).sdiv(&u8(2)), Some(u8(0xff)));
+        assert_eq!(u8(0x80).sdiv(&u8(0xff)), Some(u8(0x80)));
+        assert_eq!(u8(1).udiv(&u8(0)), None);
+        assert_eq!(u8(0xfb).srem(&u8(3)), Some(u8(0xfe)));
+        assert_eq!(u8(0xfb).urem(&u8(3)), Some(u8(2)));
+        assert!(u8(1).ult(&u8(0xff)) && !u8(1).slt(&u8(0xff)));
+        assert_eq!(u8(0x80).sext(16), APInt::new(16, 0xff80));
+        assert_eq!(u8(0x80).zext(16), APInt::new(16, 0x80));
+        assert_eq!(APInt::new(16, 0x1234).trunc(8), u8(0x34));
+        assert_eq!(APInt::from_i64(8, -1).to_i64(true), -1);
+        assert_eq!(APInt::from_i64(8, -1).to_i64(false), 255);
+        let big = APInt::new(128, 1 << 127);
+        assert_eq!(big.sdiv(&APInt::from_i64(128, -1)), Some(big));
+        assert_eq!(big.shl(&APInt::new(128, 1)), APInt::zero(128));
+
+        // folding wraps at the width of the type and respects signedness
+        let u16 = Type::Integer(16, false);
+        let s16 = Type::Integer(16, true);
+        assert_eq!(BinOp::Add.operate(&u16, 65535, 1), Some(0));
+        assert_eq!(BinOp::Add.operate(&s16, 32767, 1), Some(-32768));
+        assert_eq!(BinOp::Lt.operate(&u16, -1, 1), Some(0));
+        assert_eq!(BinOp::Lt.operate(&s16, -1, 1), Some(1));
+        assert_eq!(BinOp::Shr.operate(&u16, -2, 1), Some(0x7fff));
+        assert_eq!(BinOp::Shr.operate(&s16, -2, 1), Some(-1));
+        assert_eq!(BinOp::Shl.operate(&u16, 1, 100), Some(0));
+        assert_eq!(BinOp::Div.operate(&u16, -4, 2), Some(0x7ffe));
+        assert_eq!(BinOp::Div.operate(&s16, -4, 2), Some(-2));
+        assert_eq!(BinOp::Div.operate(&s16, 1, 0), None);
+
+        let mut module = parse(
+            "
+            $0: public fn main() u1 {
+            $0:
+                %0: u16 = 65535
+                %1: u16 = 1
+                %2 = add %0 %1
+                %3: s16 = -8
+                %4: s16 = 2
+                %5 = div %3 %4
+                %6: u1 = lt %5 %4
+                %7: u1 = eq %2 %2
+                %8 = and %6 %7
+                ret %8
+            }
+            ",
+        )
+        .unwrap();
+        module.algos_run.push(Algo::PhiLowering);
+        ConstantFolding.run(&mut module);
+        let instrs = &module.functions[0].blocks[0].instructions;
+        assert_eq!(instrs[2].operation, Operation::Integer(0));
+        assert_eq!(instrs[5].operation, Operation::Integer(-4));
+        assert_eq!(instrs[8].operation, Operation::Integer(1));
+    }
+
+    #[test]
+    fn casts() {
+        use crate::algos::opt::{
+            constant_folding::ConstantFolding, instcombine::InstCombine, OptPass,
+        };
+        use crate::ir::{Algo, Operation, ValueId};
+
+        const SRC: &str = "
+            $0: public fn main(x: u8, y: u32) u16 {
+            $0:
+                %2: u16 = zext %0
+                %3: u32 = zext %2
+                %4: u16 = trunc %3
+                %5: s16 = sext %0
+                %6: s8 = -1
+                %7: s16 = sext %6
+                %8: u16 = 300
+                %9: u8 = trunc %8
+                %10: u16 = trunc %1
+                %11: u1 = lt %2 %10
+                br %11, $1, $2
+            $1:
+                ret %4
+            $2:
+                %12: u16* = inttoptr %10
+                %13: u16 = ptrtoint %12
+                ret %13
+            }
+            ";
+        let v = ValueId;
+        let mut module = parse(SRC).unwrap();
+        verify(&module).unwrap();
+        assert_eq!(parse(&module.to_string()).unwrap().to_string(), module.to_string());
+
+        module.algos_run.push(Algo::PhiLowering);
+        ConstantFolding.run(&mut module);
+        let instrs = &module.functions[0].blocks[0].instructions;
+        assert_eq!(instrs[5].operation, Operation::Integer(-1));
+        assert_eq!(instrs[7].operation, Operation::Integer(44));
+
+        InstCombine.run(&mut module);
+        verify(&module).unwrap();
+        let func = &module.functions[0];
+        assert_eq!(func.blocks[1].terminator, Terminator::Return(v(2)));
+        assert_eq!(func.blocks[2].terminator, Terminator::Return(v(10)));
+
+        let vcode = module.lower_to_vcode::<_, IrisSelector, LinearScanRegAlloc>();
+        let asm = vcode.to_string();
+        assert!(asm.contains("and") && asm.contains("xor"));
+
+        let module = parse(
+            "
+            $0: public fn main(x: u16) u8 {
+            $0:
+                %1: u8 = zext %0
+                ret %1
+            }
+            ",
+        )
+        .unwrap();
+        let kinds: Vec<_> = verify(&module).unwrap_err().into_iter().map(|e| e.kind).collect();
+        assert!(matches!(kinds[..], [VerifyErrorKind::InvalidCast { .. }]));
+    }
+
+    #[test]
+    fn unops() {
+        use crate::algos::opt::{
+            constant_folding::ConstantFolding, instcombine::InstCombine, OptPass,
+        };
+        use crate::ir::{Algo, Operation, UnOp, ValueId};
+
+        const SRC: &str = "
+            $0: public fn main(x: u16, y: u8) u16 {
+            $0:
+                %2: s8 = 5
+                %3: s8 = neg %2
+                %4: u8 = 0
+                %5: u8 = not %4
+                %6: u1 = lnot %4
+                %7: u16 = 0
+                %8: u16 = sub %7 %0
+                %9: u16 = neg %8
+                %10: u16 = 65535
+                %11: u16 = xor %9 %10
+                %12: u16 = not %11
+                %13: u1 = lnot %1
+                br %13, $1, $2
+            $1:
+                ret %12
+            $2:
+                %14: u16 = neg %0
+                ret %14
+            }
+            ";
+        let v = ValueId;
+        let mut module = parse(SRC).unwrap();
+        verify(&module).unwrap();
+        assert_eq!(parse(&module.to_string()).unwrap().to_string(), module.to_string());
+
+        module.algos_run.push(Algo::PhiLowering);
+        ConstantFolding.run(&mut module);
+        let instrs = &module.functions[0].blocks[0].instructions;
+        assert_eq!(instrs[1].operation, Operation::Integer(-5));
+        assert_eq!(instrs[3].operation, Operation::Integer(255));
+        assert_eq!(instrs[4].operation, Operation::Integer(1));
+
+        InstCombine.run(&mut module);
+        verify(&module).unwrap();
+        let func = &module.functions[0];
+        assert_eq!(func.blocks[1].terminator, Terminator::Return(v(0)));
+        let instrs = &func.blocks[2].instructions;
+        assert_eq!(instrs[0].operation, Operation::UnOp(UnOp::Neg, v(0)));
+
+        let vcode = module.lower_to_vcode::<_, IrisSelector, LinearScanRegAlloc>();
+        let asm = vcode.to_string();
+        assert!(asm.contains("neg") && asm.contains("sete"));
+    }
+
+    #[test]
+    fn signedness() {
+        use crate::algos::opt::{constant_folding::ConstantFolding, OptPass};
+        use crate::arch::urcl::UrclSelector;
+        use crate::ir::{Algo, Operation};
+
+        const SRC: &str = "
+            $0: public fn main(x: s16, y: u16, z: s8) u16 {
+            $0:
+                %3: s16 = -4
+                %4: s16 = 3
+                %5: s16 = div %3 %4
+                %6: s16 = mod %3 %4
+                %7: s16 = shr %3 %4
+                %8: u1 = lt %3 %4
+                %9: u16 = 65532
+                %10: u16 = 3
+                %11: u16 = div %9 %10
+                %12: u16 = shr %9 %10
+                %13: u1 = lt %9 %10
+                %14: u1 = lt %0 %0
+                %15: u1 = lt %1 %1
+                %16: s16 = mod %0 %0
+                %17: s16 = shr %0 %0
+                %18: u16 = shr %1 %1
+                %19: u1 = lt %2 %2
+                ret %1
+            }
+            ";
+        let mut module = parse(SRC).unwrap();
+        verify(&module).unwrap();
+        module.algos_run.push(Algo::PhiLowering);
+        ConstantFolding.run(&mut module);
+        let instrs = &module.functions[0].blocks[0].instructions;
+        let folded: Vec<_> = [2, 3, 4, 5, 8, 9, 10].map(|i| instrs[i].operation.clone()).into();
+        let expected = [-1, -1, -1, 1, 21844, 8191, 0].map(Operation::Integer);
+        assert_eq!(folded, expected);
+
+        for asm in [
+            module.lower_to_vcode::<_, IrisSelector, LinearScanRegAlloc>().to_string(),
+            module.lower_to_vcode::<_, UrclSelector, LinearScanRegAlloc>().to_string(),
+        ] {
+            let lines: Vec<_> = asm.lines().filter_map(|l| l.split_whitespace().next()).collect();
+            for op in ["ssetl", "setl", "sdiv", "bss", "bsr"] {
+                assert!(lines.contains(&op), "no {op} in {asm}");
+            }
+            // the s8 operands of the last comparison are sign extended
+            assert!(lines.contains(&"xor"));
+        }
+    }
+
+    #[test]
+    fn stack_memory() {
+        use crate::algos::pass_manager::{OptLevel, PassManager};
+        use crate::arch::urcl::UrclSelector;
+        use crate::ir::{Operation, ValueId};
+
+        const SRC: &str = "
+            $0: public fn main(x: u16) u16 {
+            $0:
+                %1: u16* = alloca u16
+                %2 = alloca u32
+                %3 = call $1(%1, %0)
+                %4 = load %1
+                %5: u32 = zext %4
+                store %2 %5
+                ret %4
+            }
+            $1: private fn set(p: u16*, x: u16) u16 {
+            $0:
+                store %0 %1
+                %2: u16 = 0
+                ret %2
+            }
+            ";
+        let v = ValueId;
+        let mut module = parse(SRC).unwrap();
+        verify(&module).unwrap();
+        assert_eq!(parse(&module.to_string()).unwrap().to_string(), module.to_string());
+        assert_eq!(module.functions[0].values[4].ty, Type::Integer(16, false));
+
+        // stores stay even though they yield nothing
+        let mut pm = PassManager::with_opt_level(OptLevel::O1);
+        pm.set_verify(true);
+        pm.run(&mut module).unwrap();
+        let instrs = &module.functions[1].blocks[0].instructions;
+        assert_eq!(instrs[0].operation, Operation::Store(v(0), v(1)));
+
+        let asm = module.lower_to_vcode::<_, IrisSelector, LinearScanRegAlloc>().to_string();
+        assert!(asm.contains("sub r26 r26") && asm.contains("add r26 r26"));
+        assert!(asm.contains("lod") && asm.contains("str"));
+
+        let u16 = Type::Integer(16, false);
+        let mut builder = ModuleBuilder::new("stack");
+        let args = vec![("x".to_string(), u16.clone())];
+        let (main, args) = builder.push_function("main", u16.clone(), args, None);
+        builder.switch_to_fn(main);
+        let block = builder.push_block();
+        builder.switch_to_block(block);
+        builder.build_alloca(Type::Integer(32, false));
+        let ptr = builder.build_alloca(u16.clone());
+        builder.build_store_ptr(ptr, args[0]);
+        let val = builder.build_load_ptr(ptr);
+        builder.set_terminator(Terminator::Return(val));
+        let module = builder.build();
+        verify(&module).unwrap();
+        let asm = module.lower_to_vcode::<_, UrclSelector, LinearScanRegAlloc>().to_string();
+        assert!(asm.contains("sub sp sp 3") && asm.contains("add sp sp 3"));
+        assert!(asm.contains("sp 2\n"));
+
+        let module = parse(
+            "
+            $0: public fn main(x: u16) u16 {
+            $0:
+                %1: u8* = alloca u8
+                store %1 %0
+                %2: u16 = load %0
+                ret %2
+            }
+            ",
+        )
+        .unwrap();
+        let kinds: Vec<_> = verify(&module).unwrap_err().into_iter().map(|e| e.kind).collect();
+        assert!(matches!(
+            kinds[..],
+            [VerifyErrorKind::TypeMismatch { .. }, VerifyErrorKind::InvalidOperandType(_)]
+        ));
+    }
+
+    #[test]
+    fn mem2reg() {
+        use crate::algos::{
+            opt::{mem2reg::Mem2Reg, ForEachFunction, OptPass},
+            pass_manager::{OptLevel, PassManager},
+        };
+        use crate::ir::{Algo, Operation};
+
+        // sum = 0; for (i = 0; i < n; i++) sum += i; with `i` also passed
+        // to `$1` by address on the way out
+        const SRC: &str = "
+            $0: public fn main(n: u16) u16 {
+            $0:
+                %1: u16* = alloca u16
+                %2: u16* = alloca u16
+                %3: u16* = alloca u16
+                %4: u16 = 0
+                store %1 %4
+                store %2 %4
+                jmp $1
+            $1:
+                %5 = load %2
+                %6 = lt %5 %0
+                br %6, $2, $3
+            $2:
+                %7 = load %1
+                %8 = add %7 %5
+                store %1 %8
+                %9: u16 = 1
+                %10 = add %5 %9
+                store %2 %10
+                jmp $1
+            $3:
+                store %3 %5
+                %11 = call $1(%3)
+                %12 = load %1
+                ret %12
+            }
+            $1: private fn f(p: u16*) u16 {
+            $0:
+                %1 = load %0
+                ret %1
+            }
+            ";
+        let mut module = parse(SRC).unwrap();
+        verify(&module).unwrap();
+        module.algos_run.push(Algo::PhiLowering);
+        ForEachFunction(Mem2Reg).run(&mut module);
+        verify(&module).unwrap();
+
+        let func = &module.functions[0];
+        assert!(func.variables.is_empty());
+        let ops: Vec<_> = func.blocks.iter().flat_map(|b| b.instructions.iter()).collect();
+        // only the escaping slot %3 is left in memory
+        let memory: Vec<_> = ops
+            .iter()
+            .filter(|i| matches!(i.operation, Operation::Alloca(_) | Operation::Load(_)))
+            .collect();
+        assert_eq!(memory.len(), 1);
+        let phis = func.blocks[1].instructions.iter();
+        assert_eq!(phis.filter(|i| matches!(i.operation, Operation::Phi(_))).count(), 2);
+
+        let mut module = parse(SRC).unwrap();
+        let mut pm = PassManager::with_opt_level(OptLevel::O2);
+        pm.set_verify(true);
+        pm.run(&mut module).unwrap();
+        module.lower_to_vcode::<_, IrisSelector, LinearScanRegAlloc>();
+    }
+
+    #[test]
+    fn element_ptr() {
+        use crate::algos::opt::{instcombine::InstCombine, OptPass};
+        use crate::arch::urcl::UrclSelector;
+        use crate::ir::{Algo, Operation, ValueId};
+
+        const SRC: &str = "
+            $0: public fn main(a: u16*, i: u16) u16 {
+            $0:
+                %2: u16 = 0
+                %3 = elementptr u16 %0 %2
+                %4: u16 = 1
+                %5 = elementptr u16 %3 %4
+                %6: u16 = 2
+                %7 = elementptr u16 %5 %6
+                %8 = load %7
+                %9 = elementptr u32 %0 %1
+                %10 = elementptr u48 %0 %1
+                %11: u16* = inttoptr %8
+                %12 = elementptr u32 %11 %4
+                %13: u32 = zext %8
+                %14: u48 = zext %8
+                store %12 %13
+                store %9 %13
+                store %10 %14
+                ret %8
+            }
+            ";
+        let v = ValueId;
+        let mut module = parse(SRC).unwrap();
+        verify(&module).unwrap();
+        assert_eq!(parse(&module.to_string()).unwrap().to_string(), module.to_string());
+        let func = &module.functions[0];
+        assert_eq!(func.values[12].ty, Type::Pointer(Box::new(Type::Integer(32, false))));
+
+        // %3 is %0, and %7 the element 3 of %0
+        module.algos_run.push(Algo::PhiLowering);
+        InstCombine.run(&mut module);
+        verify(&module).unwrap();
+        let instrs = &module.functions[0].blocks[0].instructions;
+        let def = |val| &instrs.iter().find(|i| i.yielded == Some(val)).unwrap().operation;
+        let Operation::Load(ptr) = def(v(8)) else {
+            panic!("%8 isn't a load");
+        };
+        let Operation::ElementPtr(base, index, _) = def(*ptr) else {
+            panic!("%8 isn't loaded from an element address");
+        };
+        assert_eq!(*base, v(0));
+        assert_eq!(*def(*index), Operation::Integer(3));
+
+        let module = parse(SRC).unwrap();
+        for asm in [
+            module.lower_to_vcode::<_, IrisSelector, LinearScanRegAlloc>().to_string(),
+            module.lower_to_vcode::<_, UrclSelector, LinearScanRegAlloc>().to_string(),
+        ] {
+            let lines: Vec<_> = asm.lines().filter_map(|l| l.split_whitespace().next()).collect();
+            // u32s are 2 words apart and u48s 3
+            assert!(lines.contains(&"bsl") && lines.contains(&"mul"));
+        }
+
+        let module = parse(
+            "
+            $0: public fn main(a: u16*, i: u16) u16 {
+            $0:
+                %2: u16* = elementptr u16 %1 %0
+                ret %1
+            }
+            ",
+        )
+        .unwrap();
+        let kinds: Vec<_> = verify(&module).unwrap_err().into_iter().map(|e| e.kind).collect();
+        assert!(matches!(
+            kinds[..],
+            [VerifyErrorKind::InvalidOperandType(_), VerifyErrorKind::InvalidOperandType(_)]
+        ));
+    }
+
+    #[test]
+    fn aggregates() {
+        use crate::algos::opt::{instcombine::InstCombine, OptPass};
+        use crate::arch::urcl::UrclSelector;
+        use crate::ir::{Algo, DataLayout, Operation, ValueId};
+
+        const SRC: &str = "
+            $0: public fn main(a: {u16, u32, [u16; 3]}*, s: {u16, u32}) u16 {
+            $0:
+                %2 = fieldptr %0 2
+                %3 = fieldptr %2 1
+                %4 = load %3
+                %5: u16 = 7
+                %6 = insertvalue %1 %5 0
+                %7 = extractvalue %6 0
+                %8 = extractvalue %6 1
+                %9 = add %4 %7
+                ret %9
+            }
+            ";
+        let v = ValueId;
+        let mut module = parse(SRC).unwrap();
+        verify(&module).unwrap();
+        assert_eq!(parse(&module.to_string()).unwrap().to_string(), module.to_string());
+        let func = &module.functions[0];
+        let u16_ty = Type::Integer(16, false);
+        let u32_ty = Type::Integer(32, false);
+        let array = Type::Array(Box::new(u16_ty.clone()), 3);
+        let st = Type::Struct(vec![u16_ty.clone(), u32_ty.clone(), array.clone()]);
+        assert_eq!(func.values[0].ty, Type::Pointer(Box::new(st.clone())));
+        assert_eq!(func.values[2].ty, Type::Pointer(Box::new(array.clone())));
+        assert_eq!(func.values[4].ty, u16_ty);
+        assert_eq!(func.values[6].ty, func.values[1].ty);
+        assert_eq!(func.values[8].ty, u32_ty);
+        assert_eq!(Type::Struct(vec![]).to_string(), "{}");
+
+        // the u32 is aligned to 2 words unless scalars are at most word-aligned
+        let aligned = DataLayout::new(16, 2);
+        assert_eq!(aligned.member_offset(&st, 1), Some(2));
+        assert_eq!(aligned.member_offset(&st, 2), Some(4));
+        assert_eq!(aligned.size_of(&st), 8);
+        assert_eq!(aligned.member_offset(&array, 2), Some(2));
+        assert_eq!(aligned.member_offset(&array, 3), None);
+        let packed = DataLayout::new(16, 1);
+        assert_eq!(packed.member_offset(&st, 2), Some(3));
+        assert_eq!(packed.size_of(&st), 6);
+
+        // members are read back from the insertvalue that wrote them
+        module.algos_run.push(Algo::PhiLowering);
+        InstCombine.run(&mut module);
+        verify(&module).unwrap();
+        let instrs = &module.functions[0].blocks[0].instructions;
+        let def = |val| &instrs.iter().find(|i| i.yielded == Some(val)).unwrap().operation;
+        assert_eq!(*def(v(8)), Operation::ExtractValue(v(1), 1));
+        let Operation::BinOp(_, _, rhs) = def(v(9)) else {
+            panic!("%9 isn't an add");
+        };
+        assert_eq!(*def(*rhs), Operation::Integer(7));
+
+        let module = parse(
+            "
+            $0: public fn main(a: {u16, u32, [u16; 3]}*) u16 {
+            $0:
+                %1 = fieldptr %0 2
+                %2 = fieldptr %1 1
+                %3 = load %2
+                %4 = fieldptr %0 0
+                %5 = load %4
+                %6 = add %3 %5
+                ret %6
+            }
+            ",
+        )
+        .unwrap();
+        verify(&module).unwrap();
+        for asm in [
+            module.lower_to_vcode::<_, IrisSelector, LinearScanRegAlloc>().to_string(),
+            module.lower_to_vcode::<_, UrclSelector, LinearScanRegAlloc>().to_string(),
+        ] {
+            let lines: Vec<_> = asm.lines().filter_map(|l| l.split_whitespace().next()).collect();
+            assert_eq!(lines.iter().filter(|l| **l == "lod").count(), 2);
+        }
+
+        let module = parse(
+            "
+            $0: public fn main(a: {u16, u32}*, s: [u16; 2]) u16 {
+            $0:
+                %2: u16* = fieldptr %0 2
+                %3: u16 = extractvalue %1 2
+                ret %3
+            }
+            ",
+        )
+        .unwrap();
+        let kinds: Vec<_> = verify(&module).unwrap_err().into_iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                VerifyErrorKind::InvalidMember { value: v(0), index: 2 },
+                VerifyErrorKind::InvalidMember { value: v(1), index: 2 },
+            ]
+        );
+    }
+
+    #[test]
+    fn lower_aggregates() {
+        use crate::algos::pass_manager::{OptLevel, PassManager};
+        use crate::ir::{Algo, Operation};
+
+        const SRC: &str = "
+            $0: private fn swap(p: {u16, u16}) {u16, u16} {
+            $0:
+                %1 = extractvalue %0 0
+                %2 = extractvalue %0 1
+                %3 = insertvalue %0 %2 0
+                %4 = insertvalue %3 %1 1
+                ret %4
+            }
+            $1: public fn main(a: {u16, u16}*, b: {u16, u16}*, n: u16) u16 {
+            $0:
+                %3 = load %0
+                %4 = load %1
+                jmp $1
+            $1: ; preds = $0, $2
+                %5 = phi [$0, %3], [$2, %8]
+                %6 = phi [$0, %4], [$2, %5]
+                %7 = phi [$0, %2], [$2, %10]
+                br %7, $2, $3
+            $2:
+                %8 = call $0(%6)
+                %9: u16 = 1
+                %10 = sub %7 %9
+                jmp $1
+            $3:
+                store %0 %6
+                %11 = extractvalue %5 0
+                ret %11
+            }
+            ";
+        let mut module = parse(SRC).unwrap();
+        module.apply_mandatory_transforms_verified().unwrap();
+        let pair = Type::Struct(vec![Type::Integer(16, false); 2]);
+        let pair_ptr = Type::Pointer(Box::new(pair));
+        let swap = &module.functions[0];
+        assert_eq!(swap.ret_type, pair_ptr);
+        assert_eq!(swap.args.iter().map(|a| &a.1).collect::<Vec<_>>(), [&pair_ptr; 2]);
+        for func in module.functions.iter() {
+            for instr in func.blocks.iter().flat_map(|b| b.instructions.iter()) {
+                assert!(!matches!(
+                    instr.operation,
+                    Operation::ExtractValue(..) | Operation::InsertValue(..)
+                ));
+                if let Operation::Call(_, ref args) = instr.operation {
+                    // with the memory for the returned pair
+                    assert_eq!(args.len(), 2);
+                }
+                if let Some(val) = instr.yielded {
+                    assert!(!func.values[val.0].ty.is_aggregate());
+                }
+            }
+        }
+        module.lower_to_vcode::<_, IrisSelector, LinearScanRegAlloc>();
+
+        // the pipelines lower aggregates before removing phis
+        let mut module = parse(SRC).unwrap();
+        PassManager::with_opt_level(OptLevel::O1)
+            .set_verify(true)
+            .run(&mut module)
+            .unwrap();
+        assert!(module.algos_run.contains(&Algo::AggregateLowering));
+
+        let module = parse(
+            "
+            /* [@edges_splitted, @phis_lowered, @aggregates_lowered] module m */
+            $0: public fn main(a: {u16, u16}*) u16 {
+            $0:
+                %1 = load %0
+                %2 = extractvalue %1 1
+                ret %2
+            }
+            ",
+        )
+        .unwrap();
+        let kinds: Vec<_> = verify(&module).unwrap_err().into_iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, vec![VerifyErrorKind::UnexpectedOperation(Algo::AggregateLowering); 2]);
+    }
+
+    #[test]
+    fn globals() {
+        use crate::algos::opt::{instcombine::InstCombine, OptPass};
+        use crate::arch::urcl::{UrclSelector, URCL_DATA_LAYOUT};
+        use crate::ir::{Algo, Constant, DataLayout, GlobalId, Operation, ValueId};
+
+        const SRC: &str = "
+            @0: public global counter: u16 = 5
+            @1: private const table: [u16; 3] = [1, 2, 4]
+            @2: private const limit: u16 = 10
+            @3: external global ext: u16
+            @4: public const pair: {u16, u32} = {1, 65537}
+            $0: public fn main(i: u16) u16 {
+            $0:
+                %1 = globaladdr @0
+                %2 = load %1
+                %3 = globaladdr @2
+                %4 = load %3
+                %5 = add %2 %4
+                store %1 %5
+                %6 = globaladdr @1
+                %7 = elementptr u16 %6 %0
+                %8 = load %7
+                %9 = add %5 %8
+                ret %9
+            }
+            ";
+        let v = ValueId;
+        let mut module = parse(SRC).unwrap();
+        verify(&module).unwrap();
+        assert_eq!(parse(&module.to_string()).unwrap().to_string(), module.to_string());
+        let array = Type::Array(Box::new(Type::Integer(16, false)), 3);
+        assert_eq!(module.functions[0].values[6].ty, Type::Pointer(Box::new(array)));
+
+        // only the immutable limit is known
+        module.algos_run.push(Algo::PhiLowering);
+        InstCombine.run(&mut module);
+        verify(&module).unwrap();
+        let instrs = &module.functions[0].blocks[0].instructions;
+        let def = |val| &instrs.iter().find(|i| i.yielded == Some(val)).unwrap().operation;
+        assert_eq!(*def(v(2)), Operation::Load(v(1)));
+        assert_eq!(*def(v(4)), Operation::Integer(10));
+
+        let pair = &module.globals[4];
+        assert_eq!(URCL_DATA_LAYOUT.words(&pair.ty, pair.init.as_ref()), [1, 1, 1]);
+        let aligned = DataLayout::new(16, 2);
+        assert_eq!(aligned.words(&pair.ty, pair.init.as_ref()), [1, 0, 1, 1]);
+        assert_eq!(aligned.words(&pair.ty, None), [0; 4]);
+        // the bits above the width of a signed integer copy its sign
+        let minus_two = Some(&Constant::Integer(-2));
+        assert_eq!(URCL_DATA_LAYOUT.words(&Type::Integer(24, true), minus_two), [0xfffe, 0xffff]);
+
+        let module = parse(SRC).unwrap();
+        let mut iris = Vec::new();
+        module
+            .lower_to_vcode::<_, IrisSelector, LinearScanRegAlloc>()
+            .emit_assembly(&mut iris)
+            .unwrap();
+        let mut urcl = Vec::new();
+        module
+            .lower_to_vcode::<_, UrclSelector, LinearScanRegAlloc>()
+            .emit_assembly(&mut urcl)
+            .unwrap();
+        for (asm, table) in [(iris, "dw 1 2 4"), (urcl, "dw [1 2 4]")] {
+            let asm = String::from_utf8(asm).unwrap();
+            let lines: Vec<_> = asm.lines().collect();
+            assert!(lines.contains(&".counter") && lines.contains(&".pair"));
+            assert!(lines.contains(&table));
+            assert!(lines.iter().any(|l| l.starts_with("imm ") && l.ends_with(" .counter")));
+            assert!(!lines.contains(&".ext"));
+        }
+
+        let mut module = parse(
+            "
+            @0: public global counter: u16
+            @1: public global other: u16
+            $0: public fn main() u16 {
+            $0:
+                %0: u32* = globaladdr @0
+                %1: u16* = globaladdr @1
+                ret %1
+            }
+            ",
+        )
+        .unwrap();
+        // the parser rejects undefined globals
+        module.globals.truncate(1);
+        let kinds: Vec<_> = verify(&module).unwrap_err().into_iter().map(|e| e.kind).collect();
+        assert!(matches!(
+            kinds[..],
+            [
+                VerifyErrorKind::TypeMismatch { value: ValueId(0), .. },
+                VerifyErrorKind::InvalidGlobal(GlobalId(1)),
+                ..
+            ]
+        ));
+
+        assert!(parse("@0: public const table: [u16; 3] = [1, 2]").is_err());
+        assert!(parse("@0: external global ext: u16 = 1").is_err());
+        assert!(parse("@1: public global counter: u16").is_err());
+        let err = parse("$0: public fn main() u16* {\n$0:\n    %0: u16* = globaladdr @0\n}")
+            .unwrap_err();
+        assert_eq!((err.line, err.column), (3, 27));
+    }
 }
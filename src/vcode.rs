@@ -1,12 +1,20 @@
 use std::{collections::HashMap, fmt::Display};
 
 use crate::{
-    ir::{Instruction, Linkage, Terminator},
+    ir::{
+        DataLayout, Function, FunctionId, Global, GlobalId, Instruction, Linkage, Operation,
+        Terminator, ValueId,
+    },
     regalloc::{Regalloc, VReg},
 };
 
 pub trait InstrSelector {
     type Instr: VCodeInstr;
+    /// Whether `BinOp::MulHi` can be selected, so that passes may introduce it.
+    const SUPPORTS_MUL_HIGH: bool = false;
+    /// Called before the instructions of `func` are selected, so the selector
+    /// can look at the types of its values.
+    fn switch_to_function(&mut self, _func: &Function) {}
     fn select(&mut self, gen: &mut VCodeGenerator<Self::Instr>, instr: &Instruction);
     fn select_terminator(&mut self, gen: &mut VCodeGenerator<Self::Instr>, term: &Terminator);
     fn get_pre_function_instructions(&mut self, gen: &mut VCodeGenerator<Self::Instr>);
@@ -19,7 +27,6 @@ pub trait VCodeInstr where Self: Sized {
     fn apply_allocs(&mut self, allocs: &HashMap<VReg, VReg>);
 
     fn apply_mandatory_transforms(vcode: &mut VCode<Self>);
-    #[must_use]
     fn emit_assembly<T: std::io::Write>(w: &mut T, vcode: &VCode<Self>) -> std::io::Result<()>;
 }
 
@@ -40,10 +47,38 @@ pub enum LabelDest {
     Function(crate::ir::FunctionId),
     // usize: index of the block in the function
     Block(crate::ir::BlockId),
+    /// the address of a global of the module
+    Global(GlobalId),
+}
+
+/// The memory a function reserves on the stack for its `alloca`s, which is
+/// allocated on entry and freed on return.
+#[derive(Default)]
+pub struct StackFrame {
+    /// the offset of the memory of each `alloca` from the bottom of the frame
+    pub offsets: HashMap<ValueId, usize>,
+    /// in words
+    pub size: usize,
+}
+
+impl StackFrame {
+    pub fn new(func: &Function, layout: &DataLayout) -> StackFrame {
+        let mut frame = StackFrame::default();
+        for instr in func.blocks.iter().flat_map(|b| b.instructions.iter()) {
+            if let (Operation::Alloca(ty), Some(val)) = (&instr.operation, instr.yielded) {
+                let offset = frame.size.next_multiple_of(layout.align_of(ty));
+                frame.offsets.insert(val, offset);
+                frame.size = offset + layout.size_of(ty);
+            }
+        }
+        frame
+    }
 }
 
 pub struct VCode<I: VCodeInstr> {
     pub functions: Vec<VCodeFunction<I>>,
+    /// the globals of the module, laid out in memory by the backend
+    pub globals: Vec<Global>,
 }
 
 pub struct VCodeGenerator<I: VCodeInstr> {
@@ -64,7 +99,10 @@ impl<I: VCodeInstr> Default for VCodeGenerator<I> {
 impl<I: VCodeInstr> VCodeGenerator<I> {
     pub fn new() -> VCodeGenerator<I> {
         VCodeGenerator {
-            vcode: VCode { functions: vec![] },
+            vcode: VCode {
+                functions: vec![],
+                globals: vec![],
+            },
             current_func: None,
             current_block: None,
             vreg_count: 0,
@@ -72,6 +110,11 @@ impl<I: VCodeInstr> VCodeGenerator<I> {
             args: vec![],
         }
     }
+    /// Makes `push_vreg` skip the first `count` virtual registers, which are
+    /// those of the values of the current function.
+    pub fn reserve_vregs(&mut self, count: usize) {
+        self.vreg_count = self.vreg_count.max(count);
+    }
     pub fn push_vreg(&mut self) -> VReg {
         let vreg = VReg::Virtual(self.vreg_count);
         self.vreg_count += 1;
@@ -123,10 +166,40 @@ impl<I: VCodeInstr> VCode<I> {
         I::apply_mandatory_transforms(self)
     }
 
-    #[must_use]
     pub fn emit_assembly<W: std::io::Write>(&self, w: &mut W) -> std::io::Result<()> {
         I::emit_assembly(w, self)
     }
+
+    /// Returns the assembly label of `l` as used from `f`.
+    pub fn mangle(&self, f: &VCodeFunction<I>, l: &LabelDest) -> String {
+        match l {
+            LabelDest::Block(li) => {
+                mangle_string(&format!(".__fn_{}{}_L{}", f.name, f.arg_count, li.0))
+            }
+            LabelDest::Function(FunctionId(fi)) => {
+                symbol(&self.functions[*fi].name, self.functions[*fi].linkage)
+            }
+            LabelDest::Global(GlobalId(gi)) => {
+                symbol(&self.globals[*gi].name, self.globals[*gi].linkage)
+            }
+        }
+    }
+}
+
+/// Returns the assembly label of a function or global. Private ones get a
+/// name mangled with a hash, so they can't clash with those of other modules.
+pub fn symbol(name: &str, linkage: Linkage) -> String {
+    match linkage {
+        Linkage::Private => format!(".{}", mangle_string(name)),
+        _ => format!(".{}", name),
+    }
+}
+
+fn mangle_string(s: &str) -> String {
+    use std::hash::*;
+    let mut h = DefaultHasher::new();
+    s.hash(&mut h);
+    format!("{s}_{:16x}", h.finish())
 }
 
 impl<I: Display + VCodeInstr> Display for VCode<I> {
@@ -158,6 +231,7 @@ impl Display for LabelDest {
         match self {
             LabelDest::Function(id) => write!(f, "F{}", id.0),
             LabelDest::Block(id) => write!(f, ".L{}", id.0),
+            LabelDest::Global(id) => write!(f, "G{}", id.0),
         }
     }
 }